@@ -0,0 +1,66 @@
+// Compares the naive triple loop against the blocked and transposed-B kernels.
+// Run with `cargo bench --bench gemm`, add `--features parallel` for threads.
+use neuralnetwork::gemm;
+use neuralnetwork::matrix::{Matrix, MatrixOps};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+fn bench<F: FnMut()>(name: &str, mut f: F) -> Duration {
+    // warm up, then time enough iterations to run for roughly half a second
+    f();
+    let start = Instant::now();
+    let mut iterations = 0u32;
+    while start.elapsed() < Duration::from_millis(500) {
+        f();
+        iterations += 1;
+    }
+    let per_iter = start.elapsed() / iterations;
+    println!("{:<44} {:>12?} / iter", name, per_iter);
+    per_iter
+}
+
+fn main() {
+    println!(
+        "parallel feature: {}\n",
+        if cfg!(feature = "parallel") {
+            "on"
+        } else {
+            "off"
+        }
+    );
+    let shapes = [
+        (100, 784, 1),
+        (10, 100, 1),
+        (100, 784, 64),
+        (100, 64, 784),
+        (256, 256, 256),
+        (512, 512, 512),
+    ];
+    for &(m, k, n) in shapes.iter() {
+        let a: Matrix<f64> = Matrix::new_by_rand(m, k);
+        let b: Matrix<f64> = Matrix::new_by_rand(k, n);
+        let b_t = b.transpose();
+        let (a32, b32) = (a.convert::<f32>(), b.convert::<f32>());
+        let label = format!("{}x{} * {}x{}", m, k, k, n);
+
+        let naive = bench(&format!("naive      {}", label), || {
+            black_box(gemm::naive(black_box(&a), black_box(&b)));
+        });
+        let blocked = bench(&format!("blocked    {}", label), || {
+            black_box(gemm::blocked(black_box(&a), black_box(&b)));
+        });
+        let transposed = bench(&format!("transposed {}", label), || {
+            black_box(gemm::product_transposed(black_box(&a), black_box(&b_t)));
+        });
+        let blocked32 = bench(&format!("blocked    {} f32", label), || {
+            black_box(gemm::blocked(black_box(&a32), black_box(&b32)));
+        });
+        println!(
+            "{:<44} blocked {:.2}x, transposed {:.2}x, blocked f32 {:.2}x\n",
+            "speedup over naive",
+            naive.as_secs_f64() / blocked.as_secs_f64(),
+            naive.as_secs_f64() / transposed.as_secs_f64(),
+            naive.as_secs_f64() / blocked32.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,141 @@
+// Compares the contiguous Matrix against the previous Vec<Vec<f64>> layout on
+// the shapes used by the MNIST demo. Run with `cargo bench --bench matrix`.
+use neuralnetwork::matrix::{Matrix, MatrixOps};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+// the layout Matrix used before it switched to a single buffer
+struct NestedMatrix {
+    data: Vec<Vec<f64>>,
+    rows: usize,
+    cols: usize,
+}
+
+impl NestedMatrix {
+    fn from(matrix: &Matrix) -> NestedMatrix {
+        let mut data = Vec::new();
+        for row in 0..matrix.rows() {
+            data.push(matrix.row(row).to_vec());
+        }
+        NestedMatrix {
+            data,
+            rows: matrix.rows(),
+            cols: matrix.cols(),
+        }
+    }
+
+    fn product(&self, b: &NestedMatrix) -> NestedMatrix {
+        let mut new_data: Vec<Vec<f64>> = Vec::new();
+        for row in 0..self.rows {
+            let mut new_line: Vec<f64> = Vec::new();
+            for col in 0..b.cols {
+                let mut res: f64 = 0.0;
+                for k in 0..self.cols {
+                    res += self.data[row][k] * b.data[k][col]
+                }
+                new_line.push(res);
+            }
+            new_data.push(new_line);
+        }
+        NestedMatrix {
+            data: new_data,
+            rows: self.rows,
+            cols: b.cols,
+        }
+    }
+
+    fn transpose(&self) -> NestedMatrix {
+        let mut new_data = Vec::new();
+        for col in 0..self.cols {
+            let mut line = Vec::new();
+            for row in 0..self.rows {
+                line.push(self.data[row][col]);
+            }
+            new_data.push(line);
+        }
+        NestedMatrix {
+            data: new_data,
+            rows: self.cols,
+            cols: self.rows,
+        }
+    }
+
+    fn add(&self, b: &NestedMatrix) -> NestedMatrix {
+        let mut data = Vec::new();
+        for row in 0..self.rows {
+            let mut line = Vec::new();
+            for col in 0..self.cols {
+                line.push(self.data[row][col] + b.data[row][col]);
+            }
+            data.push(line);
+        }
+        NestedMatrix {
+            data,
+            rows: self.rows,
+            cols: self.cols,
+        }
+    }
+}
+
+fn bench<F: FnMut()>(name: &str, mut f: F) -> Duration {
+    // warm up, then time enough iterations to run for roughly half a second
+    f();
+    let start = Instant::now();
+    let mut iterations = 0u32;
+    while start.elapsed() < Duration::from_millis(500) {
+        f();
+        iterations += 1;
+    }
+    let per_iter = start.elapsed() / iterations;
+    println!("{:<40} {:>12?} / iter", name, per_iter);
+    per_iter
+}
+
+fn compare(name: &str, nested: Duration, flat: Duration) {
+    println!(
+        "{:<40} {:>11.2}x speedup\n",
+        name,
+        nested.as_secs_f64() / flat.as_secs_f64()
+    );
+}
+
+fn main() {
+    for &(rows, inner, cols) in [(100, 784, 1), (10, 100, 1), (100, 784, 32), (10, 100, 32)].iter()
+    {
+        let a = Matrix::new_by_rand(rows, inner);
+        let b = Matrix::new_by_rand(inner, cols);
+        let (na, nb) = (NestedMatrix::from(&a), NestedMatrix::from(&b));
+        let label = format!("product {}x{} * {}x{}", rows, inner, inner, cols);
+        let nested = bench(&format!("nested {}", label), || {
+            black_box(na.product(black_box(&nb)));
+        });
+        let flat = bench(&format!("flat   {}", label), || {
+            black_box(a.product(black_box(&b)));
+        });
+        compare(&label, nested, flat);
+    }
+
+    for &(rows, cols) in [(784, 100), (100, 10)].iter() {
+        let a = Matrix::new_by_rand(rows, cols);
+        let b = Matrix::new_by_rand(rows, cols);
+        let (na, nb) = (NestedMatrix::from(&a), NestedMatrix::from(&b));
+
+        let label = format!("transpose {}x{}", rows, cols);
+        let nested = bench(&format!("nested {}", label), || {
+            black_box(na.transpose());
+        });
+        let flat = bench(&format!("flat   {}", label), || {
+            black_box(a.transpose());
+        });
+        compare(&label, nested, flat);
+
+        let label = format!("add {}x{}", rows, cols);
+        let nested = bench(&format!("nested {}", label), || {
+            black_box(na.add(black_box(&nb)));
+        });
+        let flat = bench(&format!("flat   {}", label), || {
+            black_box(a.add(black_box(&b)));
+        });
+        compare(&label, nested, flat);
+    }
+}
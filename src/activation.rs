@@ -0,0 +1,264 @@
+use crate::matrix::{Matrix, MatrixOps};
+use crate::scalar::Scalar;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Activation {
+    Sigmoid,
+    Tanh,
+    Relu,
+    LeakyRelu(f64),
+    Elu(f64),
+    Gelu,
+    Softplus,
+    Identity,
+    Softmax,
+}
+
+// sqrt(2 / pi), used by the tanh approximation of GELU
+const GELU_COEF: f64 = 0.797_884_560_802_865_4;
+
+impl Activation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Activation::Sigmoid => "sigmoid",
+            Activation::Tanh => "tanh",
+            Activation::Relu => "relu",
+            Activation::LeakyRelu(_) => "leaky_relu",
+            Activation::Elu(_) => "elu",
+            Activation::Gelu => "gelu",
+            Activation::Softplus => "softplus",
+            Activation::Identity => "identity",
+            Activation::Softmax => "softmax",
+        }
+    }
+
+    // a = f(z), element-wise except for softmax which normalizes each column
+    pub fn forward<T: Scalar>(&self, input: &Matrix<T>) -> Matrix<T> {
+        if let Activation::Softmax = self {
+            return softmax(input);
+        }
+        input.map(|x| self.apply(x))
+    }
+
+    // given z, a = f(z) and dL/da, return dL/dz
+    pub fn backward<T: Scalar>(
+        &self,
+        input: &Matrix<T>,
+        output: &Matrix<T>,
+        grad: &Matrix<T>,
+    ) -> Matrix<T> {
+        assert_eq!(input.rows, grad.rows);
+        assert_eq!(input.cols, grad.cols);
+        if let Activation::Softmax = self {
+            return softmax_backward(output, grad);
+        }
+        let mut res = grad.clone();
+        for (i, g) in res.data.iter_mut().enumerate() {
+            *g *= self.derivative(input.data[i], output.data[i]);
+        }
+        res
+    }
+
+    fn apply<T: Scalar>(&self, x: T) -> T {
+        let zero = T::zero();
+        let half = T::from_f64(0.5);
+        match *self {
+            Activation::Sigmoid => Matrix::sigmoid(x),
+            Activation::Tanh => x.tanh(),
+            Activation::Relu => x.max(zero),
+            Activation::LeakyRelu(alpha) => {
+                if x > zero {
+                    x
+                } else {
+                    T::from_f64(alpha) * x
+                }
+            }
+            Activation::Elu(alpha) => {
+                if x > zero {
+                    x
+                } else {
+                    T::from_f64(alpha) * x.exp_m1()
+                }
+            }
+            Activation::Gelu => {
+                let inner = T::from_f64(GELU_COEF) * (x + T::from_f64(0.044715) * x.powi(3));
+                half * x * (T::one() + inner.tanh())
+            }
+            Activation::Softplus => {
+                // log(1 + e^x) without overflow for large x
+                x.max(zero) + (-x.abs()).exp().ln_1p()
+            }
+            Activation::Identity => x,
+            Activation::Softmax => unreachable!(),
+        }
+    }
+
+    fn derivative<T: Scalar>(&self, x: T, y: T) -> T {
+        let zero = T::zero();
+        let one = T::one();
+        match *self {
+            Activation::Sigmoid => y * (one - y),
+            Activation::Tanh => one - y * y,
+            Activation::Relu => {
+                if x > zero {
+                    one
+                } else {
+                    zero
+                }
+            }
+            Activation::LeakyRelu(alpha) => {
+                if x > zero {
+                    one
+                } else {
+                    T::from_f64(alpha)
+                }
+            }
+            Activation::Elu(alpha) => {
+                if x > zero {
+                    one
+                } else {
+                    y + T::from_f64(alpha)
+                }
+            }
+            Activation::Gelu => {
+                let coef = T::from_f64(GELU_COEF);
+                let cubic = T::from_f64(0.044715);
+                let half = T::from_f64(0.5);
+                let t = (coef * (x + cubic * x.powi(3))).tanh();
+                let d_inner = coef * (one + T::from_f64(3.0) * cubic * x * x);
+                half * (one + t) + half * x * (one - t * t) * d_inner
+            }
+            Activation::Softplus => Matrix::sigmoid(x),
+            Activation::Identity => one,
+            Activation::Softmax => unreachable!(),
+        }
+    }
+}
+
+fn softmax<T: Scalar>(input: &Matrix<T>) -> Matrix<T> {
+    let mut output = input.clone();
+    for col in 0..input.cols {
+        let mut max = input.get(0, col);
+        for row in 1..input.rows {
+            max = max.max(input.get(row, col));
+        }
+        let mut sum = T::zero();
+        for row in 0..input.rows {
+            let e = (input.get(row, col) - max).exp();
+            *output.get_mut(row, col) = e;
+            sum += e;
+        }
+        for row in 0..input.rows {
+            *output.get_mut(row, col) /= sum;
+        }
+    }
+    output
+}
+
+// dL/dz_i = s_i * (dL/ds_i - sum_j dL/ds_j * s_j), per column
+fn softmax_backward<T: Scalar>(output: &Matrix<T>, grad: &Matrix<T>) -> Matrix<T> {
+    let mut res = grad.clone();
+    for col in 0..output.cols {
+        let mut dot = T::zero();
+        for row in 0..output.rows {
+            dot += grad.get(row, col) * output.get(row, col);
+        }
+        for row in 0..output.rows {
+            *res.get_mut(row, col) = output.get(row, col) * (grad.get(row, col) - dot);
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod activation_tests {
+    use super::Activation;
+    use crate::matrix::{Matrix, MatrixOps};
+    use crate::test_util::{assert_gradient, numeric_gradient};
+
+    const ALL: [Activation; 9] = [
+        Activation::Sigmoid,
+        Activation::Tanh,
+        Activation::Relu,
+        Activation::LeakyRelu(0.01),
+        Activation::Elu(1.0),
+        Activation::Gelu,
+        Activation::Softplus,
+        Activation::Identity,
+        Activation::Softmax,
+    ];
+
+    // L = sum(g * f(z)), so dL/dz can be checked by central differences
+    fn weighted_sum(activation: &Activation, z: &Matrix, g: &Matrix) -> f64 {
+        activation.forward(z).dot(g)
+    }
+
+    #[test]
+    fn test_gradient_check() {
+        let z = Matrix::new(vec![
+            vec![0.9, -1.3, 0.05],
+            vec![-0.4, 2.2, -0.7],
+            vec![1.7, -0.2, 0.3],
+        ]);
+        let g = Matrix::new(vec![
+            vec![0.3, -0.8, 1.1],
+            vec![-0.5, 0.2, 0.6],
+            vec![0.9, 0.4, -0.1],
+        ]);
+        for activation in ALL.iter() {
+            let output = activation.forward(&z);
+            let analytic = activation.backward(&z, &output, &g);
+            let numeric = numeric_gradient(&z, 1e-6, |z| weighted_sum(activation, z, &g));
+            assert_gradient(activation.name(), &numeric, &analytic, 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_sigmoid_matches_matrix() {
+        let z = Matrix::new(vec![vec![0.975, 0.888, 1.254]]);
+        let mut expected = z.clone();
+        expected.activate_sigmoid();
+        assert_eq!(Activation::Sigmoid.forward(&z).data, expected.data);
+    }
+
+    #[test]
+    fn test_softmax_columns_sum_to_one() {
+        let z = Matrix::new(vec![vec![1000.0, 0.1], vec![1001.0, 0.2], vec![999.0, 0.3]]);
+        let s = Activation::Softmax.forward(&z);
+        s.show();
+        for col in 0..s.cols {
+            let sum: f64 = (0..s.rows).map(|row| s.get(row, col)).sum();
+            assert!((sum - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_relu_family() {
+        let z = Matrix::new(vec![vec![-2.0, 0.0, 3.0]]);
+        assert_eq!(Activation::Relu.forward(&z).data, vec![0.0, 0.0, 3.0]);
+        assert_eq!(
+            Activation::LeakyRelu(0.1).forward(&z).data,
+            vec![-0.2, 0.0, 3.0]
+        );
+        assert_eq!(Activation::Identity.forward(&z).data, z.data);
+    }
+
+    #[test]
+    fn test_f32_matches_f64() {
+        let z = Matrix::new(vec![vec![0.9, -1.3, 0.05], vec![-0.4, 2.2, -0.7]]);
+        let g = Matrix::new(vec![vec![0.3, -0.8, 1.1], vec![-0.5, 0.2, 0.6]]);
+        let (z32, g32) = (z.convert::<f32>(), g.convert::<f32>());
+        for activation in ALL.iter() {
+            let output = activation.forward(&z);
+            let output32 = activation.forward(&z32);
+            assert!(output32.convert().approx_eq(&output, 1e-6));
+            let grad = activation.backward(&z, &output, &g);
+            let grad32 = activation.backward(&z32, &output32, &g32);
+            assert!(
+                grad32.convert().approx_eq(&grad, 1e-5),
+                "{} f32 gradient differs",
+                activation.name()
+            );
+        }
+    }
+}
@@ -0,0 +1,458 @@
+use crate::activation::Activation;
+use crate::loss::{softmax_cross_entropy, softmax_cross_entropy_gradient, Loss};
+use crate::matrix::{Matrix, MatrixError, MatrixOps};
+use crate::scalar::Scalar;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum AutogradError {
+    Matrix(MatrixError),
+    // a layer the tape has no operations for
+    Unsupported { layer: usize, name: &'static str },
+    // a parameter the loss was not computed from
+    MissingGradient { parameter: usize },
+}
+
+impl fmt::Display for AutogradError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AutogradError::Matrix(err) => write!(f, "{}", err),
+            AutogradError::Unsupported { layer, name } => {
+                write!(f, "layer {} ({}) cannot be recorded on a tape", layer, name)
+            }
+            AutogradError::MissingGradient { parameter } => {
+                write!(f, "the loss does not depend on parameter {}", parameter)
+            }
+        }
+    }
+}
+
+impl Error for AutogradError {}
+
+impl From<MatrixError> for AutogradError {
+    fn from(err: MatrixError) -> AutogradError {
+        AutogradError::Matrix(err)
+    }
+}
+
+// Handle of a value recorded on a tape, only meaningful for that tape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Var(usize);
+
+// operation that produced a node, with the nodes it read
+#[derive(Debug, Clone)]
+enum Op<T> {
+    Leaf,
+    Product(usize, usize),
+    Add(usize, usize),
+    // a column added to every column
+    AddColumn(usize, usize),
+    Sub(usize, usize),
+    Mul(usize, usize),
+    Scale(usize, f64),
+    Transpose(usize),
+    Abs(usize),
+    Activation(usize, Activation),
+    Sum(usize),
+    SumColumns(usize),
+    Loss(usize, Matrix<T>, Loss),
+    SoftmaxCrossEntropy(usize, Matrix<T>),
+}
+
+#[derive(Debug, Clone)]
+struct Node<T> {
+    value: Matrix<T>,
+    op: Op<T>,
+}
+
+// Reverse-mode automatic differentiation. Every operation computes its value
+// right away and appends a node to the tape, backward() then walks the tape
+// from the end and hands each node's gradient to the nodes it read.
+// Reductions and losses give 1x1 values, e.g.
+//   let w = tape.leaf(weights);
+//   let x = tape.leaf(inputs);
+//   let z = tape.product(w, x)?;
+//   let a = tape.activation(z, Activation::Sigmoid);
+//   let loss = tape.loss(a, &labels, Loss::Mse)?;
+//   let dw = tape.backward(loss).get(w);
+#[derive(Debug, Clone)]
+pub struct Tape<T = f64> {
+    nodes: Vec<Node<T>>,
+}
+
+impl<T: Scalar> Default for Tape<T> {
+    fn default() -> Tape<T> {
+        Tape::new()
+    }
+}
+
+impl<T: Scalar> Tape<T> {
+    pub fn new() -> Tape<T> {
+        Tape { nodes: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn value(&self, var: Var) -> &Matrix<T> {
+        &self.nodes[var.0].value
+    }
+
+    fn push(&mut self, value: Matrix<T>, op: Op<T>) -> Var {
+        self.nodes.push(Node { value, op });
+        Var(self.nodes.len() - 1)
+    }
+
+    // an input or parameter, gradients can be asked for any leaf
+    pub fn leaf(&mut self, value: Matrix<T>) -> Var {
+        self.push(value, Op::Leaf)
+    }
+
+    pub fn product(&mut self, a: Var, b: Var) -> Result<Var, MatrixError> {
+        let value = self.value(a).try_product(self.value(b))?;
+        Ok(self.push(value, Op::Product(a.0, b.0)))
+    }
+
+    pub fn add(&mut self, a: Var, b: Var) -> Result<Var, MatrixError> {
+        let value = self.value(a).try_add(self.value(b))?;
+        Ok(self.push(value, Op::Add(a.0, b.0)))
+    }
+
+    // column is rows x 1 and is added to every column of a, like a bias
+    pub fn add_column(&mut self, a: Var, column: Var) -> Result<Var, MatrixError> {
+        let value = self.value(a).try_add_column(self.value(column))?;
+        Ok(self.push(value, Op::AddColumn(a.0, column.0)))
+    }
+
+    pub fn sub(&mut self, a: Var, b: Var) -> Result<Var, MatrixError> {
+        let value = self.value(a).try_sub(self.value(b))?;
+        Ok(self.push(value, Op::Sub(a.0, b.0)))
+    }
+
+    // element-wise
+    pub fn mul(&mut self, a: Var, b: Var) -> Result<Var, MatrixError> {
+        let value = self.value(a).try_mul(self.value(b))?;
+        Ok(self.push(value, Op::Mul(a.0, b.0)))
+    }
+
+    pub fn scale(&mut self, a: Var, factor: f64) -> Var {
+        let value = self.value(a).mul_const(T::from_f64(factor));
+        self.push(value, Op::Scale(a.0, factor))
+    }
+
+    pub fn transpose(&mut self, a: Var) -> Var {
+        let value = self.value(a).transpose();
+        self.push(value, Op::Transpose(a.0))
+    }
+
+    // the gradient at 0 is taken as 0
+    pub fn abs(&mut self, a: Var) -> Var {
+        let value = self.value(a).map(|x| x.abs());
+        self.push(value, Op::Abs(a.0))
+    }
+
+    pub fn activation(&mut self, a: Var, activation: Activation) -> Var {
+        let value = activation.forward(self.value(a));
+        self.push(value, Op::Activation(a.0, activation))
+    }
+
+    // sum of all elements
+    pub fn sum(&mut self, a: Var) -> Var {
+        let sum = self
+            .value(a)
+            .as_slice()
+            .iter()
+            .fold(T::zero(), |s, x| s + *x);
+        self.push(Matrix::from_vec(1, 1, vec![sum]), Op::Sum(a.0))
+    }
+
+    pub fn mean(&mut self, a: Var) -> Var {
+        let len = self.value(a).as_slice().len();
+        let sum = self.sum(a);
+        self.scale(sum, 1.0 / len as f64)
+    }
+
+    // sum of every row, rows x 1
+    pub fn sum_columns(&mut self, a: Var) -> Var {
+        let value = self.value(a).sum_columns();
+        self.push(value, Op::SumColumns(a.0))
+    }
+
+    // mean loss over the columns of output, see Loss
+    pub fn loss(
+        &mut self,
+        output: Var,
+        target: &Matrix<T>,
+        loss: Loss,
+    ) -> Result<Var, MatrixError> {
+        check_target(self.value(output), target)?;
+        let value = T::from_f64(loss.loss(self.value(output), target));
+        Ok(self.push(
+            Matrix::from_vec(1, 1, vec![value]),
+            Op::Loss(output.0, target.clone(), loss),
+        ))
+    }
+
+    // softmax of the logits followed by the categorical cross entropy, taken
+    // in one step so that saturated outputs keep a finite gradient
+    pub fn softmax_cross_entropy(
+        &mut self,
+        logits: Var,
+        target: &Matrix<T>,
+    ) -> Result<Var, MatrixError> {
+        check_target(self.value(logits), target)?;
+        let value = T::from_f64(softmax_cross_entropy(self.value(logits), target));
+        Ok(self.push(
+            Matrix::from_vec(1, 1, vec![value]),
+            Op::SoftmaxCrossEntropy(logits.0, target.clone()),
+        ))
+    }
+
+    // Gradients of the sum of all elements of output with respect to the
+    // leaves recorded before it, so a 1x1 loss gives the usual gradients.
+    pub fn backward(&self, output: Var) -> Gradients<T> {
+        let mut grads: Vec<Option<Matrix<T>>> = vec![None; output.0 + 1];
+        let (rows, cols) = self.value(output).shape();
+        grads[output.0] = Some(Matrix::ones(rows, cols));
+        for index in (0..=output.0).rev() {
+            let grad = match grads[index].take() {
+                Some(grad) => grad,
+                None => continue,
+            };
+            let node = &self.nodes[index];
+            let value = |i: usize| &self.nodes[i].value;
+            match &node.op {
+                Op::Leaf => {}
+                Op::Product(a, b) => {
+                    accumulate(&mut grads, *a, grad.product_transposed(value(*b)));
+                    accumulate(&mut grads, *b, value(*a).transpose().product(&grad));
+                }
+                Op::Add(a, b) => {
+                    accumulate(&mut grads, *a, grad.clone());
+                    accumulate(&mut grads, *b, grad.clone());
+                }
+                Op::AddColumn(a, column) => {
+                    accumulate(&mut grads, *column, grad.sum_columns());
+                    accumulate(&mut grads, *a, grad.clone());
+                }
+                Op::Sub(a, b) => {
+                    accumulate(&mut grads, *b, -&grad);
+                    accumulate(&mut grads, *a, grad.clone());
+                }
+                Op::Mul(a, b) => {
+                    accumulate(&mut grads, *a, grad.mul(value(*b)));
+                    accumulate(&mut grads, *b, grad.mul(value(*a)));
+                }
+                Op::Scale(a, factor) => {
+                    accumulate(&mut grads, *a, grad.mul_const(T::from_f64(*factor)));
+                }
+                Op::Transpose(a) => accumulate(&mut grads, *a, grad.transpose()),
+                Op::Abs(a) => {
+                    let sign = value(*a).map(|x| {
+                        if x > T::zero() {
+                            T::one()
+                        } else if x < T::zero() {
+                            -T::one()
+                        } else {
+                            T::zero()
+                        }
+                    });
+                    accumulate(&mut grads, *a, grad.mul(&sign));
+                }
+                Op::Activation(a, activation) => {
+                    let input = value(*a);
+                    accumulate(
+                        &mut grads,
+                        *a,
+                        activation.backward(input, &node.value, &grad),
+                    );
+                }
+                Op::Sum(a) => {
+                    let (rows, cols) = value(*a).shape();
+                    accumulate(
+                        &mut grads,
+                        *a,
+                        Matrix::ones(rows, cols).mul_const(grad[(0, 0)]),
+                    );
+                }
+                Op::SumColumns(a) => {
+                    let (rows, cols) = value(*a).shape();
+                    accumulate(&mut grads, *a, Matrix::zeros(rows, cols).add_column(&grad));
+                }
+                Op::Loss(a, target, loss) => {
+                    let gradient = loss.gradient(value(*a), target);
+                    accumulate(&mut grads, *a, gradient.mul_const(grad[(0, 0)]));
+                }
+                Op::SoftmaxCrossEntropy(a, target) => {
+                    let output = Activation::Softmax.forward(value(*a));
+                    let gradient = softmax_cross_entropy_gradient(&output, target);
+                    accumulate(&mut grads, *a, gradient.mul_const(grad[(0, 0)]));
+                }
+            }
+            // the gradients of leaves are kept for the caller
+            if let Op::Leaf = node.op {
+                grads[index] = Some(grad);
+            }
+        }
+        Gradients { grads }
+    }
+}
+
+fn accumulate<T: Scalar>(grads: &mut [Option<Matrix<T>>], index: usize, grad: Matrix<T>) {
+    match &mut grads[index] {
+        Some(sum) => *sum += grad,
+        None => grads[index] = Some(grad),
+    }
+}
+
+fn check_target<T: Scalar>(output: &Matrix<T>, target: &Matrix<T>) -> Result<(), MatrixError> {
+    if output.shape() != target.shape() {
+        return Err(MatrixError::ShapeMismatch {
+            op: "loss",
+            left: output.shape(),
+            right: target.shape(),
+        });
+    }
+    Ok(())
+}
+
+// Result of Tape::backward, holds the gradient of every leaf the output
+// depends on.
+#[derive(Debug, Clone)]
+pub struct Gradients<T = f64> {
+    grads: Vec<Option<Matrix<T>>>,
+}
+
+impl<T: Scalar> Gradients<T> {
+    // None for leaves the output does not depend on or that were recorded after it
+    pub fn get(&self, var: Var) -> Option<&Matrix<T>> {
+        self.grads.get(var.0).and_then(|grad| grad.as_ref())
+    }
+
+    pub fn take(&mut self, var: Var) -> Option<Matrix<T>> {
+        self.grads.get_mut(var.0).and_then(|grad| grad.take())
+    }
+}
+
+#[cfg(test)]
+mod autograd_tests {
+    use super::{Tape, Var};
+    use crate::activation::Activation;
+    use crate::init::{seeded_rng, Initializer};
+    use crate::loss::Loss;
+    use crate::matrix::{Matrix, MatrixOps};
+    use crate::test_util::{assert_gradient, numeric_gradient};
+
+    fn random(rows: usize, cols: usize, seed: u64) -> Matrix {
+        Initializer::Normal(0.0, 1.0).init(rows, cols, &mut seeded_rng(seed))
+    }
+
+    // compares the tape's gradient of every leaf with central differences of
+    // the function rebuilt from perturbed leaves
+    fn check<F: Fn(&mut Tape, &[Var]) -> Var>(leaves: Vec<Matrix>, f: F) {
+        let record = |leaves: &[Matrix]| {
+            let mut tape = Tape::new();
+            let vars: Vec<Var> = leaves.iter().map(|leaf| tape.leaf(leaf.clone())).collect();
+            let output = f(&mut tape, &vars);
+            (tape, vars, output)
+        };
+        let (tape, vars, output) = record(&leaves);
+        assert_eq!(tape.value(output).shape(), (1, 1));
+        let grads = tape.backward(output);
+        for (i, var) in vars.iter().enumerate() {
+            let analytic = grads.get(*var).unwrap();
+            let numeric = numeric_gradient(&leaves[i], 1e-6, |leaf| {
+                let mut leaves = leaves.clone();
+                leaves[i] = leaf.clone();
+                let (tape, _, output) = record(&leaves);
+                tape.value(output)[(0, 0)]
+            });
+            assert_gradient(&format!("leaf {}", i), &numeric, analytic, 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_simple() {
+        // d/da sum(a * b + a) = b + 1, a is read twice
+        let mut tape: Tape = Tape::new();
+        let a = tape.leaf(Matrix::new(vec![vec![1.0, 2.0], vec![3.0, 4.0]]));
+        let b = tape.leaf(Matrix::new(vec![vec![5.0, 6.0], vec![7.0, 8.0]]));
+        let c = tape.leaf(Matrix::ones(1, 1));
+        let ab = tape.mul(a, b).unwrap();
+        let sum = tape.add(ab, a).unwrap();
+        let output = tape.sum(sum);
+        println!("{:?}", tape.value(output));
+        assert_eq!(tape.value(output)[(0, 0)], 80.0);
+        assert_eq!(tape.len(), 6);
+
+        let grads = tape.backward(output);
+        assert_eq!(
+            grads.get(a).unwrap(),
+            &tape.value(b).add(&Matrix::ones(2, 2))
+        );
+        assert_eq!(grads.get(b).unwrap(), tape.value(a));
+        assert!(grads.get(c).is_none());
+        assert!(grads.get(ab).is_none());
+
+        assert!(tape.product(a, c).is_err());
+        assert!(tape.loss(a, &Matrix::ones(3, 2), Loss::Mse).is_err());
+    }
+
+    #[test]
+    fn test_ops() {
+        check(vec![random(3, 4, 1), random(4, 2, 2)], |tape, v| {
+            let p = tape.product(v[0], v[1]).unwrap();
+            let t = tape.transpose(p);
+            let s = tape.scale(t, 0.5);
+            let m = tape.mul(s, t).unwrap();
+            tape.mean(m)
+        });
+        check(
+            vec![random(3, 4, 3), random(3, 1, 4), random(3, 4, 5)],
+            |tape, v| {
+                let a = tape.add_column(v[0], v[1]).unwrap();
+                let d = tape.sub(a, v[2]).unwrap();
+                let r = tape.sum_columns(d);
+                let r = tape.abs(r);
+                let r = tape.mul(r, v[1]).unwrap();
+                tape.sum(r)
+            },
+        );
+    }
+
+    #[test]
+    fn test_activations_and_losses() {
+        let activations = [
+            Activation::Sigmoid,
+            Activation::Tanh,
+            Activation::LeakyRelu(0.1),
+            Activation::Elu(1.0),
+            Activation::Gelu,
+            Activation::Softplus,
+            Activation::Softmax,
+        ];
+        let target = Matrix::new(vec![vec![1.0, 0.0], vec![0.0, 0.0], vec![0.0, 1.0]]);
+        for activation in activations.iter() {
+            println!("{}", activation.name());
+            check(vec![random(3, 2, 6)], |tape, v| {
+                let a = tape.activation(v[0], *activation);
+                let b = tape.activation(a, Activation::Sigmoid);
+                tape.loss(b, &target, Loss::BinaryCrossEntropy).unwrap()
+            });
+        }
+        for loss in [Loss::Mse, Loss::Mae, Loss::Huber(0.5)].iter() {
+            check(vec![random(3, 2, 7)], |tape, v| {
+                tape.loss(v[0], &target, *loss).unwrap()
+            });
+        }
+        check(vec![random(3, 2, 8)], |tape, v| {
+            tape.softmax_cross_entropy(v[0], &target).unwrap()
+        });
+    }
+}
@@ -0,0 +1,675 @@
+use crate::activation::Activation;
+use crate::conv::Conv2d;
+use crate::dropout::Dropout;
+use crate::init::{seeded_rng, Initializer};
+use crate::layer::{ActivationLayer, Dense, Layer, Reshape};
+use crate::loss::Loss;
+use crate::nn::NeuralNetwork;
+use crate::normalization::{BatchNorm, LayerNorm};
+use crate::optimizer::Optimizer;
+use crate::scalar::Scalar;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildError {
+    Empty,
+    // a layer that needs to know its input size before any input was declared
+    MissingInput {
+        layer: usize,
+        name: &'static str,
+    },
+    ZeroSize {
+        layer: usize,
+        name: &'static str,
+    },
+    SizeMismatch {
+        layer: usize,
+        name: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    // a convolution whose input is not [channels, height, width]
+    NotSpatial {
+        layer: usize,
+        shape: Vec<usize>,
+    },
+    KernelTooLarge {
+        layer: usize,
+        kernel: (usize, usize),
+        input: (usize, usize),
+        padding: usize,
+    },
+    InvalidRate {
+        layer: usize,
+        rate: f64,
+    },
+    // a declared input whose size does not fit in a usize
+    InputTooLarge {
+        shape: Vec<usize>,
+    },
+    // a layer whose weights or output size do not fit in a usize
+    TooLarge {
+        layer: usize,
+        name: &'static str,
+    },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::Empty => write!(f, "network has no layers"),
+            BuildError::MissingInput { layer, name } => write!(
+                f,
+                "layer {} ({}) needs an input size, declare it with input()",
+                layer, name
+            ),
+            BuildError::ZeroSize { layer, name } => {
+                write!(f, "layer {} ({}) has a zero-sized dimension", layer, name)
+            }
+            BuildError::SizeMismatch {
+                layer,
+                name,
+                expected,
+                found,
+            } => {
+                write!(
+                    f,
+                    "layer {} ({}) expects {} values per sample but ",
+                    layer, name, expected
+                )?;
+                match layer {
+                    0 => write!(f, "the input has {}", found),
+                    _ => write!(f, "layer {} outputs {}", layer - 1, found),
+                }
+            }
+            BuildError::NotSpatial { layer, shape } => write!(
+                f,
+                "layer {} (conv2d) needs a [channels, height, width] input but gets {:?}",
+                layer, shape
+            ),
+            BuildError::KernelTooLarge {
+                layer,
+                kernel,
+                input,
+                padding,
+            } => write!(
+                f,
+                "layer {} (conv2d) kernel {}x{} does not fit the {}x{} input with padding {}",
+                layer, kernel.0, kernel.1, input.0, input.1, padding
+            ),
+            BuildError::InvalidRate { layer, rate } => {
+                write!(
+                    f,
+                    "layer {} (dropout) rate {} is not in [0, 1)",
+                    layer, rate
+                )
+            }
+            BuildError::InputTooLarge { shape } => {
+                write!(f, "input shape {:?} is too large", shape)
+            }
+            BuildError::TooLarge { layer, name } => {
+                write!(f, "layer {} ({}) is too large", layer, name)
+            }
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+// number of values in a shape, None when it overflows
+fn shape_size(shape: &[usize]) -> Option<usize> {
+    shape
+        .iter()
+        .try_fold(1usize, |size, &dim| size.checked_mul(dim))
+}
+
+#[derive(Debug)]
+enum LayerSpec<T> {
+    Dense {
+        units: usize,
+        bias: bool,
+    },
+    Activation(Activation),
+    Dropout(f64),
+    BatchNorm,
+    LayerNorm,
+    Conv2d {
+        out_channels: usize,
+        kernel: (usize, usize),
+        stride: usize,
+        padding: usize,
+    },
+    Reshape(Vec<usize>),
+    Custom(Box<dyn Layer<T>>),
+}
+
+// Declares a network layer by layer, e.g.
+//   NeuralNetwork::builder().input(784).dense(128).relu().dropout(0.2).dense(10).softmax().build()
+// Every layer takes its input size from the one before it, the shapes are
+// checked and the weights drawn by build().
+#[derive(Debug)]
+pub struct NetworkBuilder<T = f64> {
+    input: Option<Vec<usize>>,
+    layers: Vec<LayerSpec<T>>,
+    initializer: Initializer,
+    seed: Option<u64>,
+    lr: Option<f64>,
+    optimizer: Option<Box<dyn Optimizer<T>>>,
+    loss: Option<Loss>,
+}
+
+impl<T: Scalar> Default for NetworkBuilder<T> {
+    fn default() -> NetworkBuilder<T> {
+        NetworkBuilder::new()
+    }
+}
+
+impl<T: Scalar> NetworkBuilder<T> {
+    pub fn new() -> NetworkBuilder<T> {
+        NetworkBuilder {
+            input: None,
+            layers: Vec::new(),
+            initializer: Initializer::default(),
+            seed: None,
+            lr: None,
+            optimizer: None,
+            loss: None,
+        }
+    }
+
+    // number of values per sample
+    pub fn input(mut self, size: usize) -> NetworkBuilder<T> {
+        self.input = Some(vec![size]);
+        self
+    }
+
+    // per-sample shape, e.g. [channels, height, width] in front of a convolution
+    pub fn input_shape(mut self, shape: Vec<usize>) -> NetworkBuilder<T> {
+        self.input = Some(shape);
+        self
+    }
+
+    // used by the dense and convolution layers declared after it
+    pub fn initializer(mut self, initializer: Initializer) -> NetworkBuilder<T> {
+        self.initializer = initializer;
+        self
+    }
+
+    // all weights are drawn from one generator seeded with `seed`
+    pub fn seed(mut self, seed: u64) -> NetworkBuilder<T> {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn lr(mut self, lr: f64) -> NetworkBuilder<T> {
+        self.lr = Some(lr);
+        self
+    }
+
+    pub fn optimizer<O: Optimizer<T> + 'static>(mut self, optimizer: O) -> NetworkBuilder<T> {
+        self.optimizer = Some(Box::new(optimizer));
+        self
+    }
+
+    // defaults to categorical cross-entropy after a softmax output and MSE otherwise
+    pub fn loss(mut self, loss: Loss) -> NetworkBuilder<T> {
+        self.loss = Some(loss);
+        self
+    }
+
+    pub fn dense(mut self, units: usize) -> NetworkBuilder<T> {
+        self.layers.push(LayerSpec::Dense { units, bias: true });
+        self
+    }
+
+    pub fn dense_without_bias(mut self, units: usize) -> NetworkBuilder<T> {
+        self.layers.push(LayerSpec::Dense { units, bias: false });
+        self
+    }
+
+    pub fn activation(mut self, activation: Activation) -> NetworkBuilder<T> {
+        self.layers.push(LayerSpec::Activation(activation));
+        self
+    }
+
+    pub fn sigmoid(self) -> NetworkBuilder<T> {
+        self.activation(Activation::Sigmoid)
+    }
+
+    pub fn tanh(self) -> NetworkBuilder<T> {
+        self.activation(Activation::Tanh)
+    }
+
+    pub fn relu(self) -> NetworkBuilder<T> {
+        self.activation(Activation::Relu)
+    }
+
+    pub fn leaky_relu(self, alpha: f64) -> NetworkBuilder<T> {
+        self.activation(Activation::LeakyRelu(alpha))
+    }
+
+    pub fn softmax(self) -> NetworkBuilder<T> {
+        self.activation(Activation::Softmax)
+    }
+
+    pub fn dropout(mut self, rate: f64) -> NetworkBuilder<T> {
+        self.layers.push(LayerSpec::Dropout(rate));
+        self
+    }
+
+    pub fn batch_norm(mut self) -> NetworkBuilder<T> {
+        self.layers.push(LayerSpec::BatchNorm);
+        self
+    }
+
+    pub fn layer_norm(mut self) -> NetworkBuilder<T> {
+        self.layers.push(LayerSpec::LayerNorm);
+        self
+    }
+
+    // the input must be [channels, height, width], see input_shape and reshape
+    pub fn conv2d(
+        mut self,
+        out_channels: usize,
+        kernel: (usize, usize),
+        stride: usize,
+        padding: usize,
+    ) -> NetworkBuilder<T> {
+        self.layers.push(LayerSpec::Conv2d {
+            out_channels,
+            kernel,
+            stride,
+            padding,
+        });
+        self
+    }
+
+    pub fn reshape(mut self, shape: Vec<usize>) -> NetworkBuilder<T> {
+        self.layers.push(LayerSpec::Reshape(shape));
+        self
+    }
+
+    // any other layer, its input size is checked like the built-in ones
+    pub fn layer<L: Layer<T> + 'static>(mut self, layer: L) -> NetworkBuilder<T> {
+        self.layers.push(LayerSpec::Custom(Box::new(layer)));
+        self
+    }
+
+    pub fn build(self) -> Result<NeuralNetwork<T>, BuildError> {
+        if self.layers.is_empty() {
+            return Err(BuildError::Empty);
+        }
+        let mut rng = match self.seed {
+            Some(seed) => seeded_rng(seed),
+            None => StdRng::from_entropy(),
+        };
+        let dropout_seed = self.seed.unwrap_or_else(rand::random);
+        if let Some(input) = &self.input {
+            if shape_size(input).is_none() {
+                return Err(BuildError::InputTooLarge {
+                    shape: input.clone(),
+                });
+            }
+        }
+        // per-sample shape of the current output, None until it is known
+        let mut shape = self.input.clone();
+        let mut layers: Vec<Box<dyn Layer<T>>> = Vec::new();
+        for (index, spec) in self.layers.into_iter().enumerate() {
+            let too_large = |name| BuildError::TooLarge { layer: index, name };
+            // the input and every layer's output are checked, so this cannot overflow
+            let size = shape.as_ref().map(|shape| shape.iter().product::<usize>());
+            let require = |name| size.ok_or(BuildError::MissingInput { layer: index, name });
+            if size == Some(0) {
+                return Err(BuildError::ZeroSize {
+                    layer: index,
+                    name: "input",
+                });
+            }
+            let layer: Box<dyn Layer<T>> = match spec {
+                LayerSpec::Dense { units, bias } => {
+                    let inputs = require("dense")?;
+                    if units == 0 {
+                        return Err(BuildError::ZeroSize {
+                            layer: index,
+                            name: "dense",
+                        });
+                    }
+                    inputs.checked_mul(units).ok_or(too_large("dense"))?;
+                    let dense = Dense::new_by_init(inputs, units, self.initializer, &mut rng);
+                    shape = Some(vec![units]);
+                    if bias {
+                        Box::new(dense)
+                    } else {
+                        Box::new(Dense {
+                            bias: None,
+                            ..dense
+                        })
+                    }
+                }
+                LayerSpec::Activation(activation) => Box::new(ActivationLayer::new(activation)),
+                LayerSpec::Dropout(rate) => {
+                    if !(0.0..1.0).contains(&rate) {
+                        return Err(BuildError::InvalidRate { layer: index, rate });
+                    }
+                    let seed = dropout_seed.wrapping_add(index as u64);
+                    Box::new(Dropout::new(rate).with_seed(seed))
+                }
+                LayerSpec::BatchNorm => Box::new(BatchNorm::new(require("batch_norm")?)),
+                LayerSpec::LayerNorm => Box::new(LayerNorm::new(require("layer_norm")?)),
+                LayerSpec::Conv2d {
+                    out_channels,
+                    kernel,
+                    stride,
+                    padding,
+                } => {
+                    let input = match shape.as_deref() {
+                        Some(&[channels, height, width]) => (channels, (height, width)),
+                        Some(other) => {
+                            return Err(BuildError::NotSpatial {
+                                layer: index,
+                                shape: other.to_vec(),
+                            })
+                        }
+                        None => {
+                            return Err(BuildError::MissingInput {
+                                layer: index,
+                                name: "conv2d",
+                            })
+                        }
+                    };
+                    let (channels, (height, width)) = input;
+                    if out_channels == 0 || kernel.0 == 0 || kernel.1 == 0 || stride == 0 {
+                        return Err(BuildError::ZeroSize {
+                            layer: index,
+                            name: "conv2d",
+                        });
+                    }
+                    let padded = |size: usize| {
+                        padding
+                            .checked_mul(2)
+                            .and_then(|padding| size.checked_add(padding))
+                            .ok_or(too_large("conv2d"))
+                    };
+                    if kernel.0 > padded(height)? || kernel.1 > padded(width)? {
+                        return Err(BuildError::KernelTooLarge {
+                            layer: index,
+                            kernel,
+                            input: (height, width),
+                            padding,
+                        });
+                    }
+                    shape_size(&[out_channels, channels, kernel.0, kernel.1])
+                        .ok_or(too_large("conv2d"))?;
+                    let out_height = (padded(height)? - kernel.0) / stride + 1;
+                    let out_width = (padded(width)? - kernel.1) / stride + 1;
+                    shape_size(&[out_channels, out_height, out_width])
+                        .ok_or(too_large("conv2d"))?;
+                    let conv = Conv2d::new_by_init(
+                        channels,
+                        (height, width),
+                        out_channels,
+                        kernel,
+                        self.initializer,
+                        &mut rng,
+                    )
+                    .with_stride(stride)
+                    .with_padding(padding);
+                    shape = Some(vec![out_channels, out_height, out_width]);
+                    Box::new(conv)
+                }
+                LayerSpec::Reshape(target) => {
+                    let inputs = require("reshape")?;
+                    let outputs = shape_size(&target).ok_or(too_large("reshape"))?;
+                    if outputs != inputs {
+                        return Err(BuildError::SizeMismatch {
+                            layer: index,
+                            name: "reshape",
+                            expected: outputs,
+                            found: inputs,
+                        });
+                    }
+                    let input_shape = shape.replace(target.clone()).unwrap();
+                    Box::new(Reshape::new(input_shape, target))
+                }
+                LayerSpec::Custom(layer) => {
+                    match (layer.input_size(), size) {
+                        (Some(expected), Some(found)) if expected != found => {
+                            return Err(BuildError::SizeMismatch {
+                                layer: index,
+                                name: layer.name(),
+                                expected,
+                                found,
+                            })
+                        }
+                        (None, None) => {
+                            return Err(BuildError::MissingInput {
+                                layer: index,
+                                name: layer.name(),
+                            })
+                        }
+                        _ => {}
+                    }
+                    let inputs = size.or(layer.input_size()).unwrap();
+                    let outputs = layer.output_size(inputs);
+                    // a layer that keeps the size keeps the shape
+                    if outputs != inputs || shape.is_none() {
+                        shape = Some(vec![outputs]);
+                    }
+                    layer
+                }
+            };
+            layers.push(layer);
+        }
+
+        let softmax_output = layers.last().is_some_and(|layer| {
+            layer
+                .as_any()
+                .downcast_ref::<ActivationLayer<T>>()
+                .is_some_and(|layer| layer.activation() == Activation::Softmax)
+        });
+        let loss = self.loss.unwrap_or(if softmax_output {
+            Loss::CategoricalCrossEntropy
+        } else {
+            Loss::Mse
+        });
+        let mut nn = NeuralNetwork::from_layers(layers).with_loss(loss);
+        if let Some(optimizer) = self.optimizer {
+            nn.optimizer = optimizer;
+        }
+        if let Some(lr) = self.lr {
+            nn.set_lr(lr);
+        }
+        Ok(nn)
+    }
+}
+
+#[cfg(test)]
+mod builder_tests {
+    use super::BuildError;
+    use crate::activation::Activation;
+    use crate::dataset::read_csv_by_path;
+    use crate::dropout::Dropout;
+    use crate::init::{seeded_rng, Initializer};
+    use crate::layer::{ActivationLayer, Dense};
+    use crate::loss::Loss;
+    use crate::matrix::{Matrix, MatrixOps};
+    use crate::nn::NeuralNetwork;
+    use crate::normalization::LayerNorm;
+    use crate::optimizer::Adam;
+
+    #[test]
+    fn test_build_mlp() {
+        let nn: NeuralNetwork = NeuralNetwork::builder()
+            .input(784)
+            .dense(128)
+            .relu()
+            .dropout(0.2)
+            .dense(10)
+            .softmax()
+            .build()
+            .unwrap();
+        nn.show();
+        let names: Vec<&str> = nn.layers().iter().map(|layer| layer.name()).collect();
+        assert_eq!(names, vec!["dense", "relu", "dropout", "dense", "softmax"]);
+        assert_eq!(nn.layer::<Dense>(0).unwrap().weights().shape(), (128, 784));
+        assert_eq!(nn.layer::<Dropout>(2).unwrap().rate(), 0.2);
+        assert_eq!(nn.loss(), Loss::CategoricalCrossEntropy);
+        let output = nn.inference(Matrix::zeros(784, 3)).unwrap();
+        assert_eq!(output.shape(), (10, 3));
+    }
+
+    #[test]
+    fn test_matches_new_with_init() {
+        let activations = vec![Activation::Tanh, Activation::Sigmoid];
+        let expected: NeuralNetwork = NeuralNetwork::new_with_init(
+            vec![4, 6, 2],
+            activations,
+            Initializer::XavierNormal,
+            &mut seeded_rng(5),
+        );
+        let nn: NeuralNetwork = NeuralNetwork::builder()
+            .input(4)
+            .initializer(Initializer::XavierNormal)
+            .seed(5)
+            .dense(6)
+            .tanh()
+            .dense(2)
+            .sigmoid()
+            .lr(0.1)
+            .build()
+            .unwrap();
+        for (a, b) in expected.layers().iter().zip(nn.layers()) {
+            assert_eq!(a.parameters(), b.parameters());
+        }
+        assert_eq!(nn.lr(), 0.1);
+        assert_eq!(nn.loss(), Loss::Mse);
+
+        let no_bias: NeuralNetwork = NeuralNetwork::builder()
+            .input(4)
+            .dense_without_bias(3)
+            .build()
+            .unwrap();
+        assert!(!no_bias.layer::<Dense>(0).unwrap().has_bias());
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let errors = vec![
+            (NeuralNetwork::<f64>::builder().build(), BuildError::Empty),
+            (
+                NeuralNetwork::builder().dense(3).build(),
+                BuildError::MissingInput {
+                    layer: 0,
+                    name: "dense",
+                },
+            ),
+            (
+                NeuralNetwork::builder().input(4).dense(3).dense(0).build(),
+                BuildError::ZeroSize {
+                    layer: 1,
+                    name: "dense",
+                },
+            ),
+            (
+                NeuralNetwork::builder()
+                    .input(4)
+                    .dense(3)
+                    .relu()
+                    .layer(LayerNorm::new(5))
+                    .build(),
+                BuildError::SizeMismatch {
+                    layer: 2,
+                    name: "layer_norm",
+                    expected: 5,
+                    found: 3,
+                },
+            ),
+            (
+                NeuralNetwork::builder()
+                    .input(784)
+                    .conv2d(4, (3, 3), 1, 0)
+                    .build(),
+                BuildError::NotSpatial {
+                    layer: 0,
+                    shape: vec![784],
+                },
+            ),
+            (
+                NeuralNetwork::builder()
+                    .input(784)
+                    .reshape(vec![1, 28, 27])
+                    .build(),
+                BuildError::SizeMismatch {
+                    layer: 0,
+                    name: "reshape",
+                    expected: 756,
+                    found: 784,
+                },
+            ),
+            (
+                NeuralNetwork::builder()
+                    .input_shape(vec![1, 4, 4])
+                    .conv2d(2, (7, 3), 1, 1)
+                    .build(),
+                BuildError::KernelTooLarge {
+                    layer: 0,
+                    kernel: (7, 3),
+                    input: (4, 4),
+                    padding: 1,
+                },
+            ),
+            (
+                NeuralNetwork::builder().input(4).dropout(1.0).build(),
+                BuildError::InvalidRate {
+                    layer: 0,
+                    rate: 1.0,
+                },
+            ),
+        ];
+        for (result, expected) in errors {
+            let err = result.unwrap_err();
+            println!("{}", err);
+            assert_eq!(err, expected);
+        }
+        let err = NeuralNetwork::<f64>::builder()
+            .input(4)
+            .dense(3)
+            .layer(Dense::new_by_rand(5, 2))
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "layer 1 (dense) expects 5 values per sample but layer 0 outputs 3"
+        );
+    }
+
+    #[test]
+    fn test_build_conv_net() {
+        let (labels, data) = read_csv_by_path::<f64>("data/mnist_test_10.csv").unwrap();
+        let mut nn: NeuralNetwork = NeuralNetwork::builder()
+            .input(784)
+            .seed(3)
+            .initializer(Initializer::HeNormal)
+            .reshape(vec![1, 28, 28])
+            .conv2d(4, (4, 4), 4, 0)
+            .relu()
+            .layer_norm()
+            .dense(10)
+            .softmax()
+            .optimizer(Adam::new(0.01, 0.9, 0.999))
+            .build()
+            .unwrap();
+        assert_eq!(nn.layer::<Dense>(4).unwrap().weights().shape(), (10, 196));
+        assert_eq!(
+            nn.layer::<ActivationLayer>(5).unwrap().activation(),
+            Activation::Softmax
+        );
+        assert_eq!(nn.optimizer().name(), "adam");
+        let history = nn.fit(&data, &labels, 5, 10).unwrap();
+        println!("loss curve: {:?}", history);
+        assert!(history[9] < history[0]);
+    }
+}
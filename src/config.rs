@@ -0,0 +1,1035 @@
+use crate::activation::Activation;
+use crate::builder::{BuildError, NetworkBuilder};
+use crate::init::Initializer;
+use crate::loss::Loss;
+use crate::nn::NeuralNetwork;
+use crate::optimizer::{AdaGrad, Adam, AdamW, Momentum, Nesterov, RMSProp, Sgd};
+use crate::scalar::Scalar;
+use crate::schedule::{CosineWarmRestarts, ExponentialDecay, OneCycle, ReduceOnPlateau, StepDecay};
+use crate::trainer::{EarlyStopping, LearningRateScheduler, Monitor, ProgressLogger, Trainer};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use serde_path_to_error::Segment;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    // neither .json nor .toml
+    UnknownFormat(PathBuf),
+    // malformed JSON or TOML, the message carries the line and column
+    Parse(String),
+    // well-formed but wrong, entry is a path into the file such as
+    // "layers[2]" or "optimizer.lr"
+    Invalid { entry: String, message: String },
+}
+
+impl ConfigError {
+    fn invalid<E: fmt::Display>(entry: &str, message: E) -> ConfigError {
+        ConfigError::Invalid {
+            entry: entry.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "io error: {}", err),
+            ConfigError::UnknownFormat(path) => {
+                write!(f, "{} is neither a .json nor a .toml file", path.display())
+            }
+            ConfigError::Parse(message) => write!(f, "parse error: {}", message),
+            ConfigError::Invalid { entry, message } => write!(f, "{}: {}", entry, message),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> ConfigError {
+        ConfigError::Io(err)
+    }
+}
+
+// the builder numbers layers in file order, so its index is the entry
+impl From<BuildError> for ConfigError {
+    fn from(err: BuildError) -> ConfigError {
+        let layer = match &err {
+            BuildError::Empty => return ConfigError::invalid("layers", err),
+            BuildError::InputTooLarge { .. } => return ConfigError::invalid("input", err),
+            BuildError::MissingInput { layer, .. }
+            | BuildError::ZeroSize { layer, .. }
+            | BuildError::SizeMismatch { layer, .. }
+            | BuildError::NotSpatial { layer, .. }
+            | BuildError::KernelTooLarge { layer, .. }
+            | BuildError::InvalidRate { layer, .. }
+            | BuildError::TooLarge { layer, .. } => *layer,
+        };
+        ConfigError::invalid(&format!("layers[{}]", layer), err)
+    }
+}
+
+// a flat size or a [channels, height, width] shape
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum InputConfig {
+    Size(usize),
+    Shape(Vec<usize>),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LayerConfig {
+    Dense {
+        units: usize,
+        #[serde(default = "default_bias")]
+        bias: bool,
+    },
+    Sigmoid,
+    Tanh,
+    Relu,
+    LeakyRelu {
+        alpha: f64,
+    },
+    Elu {
+        alpha: f64,
+    },
+    Gelu,
+    Softplus,
+    Identity,
+    Softmax,
+    Dropout {
+        rate: f64,
+    },
+    BatchNorm,
+    LayerNorm,
+    Conv2d {
+        out_channels: usize,
+        kernel: (usize, usize),
+        #[serde(default = "default_stride")]
+        stride: usize,
+        #[serde(default)]
+        padding: usize,
+    },
+    Reshape {
+        shape: Vec<usize>,
+    },
+}
+
+fn default_bias() -> bool {
+    true
+}
+
+fn default_stride() -> usize {
+    1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum OptimizerConfig {
+    Sgd {
+        lr: f64,
+    },
+    Momentum {
+        lr: f64,
+        #[serde(default = "default_momentum")]
+        momentum: f64,
+    },
+    Nesterov {
+        lr: f64,
+        #[serde(default = "default_momentum")]
+        momentum: f64,
+    },
+    #[serde(rename = "adagrad")]
+    AdaGrad {
+        lr: f64,
+        epsilon: Option<f64>,
+    },
+    #[serde(rename = "rmsprop")]
+    RmsProp {
+        lr: f64,
+        #[serde(default = "default_rho")]
+        rho: f64,
+        epsilon: Option<f64>,
+    },
+    Adam {
+        lr: f64,
+        #[serde(default = "default_beta1")]
+        beta1: f64,
+        #[serde(default = "default_beta2")]
+        beta2: f64,
+        epsilon: Option<f64>,
+    },
+    #[serde(rename = "adamw")]
+    AdamW {
+        lr: f64,
+        #[serde(default = "default_beta1")]
+        beta1: f64,
+        #[serde(default = "default_beta2")]
+        beta2: f64,
+        weight_decay: f64,
+        epsilon: Option<f64>,
+    },
+}
+
+fn default_momentum() -> f64 {
+    0.9
+}
+
+fn default_rho() -> f64 {
+    0.9
+}
+
+fn default_beta1() -> f64 {
+    0.9
+}
+
+fn default_beta2() -> f64 {
+    0.999
+}
+
+impl OptimizerConfig {
+    pub fn lr(&self) -> f64 {
+        match *self {
+            OptimizerConfig::Sgd { lr }
+            | OptimizerConfig::Momentum { lr, .. }
+            | OptimizerConfig::Nesterov { lr, .. }
+            | OptimizerConfig::AdaGrad { lr, .. }
+            | OptimizerConfig::RmsProp { lr, .. }
+            | OptimizerConfig::Adam { lr, .. }
+            | OptimizerConfig::AdamW { lr, .. } => lr,
+        }
+    }
+
+    fn epsilon(&self) -> Option<f64> {
+        match *self {
+            OptimizerConfig::AdaGrad { epsilon, .. }
+            | OptimizerConfig::RmsProp { epsilon, .. }
+            | OptimizerConfig::Adam { epsilon, .. }
+            | OptimizerConfig::AdamW { epsilon, .. } => epsilon,
+            _ => None,
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        check(self.lr() > 0.0, "optimizer.lr", "must be positive")?;
+        if let Some(epsilon) = self.epsilon() {
+            check(epsilon > 0.0, "optimizer.epsilon", "must be positive")?;
+        }
+        match *self {
+            OptimizerConfig::Momentum { momentum, .. }
+            | OptimizerConfig::Nesterov { momentum, .. } => {
+                check(unit(momentum), "optimizer.momentum", "must be in [0, 1)")
+            }
+            OptimizerConfig::RmsProp { rho, .. } => {
+                check(unit(rho), "optimizer.rho", "must be in [0, 1)")
+            }
+            OptimizerConfig::Adam { beta1, beta2, .. } => {
+                check(unit(beta1), "optimizer.beta1", "must be in [0, 1)")?;
+                check(unit(beta2), "optimizer.beta2", "must be in [0, 1)")
+            }
+            OptimizerConfig::AdamW {
+                beta1,
+                beta2,
+                weight_decay,
+                ..
+            } => {
+                check(unit(beta1), "optimizer.beta1", "must be in [0, 1)")?;
+                check(unit(beta2), "optimizer.beta2", "must be in [0, 1)")?;
+                check(
+                    weight_decay >= 0.0,
+                    "optimizer.weight_decay",
+                    "must be non-negative",
+                )
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn apply<T: Scalar>(&self, builder: NetworkBuilder<T>) -> NetworkBuilder<T> {
+        match *self {
+            OptimizerConfig::Sgd { lr } => builder.optimizer(Sgd::new(lr)),
+            OptimizerConfig::Momentum { lr, momentum } => {
+                builder.optimizer(Momentum::new(lr, momentum))
+            }
+            OptimizerConfig::Nesterov { lr, momentum } => {
+                builder.optimizer(Nesterov::new(lr, momentum))
+            }
+            OptimizerConfig::AdaGrad { lr, epsilon } => {
+                let optimizer = AdaGrad::new(lr);
+                match epsilon {
+                    Some(epsilon) => builder.optimizer(optimizer.with_epsilon(epsilon)),
+                    None => builder.optimizer(optimizer),
+                }
+            }
+            OptimizerConfig::RmsProp { lr, rho, epsilon } => {
+                let optimizer = RMSProp::new(lr, rho);
+                match epsilon {
+                    Some(epsilon) => builder.optimizer(optimizer.with_epsilon(epsilon)),
+                    None => builder.optimizer(optimizer),
+                }
+            }
+            OptimizerConfig::Adam {
+                lr,
+                beta1,
+                beta2,
+                epsilon,
+            } => {
+                let optimizer = Adam::new(lr, beta1, beta2);
+                match epsilon {
+                    Some(epsilon) => builder.optimizer(optimizer.with_epsilon(epsilon)),
+                    None => builder.optimizer(optimizer),
+                }
+            }
+            OptimizerConfig::AdamW {
+                lr,
+                beta1,
+                beta2,
+                weight_decay,
+                epsilon,
+            } => {
+                let optimizer = AdamW::new(lr, beta1, beta2, weight_decay);
+                match epsilon {
+                    Some(epsilon) => builder.optimizer(optimizer.with_epsilon(epsilon)),
+                    None => builder.optimizer(optimizer),
+                }
+            }
+        }
+    }
+}
+
+// Learning rate schedules start from the optimizer's lr.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ScheduleConfig {
+    Step {
+        step_size: usize,
+        gamma: f64,
+    },
+    Exponential {
+        gamma: f64,
+    },
+    Cosine {
+        #[serde(default)]
+        min_lr: f64,
+        period: usize,
+        #[serde(default = "default_mult")]
+        mult: usize,
+    },
+    OneCycle {
+        total_steps: usize,
+    },
+    Plateau {
+        factor: f64,
+        patience: usize,
+        #[serde(default)]
+        min_lr: f64,
+        #[serde(default = "default_monitor")]
+        monitor: Monitor,
+    },
+}
+
+fn default_mult() -> usize {
+    1
+}
+
+fn default_monitor() -> Monitor {
+    Monitor::ValLoss
+}
+
+impl ScheduleConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let entry = "training.schedule";
+        match *self {
+            ScheduleConfig::Step { step_size, gamma } => {
+                check(step_size > 0, entry, "step_size must be positive")?;
+                check(gamma > 0.0, entry, "gamma must be positive")
+            }
+            ScheduleConfig::Exponential { gamma } => {
+                check(gamma > 0.0, entry, "gamma must be positive")
+            }
+            ScheduleConfig::Cosine { period, mult, .. } => {
+                check(period > 0, entry, "period must be positive")?;
+                check(mult > 0, entry, "mult must be positive")
+            }
+            ScheduleConfig::OneCycle { total_steps } => {
+                check(total_steps >= 2, entry, "total_steps must be at least 2")
+            }
+            ScheduleConfig::Plateau { factor, .. } => check(
+                factor > 0.0 && factor < 1.0,
+                entry,
+                "factor must be in (0, 1)",
+            ),
+        }
+    }
+
+    fn scheduler(&self, lr: f64) -> LearningRateScheduler {
+        match *self {
+            ScheduleConfig::Step { step_size, gamma } => {
+                LearningRateScheduler::new(StepDecay::new(lr, step_size, gamma))
+            }
+            ScheduleConfig::Exponential { gamma } => {
+                LearningRateScheduler::new(ExponentialDecay::new(lr, gamma))
+            }
+            ScheduleConfig::Cosine {
+                min_lr,
+                period,
+                mult,
+            } => LearningRateScheduler::new(
+                CosineWarmRestarts::new(lr, min_lr, period).with_mult(mult),
+            ),
+            ScheduleConfig::OneCycle { total_steps } => {
+                LearningRateScheduler::new(OneCycle::new(lr, total_steps))
+            }
+            ScheduleConfig::Plateau {
+                factor,
+                patience,
+                min_lr,
+                monitor,
+            } => LearningRateScheduler::new(
+                ReduceOnPlateau::new(lr, factor, patience).with_min_lr(min_lr),
+            )
+            .with_monitor(monitor),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EarlyStoppingConfig {
+    #[serde(default = "default_monitor")]
+    pub monitor: Monitor,
+    pub patience: usize,
+    #[serde(default)]
+    pub min_delta: f64,
+    #[serde(default)]
+    pub restore_best: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrainingConfig {
+    pub epochs: usize,
+    pub batch_size: usize,
+    // seed of the per-epoch shuffle, None keeps the dataset order
+    pub shuffle: Option<u64>,
+    // print the stats of every n-th epoch
+    pub log_every: Option<usize>,
+    pub early_stopping: Option<EarlyStoppingConfig>,
+    pub schedule: Option<ScheduleConfig>,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> TrainingConfig {
+        TrainingConfig {
+            epochs: 10,
+            batch_size: 1,
+            shuffle: None,
+            log_every: None,
+            early_stopping: None,
+            schedule: None,
+        }
+    }
+}
+
+// A network and its training run described in JSON or TOML, e.g.
+//   input = 784
+//   seed = 42
+//   initializer = "he_normal"
+//   layers = [{ type = "dense", units = 128 }, { type = "relu" }, { type = "dense", units = 10 }, { type = "softmax" }]
+//   optimizer = { type = "adam", lr = 0.001 }
+//   training = { epochs = 20, batch_size = 32, shuffle = 7 }
+// Only input and layers are required, the rest falls back to the builder's
+// defaults. Every error names the entry it was found in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub input: InputConfig,
+    pub seed: Option<u64>,
+    pub initializer: Option<Initializer>,
+    pub layers: Vec<LayerConfig>,
+    pub loss: Option<Loss>,
+    pub optimizer: Option<OptimizerConfig>,
+    pub training: TrainingConfig,
+}
+
+impl Config {
+    pub fn from_json(text: &str) -> Result<Config, ConfigError> {
+        let value: Value =
+            serde_json::from_str(text).map_err(|err| ConfigError::Parse(err.to_string()))?;
+        Config::from_value(value)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Config, ConfigError> {
+        let value: toml::Value =
+            toml::from_str(text).map_err(|err| ConfigError::Parse(err.to_string()))?;
+        let value =
+            serde_json::to_value(value).map_err(|err| ConfigError::Parse(err.to_string()))?;
+        Config::from_value(value)
+    }
+
+    // the format follows the file extension
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        match extension {
+            Some("json") => Config::from_json(&fs::read_to_string(path)?),
+            Some("toml") => Config::from_toml(&fs::read_to_string(path)?),
+            _ => Err(ConfigError::UnknownFormat(path.to_path_buf())),
+        }
+    }
+
+    // Every top-level entry and every layer is decoded on its own so that
+    // errors can say where they come from.
+    fn from_value(value: Value) -> Result<Config, ConfigError> {
+        let mut object = match value {
+            Value::Object(object) => object,
+            _ => {
+                return Err(ConfigError::invalid(
+                    "config",
+                    "expected a table of entries",
+                ))
+            }
+        };
+        let input =
+            take(&mut object, "input")?.ok_or_else(|| ConfigError::invalid("input", "missing"))?;
+        let layers = match object.remove("layers") {
+            Some(Value::Array(layers)) => layers
+                .into_iter()
+                .enumerate()
+                .map(|(i, layer)| decode(layer, &format!("layers[{}]", i)))
+                .collect::<Result<Vec<LayerConfig>, ConfigError>>()?,
+            Some(_) => return Err(ConfigError::invalid("layers", "expected a list of layers")),
+            None => return Err(ConfigError::invalid("layers", "missing")),
+        };
+        let config = Config {
+            input,
+            seed: take(&mut object, "seed")?,
+            initializer: take(&mut object, "initializer")?,
+            layers,
+            loss: take(&mut object, "loss")?,
+            optimizer: take(&mut object, "optimizer")?,
+            training: take(&mut object, "training")?.unwrap_or_default(),
+        };
+        if let Some(key) = object.keys().next() {
+            return Err(ConfigError::invalid(key, "unknown entry"));
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    // value checks that the file format cannot express, the layer shapes are
+    // checked when the network is built
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check(!self.layers.is_empty(), "layers", "is empty")?;
+        if let Some(optimizer) = &self.optimizer {
+            optimizer.validate()?;
+        }
+        let training = &self.training;
+        check(
+            training.batch_size > 0,
+            "training.batch_size",
+            "must be positive",
+        )?;
+        if let Some(every) = training.log_every {
+            check(every > 0, "training.log_every", "must be positive")?;
+        }
+        if let Some(schedule) = &training.schedule {
+            schedule.validate()?;
+            check(
+                self.optimizer.is_some(),
+                "training.schedule",
+                "needs an optimizer to take the learning rate from",
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn builder<T: Scalar>(&self) -> NetworkBuilder<T> {
+        let mut builder = match &self.input {
+            InputConfig::Size(size) => NeuralNetwork::builder().input(*size),
+            InputConfig::Shape(shape) => NeuralNetwork::builder().input_shape(shape.clone()),
+        };
+        if let Some(seed) = self.seed {
+            builder = builder.seed(seed);
+        }
+        if let Some(initializer) = self.initializer {
+            builder = builder.initializer(initializer);
+        }
+        if let Some(loss) = self.loss {
+            builder = builder.loss(loss);
+        }
+        if let Some(optimizer) = &self.optimizer {
+            builder = optimizer.apply(builder);
+        }
+        for layer in &self.layers {
+            builder = match layer {
+                LayerConfig::Dense { units, bias: true } => builder.dense(*units),
+                LayerConfig::Dense { units, bias: false } => builder.dense_without_bias(*units),
+                LayerConfig::Sigmoid => builder.sigmoid(),
+                LayerConfig::Tanh => builder.tanh(),
+                LayerConfig::Relu => builder.relu(),
+                LayerConfig::LeakyRelu { alpha } => builder.leaky_relu(*alpha),
+                LayerConfig::Elu { alpha } => builder.activation(Activation::Elu(*alpha)),
+                LayerConfig::Gelu => builder.activation(Activation::Gelu),
+                LayerConfig::Softplus => builder.activation(Activation::Softplus),
+                LayerConfig::Identity => builder.activation(Activation::Identity),
+                LayerConfig::Softmax => builder.softmax(),
+                LayerConfig::Dropout { rate } => builder.dropout(*rate),
+                LayerConfig::BatchNorm => builder.batch_norm(),
+                LayerConfig::LayerNorm => builder.layer_norm(),
+                LayerConfig::Conv2d {
+                    out_channels,
+                    kernel,
+                    stride,
+                    padding,
+                } => builder.conv2d(*out_channels, *kernel, *stride, *padding),
+                LayerConfig::Reshape { shape } => builder.reshape(shape.clone()),
+            };
+        }
+        builder
+    }
+
+    pub fn build<T: Scalar>(&self) -> Result<NeuralNetwork<T>, ConfigError> {
+        self.validate()?;
+        Ok(self.builder().build()?)
+    }
+
+    pub fn trainer<T: Scalar>(&self) -> Result<Trainer<T>, ConfigError> {
+        self.validate()?;
+        let training = &self.training;
+        let mut trainer = Trainer::new(training.epochs, training.batch_size);
+        if let Some(seed) = training.shuffle {
+            trainer = trainer.with_shuffle(seed);
+        }
+        if let (Some(schedule), Some(optimizer)) = (&training.schedule, &self.optimizer) {
+            trainer = trainer.with_callback(schedule.scheduler(optimizer.lr()));
+        }
+        if let Some(early) = &training.early_stopping {
+            trainer = trainer.with_callback(
+                EarlyStopping::new(early.monitor, early.patience)
+                    .with_min_delta(early.min_delta)
+                    .with_restore_best(early.restore_best),
+            );
+        }
+        if let Some(every) = training.log_every {
+            trainer = trainer.with_callback(ProgressLogger::new(every));
+        }
+        Ok(trainer)
+    }
+}
+
+fn check(ok: bool, entry: &str, message: &str) -> Result<(), ConfigError> {
+    match ok {
+        true => Ok(()),
+        false => Err(ConfigError::invalid(entry, message)),
+    }
+}
+
+// in [0, 1)
+fn unit(value: f64) -> bool {
+    (0.0..1.0).contains(&value)
+}
+
+// errors inside a nested table name the key that failed, e.g. training.epochs
+fn decode<D: DeserializeOwned>(value: Value, entry: &str) -> Result<D, ConfigError> {
+    let err = match serde_path_to_error::deserialize(value.clone()) {
+        Ok(decoded) => return Ok(decoded),
+        Err(err) => err,
+    };
+    let mut entry = entry.to_string();
+    let mut pointer = String::new();
+    for segment in err.path().iter() {
+        match segment {
+            Segment::Seq { index } => {
+                entry += &format!("[{}]", index);
+                pointer += &format!("/{}", index);
+            }
+            Segment::Map { key } => {
+                entry += &format!(".{}", key);
+                pointer += &format!("/{}", key.replace('~', "~0").replace('/', "~1"));
+            }
+            Segment::Enum { .. } | Segment::Unknown => {}
+        }
+    }
+    let message = err.into_inner().to_string();
+    if !message.starts_with("missing field") {
+        if let Some(key) = buffered_field::<D>(&value, &pointer, &message) {
+            entry += &format!(".{}", key);
+        }
+    }
+    Err(ConfigError::invalid(&entry, message))
+}
+
+// Tagged tables like the optimizer are read in full before their fields are
+// decoded, which loses the path. The field in error is the first one that fails
+// the same way when decoded with only the tag and the fields before it.
+fn buffered_field<D: DeserializeOwned>(
+    value: &Value,
+    pointer: &str,
+    message: &str,
+) -> Option<String> {
+    let table = value.pointer(pointer)?.as_object()?;
+    let mut prefix = Map::new();
+    if let Some(tag) = table.get("type") {
+        prefix.insert("type".to_string(), tag.clone());
+    }
+    for (key, field) in table.iter().filter(|(key, _)| *key != "type") {
+        prefix.insert(key.clone(), field.clone());
+        let mut value = value.clone();
+        *value.pointer_mut(pointer)? = Value::Object(prefix.clone());
+        if let Err(err) = serde_json::from_value::<D>(value) {
+            if err.to_string() == message {
+                return Some(key.clone());
+            }
+        }
+    }
+    None
+}
+
+fn take<D: DeserializeOwned>(
+    object: &mut Map<String, Value>,
+    key: &str,
+) -> Result<Option<D>, ConfigError> {
+    object
+        .remove(key)
+        .map(|value| decode(value, key))
+        .transpose()
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::{Config, ConfigError, InputConfig, LayerConfig, OptimizerConfig, ScheduleConfig};
+    use crate::dataset::{read_csv_by_path, Dataset};
+    use crate::init::Initializer;
+    use crate::layer::Dense;
+    use crate::loss::Loss;
+    use crate::nn::NeuralNetwork;
+    use crate::trainer::Monitor;
+
+    const JSON: &str = r#"{
+        "input": 4,
+        "seed": 3,
+        "initializer": "xavier_normal",
+        "layers": [
+            { "type": "dense", "units": 6 },
+            { "type": "leaky_relu", "alpha": 0.1 },
+            { "type": "dropout", "rate": 0.25 },
+            { "type": "dense", "units": 2, "bias": false },
+            { "type": "softmax" }
+        ],
+        "optimizer": { "type": "adam", "lr": 0.01 },
+        "training": {
+            "epochs": 5,
+            "batch_size": 8,
+            "shuffle": 1,
+            "early_stopping": { "monitor": "train_loss", "patience": 2 },
+            "schedule": { "type": "step", "step_size": 2, "gamma": 0.5 }
+        }
+    }"#;
+
+    const TOML: &str = r#"
+        input = 4
+        seed = 3
+        initializer = "xavier_normal"
+        optimizer = { type = "adam", lr = 0.01 }
+
+        [[layers]]
+        type = "dense"
+        units = 6
+
+        [[layers]]
+        type = "leaky_relu"
+        alpha = 0.1
+
+        [[layers]]
+        type = "dropout"
+        rate = 0.25
+
+        [[layers]]
+        type = "dense"
+        units = 2
+        bias = false
+
+        [[layers]]
+        type = "softmax"
+
+        [training]
+        epochs = 5
+        batch_size = 8
+        shuffle = 1
+        early_stopping = { monitor = "train_loss", patience = 2 }
+        schedule = { type = "step", step_size = 2, gamma = 0.5 }
+    "#;
+
+    fn entry(err: ConfigError) -> String {
+        println!("{}", err);
+        match err {
+            ConfigError::Invalid { entry, .. } => entry,
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let json = Config::from_json(JSON).unwrap();
+        let toml = Config::from_toml(TOML).unwrap();
+        println!("{:?}", json);
+        assert_eq!(json, toml);
+        assert_eq!(json.input, InputConfig::Size(4));
+        assert_eq!(json.initializer, Some(Initializer::XavierNormal));
+        assert_eq!(
+            json.layers[3],
+            LayerConfig::Dense {
+                units: 2,
+                bias: false
+            }
+        );
+        assert_eq!(
+            json.optimizer,
+            Some(OptimizerConfig::Adam {
+                lr: 0.01,
+                beta1: 0.9,
+                beta2: 0.999,
+                epsilon: None
+            })
+        );
+        assert_eq!(json.training.epochs, 5);
+        assert_eq!(
+            json.training.early_stopping.unwrap().monitor,
+            Monitor::TrainLoss
+        );
+        assert_eq!(
+            json.training.schedule,
+            Some(ScheduleConfig::Step {
+                step_size: 2,
+                gamma: 0.5
+            })
+        );
+
+        let nn: NeuralNetwork = json.build().unwrap();
+        nn.show();
+        let names: Vec<&str> = nn.layers().iter().map(|layer| layer.name()).collect();
+        assert_eq!(
+            names,
+            vec!["dense", "leaky_relu", "dropout", "dense", "softmax"]
+        );
+        assert!(!nn.layer::<Dense>(3).unwrap().has_bias());
+        assert_eq!(nn.loss(), Loss::CategoricalCrossEntropy);
+        assert_eq!(nn.lr(), 0.01);
+
+        // same seed, same weights
+        let other: NeuralNetwork = toml.build().unwrap();
+        for (a, b) in nn.layers().iter().zip(other.layers()) {
+            assert_eq!(a.parameters(), b.parameters());
+        }
+
+        let shaped = Config::from_json(
+            r#"{"input": [1, 4, 4], "loss": {"huber": 1.0}, "layers": [
+                {"type": "conv2d", "out_channels": 2, "kernel": [3, 3], "padding": 1},
+                {"type": "reshape", "shape": [32]},
+                {"type": "dense", "units": 1}
+            ]}"#,
+        )
+        .unwrap();
+        let nn: NeuralNetwork = shaped.build().unwrap();
+        assert_eq!(nn.loss(), Loss::Huber(1.0));
+        assert_eq!(shaped.training.batch_size, 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        // syntax errors keep the parser's position
+        match Config::from_json("{\"input\": 4,\n \"layers\": [}") {
+            Err(ConfigError::Parse(message)) => {
+                println!("{}", message);
+                assert!(message.contains("line 2"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            Config::from_toml("input = "),
+            Err(ConfigError::Parse(_))
+        ));
+
+        let layers = r#"[{"type": "dense", "units": 3}]"#;
+        let cases = vec![
+            (r#"{"layers": []}"#.to_string(), "input"),
+            (r#"{"input": 4}"#.to_string(), "layers"),
+            (r#"{"input": 4, "layers": []}"#.to_string(), "layers"),
+            (
+                r#"{"input": 4, "layers": [{"type": "relu"}, {"type": "swish"}]}"#.to_string(),
+                "layers[1].type",
+            ),
+            (
+                r#"{"input": 4, "layers": [{"type": "dense", "units": 3, "size": 2}]}"#.to_string(),
+                "layers[0].size",
+            ),
+            (
+                r#"{"input": 4, "layers": [{"type": "dense"}]}"#.to_string(),
+                "layers[0]",
+            ),
+            (
+                format!(r#"{{"input": 4, "layers": {}, "epochs": 3}}"#, layers),
+                "epochs",
+            ),
+            (
+                format!(r#"{{"input": 4, "layers": {}, "loss": "l2"}}"#, layers),
+                "loss",
+            ),
+            (
+                format!(
+                    r#"{{"input": 4, "layers": {}, "optimizer": {{"type": "sgd", "lr": -1}}}}"#,
+                    layers
+                ),
+                "optimizer.lr",
+            ),
+            (
+                format!(
+                    r#"{{"input": 4, "layers": {}, "optimizer": {{"type": "adam", "lr": 0.1, "beta1": 1.5}}}}"#,
+                    layers
+                ),
+                "optimizer.beta1",
+            ),
+            (
+                format!(
+                    r#"{{"input": 4, "layers": {}, "optimizer": {{"type": "adamw", "lr": 0.1, "weight_decay": -0.01}}}}"#,
+                    layers
+                ),
+                "optimizer.weight_decay",
+            ),
+            (
+                format!(
+                    r#"{{"input": 4, "layers": {}, "optimizer": {{"type": "rmsprop", "lr": 0.1, "epsilon": 0}}}}"#,
+                    layers
+                ),
+                "optimizer.epsilon",
+            ),
+            (
+                format!(
+                    r#"{{"input": 4, "layers": {}, "training": {{"batch_size": 0}}}}"#,
+                    layers
+                ),
+                "training.batch_size",
+            ),
+            // type errors inside a table point at the key
+            (
+                format!(
+                    r#"{{"input": 4, "layers": {}, "training": {{"epochs": "x"}}}}"#,
+                    layers
+                ),
+                "training.epochs",
+            ),
+            (
+                format!(
+                    r#"{{"input": 4, "layers": {}, "training": {{"early_stopping": {{"patience": -1}}}}}}"#,
+                    layers
+                ),
+                "training.early_stopping.patience",
+            ),
+            (
+                format!(
+                    r#"{{"input": 4, "layers": {}, "optimizer": {{"type": "adam", "lr": "x"}}}}"#,
+                    layers
+                ),
+                "optimizer.lr",
+            ),
+            (
+                r#"{"input": 4, "layers": [{"type": "dense", "units": "x"}]}"#.to_string(),
+                "layers[0].units",
+            ),
+            (
+                r#"{"input": 4, "layers": [{"type": "dense", "bias": false}]}"#.to_string(),
+                "layers[0]",
+            ),
+            (
+                format!(
+                    r#"{{"input": 4, "layers": {}, "training": {{"schedule": {{"type": "exponential", "gamma": "x"}}}}}}"#,
+                    layers
+                ),
+                "training.schedule.gamma",
+            ),
+            (r#"{"input": [4, "x"], "layers": []}"#.to_string(), "input"),
+            (
+                format!(
+                    r#"{{"input": 4, "layers": {}, "training": {{"schedule": {{"type": "exponential", "gamma": 0.9}}}}}}"#,
+                    layers
+                ),
+                "training.schedule",
+            ),
+        ];
+        for (text, expected) in cases {
+            let err = Config::from_json(&text).unwrap_err();
+            println!("{}", err);
+            assert_eq!(entry(err), expected);
+        }
+
+        // shape errors are found while building and point at the layer
+        let config = Config::from_json(
+            r#"{"input": 4, "layers": [
+                {"type": "dense", "units": 3},
+                {"type": "dropout", "rate": 1.5}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(entry(config.build::<f64>().unwrap_err()), "layers[1]");
+        let config = Config::from_toml(
+            "input = [1, 2, 2]\nlayers = [{ type = \"conv2d\", out_channels = 1, kernel = [3, 3] }]",
+        )
+        .unwrap();
+        assert_eq!(entry(config.build::<f64>().unwrap_err()), "layers[0]");
+
+        // sizes that overflow are rejected instead of panicking
+        let cases = vec![
+            (
+                r#"{"input": [4294967296, 4294967296, 2], "layers": [{"type": "relu"}]}"#,
+                "input",
+            ),
+            (
+                r#"{"input": 4, "layers": [
+                    {"type": "reshape", "shape": [4294967296, 4294967296, 2]}
+                ]}"#,
+                "layers[0]",
+            ),
+            (
+                r#"{"input": [1, 4, 4], "layers": [
+                    {"type": "conv2d", "out_channels": 1, "kernel": [3, 3], "padding": 9223372036854775808}
+                ]}"#,
+                "layers[0]",
+            ),
+        ];
+        for (text, expected) in cases {
+            let err = Config::from_json(text).unwrap().build::<f64>().unwrap_err();
+            println!("{}", err);
+            assert_eq!(entry(err), expected);
+        }
+
+        assert!(matches!(
+            Config::load("model.yaml"),
+            Err(ConfigError::UnknownFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_and_train() {
+        let config = Config::load("data/mnist_mlp.toml").unwrap();
+        let mut nn: NeuralNetwork = config.build().unwrap();
+        let mut trainer = config.trainer().unwrap();
+
+        let (label, data) = read_csv_by_path("data/mnist_train_100.csv").unwrap();
+        let train = Dataset::new(data, label).unwrap();
+        let (label, data) = read_csv_by_path("data/mnist_test_10.csv").unwrap();
+        let test = Dataset::new(data, label).unwrap();
+        let history = trainer.fit(&mut nn, &train, Some(&test)).unwrap();
+        println!("{:?}", history.train_loss());
+        assert_eq!(history.len(), config.training.epochs);
+        assert!(history.train_loss().last().unwrap() < &history.train_loss()[0]);
+    }
+}
@@ -0,0 +1,417 @@
+use crate::init::Initializer;
+use crate::layer::{check_input, Layer};
+use crate::matrix::{Matrix, MatrixError, MatrixOps};
+use crate::scalar::Scalar;
+use rand::Rng;
+use std::any::Any;
+
+// 2D convolution (cross-correlation) over samples stored as flattened
+// channel-major columns: channel c, row y, column x of a sample is value
+// (c * height + y) * width + x. The output uses the same layout with
+// `out_channels` channels. Every sample is unfolded with im2col so the
+// convolution is one matrix product with the out x (channels * kh * kw)
+// weights.
+#[derive(Debug, Clone)]
+pub struct Conv2d<T = f64> {
+    pub(crate) in_channels: usize,
+    pub(crate) input: (usize, usize),
+    pub(crate) out_channels: usize,
+    pub(crate) kernel: (usize, usize),
+    pub(crate) stride: usize,
+    pub(crate) padding: usize,
+    pub(crate) weights_matrix: Matrix<T>,
+    pub(crate) bias: Matrix<T>,
+    // unfolded samples of the last training forward pass
+    pub(crate) columns: Vec<Matrix<T>>,
+    pub(crate) gradients: Vec<Matrix<T>>,
+}
+
+impl<T: Scalar> Conv2d<T> {
+    // input is (height, width), kernel is (height, width), stride 1 and no
+    // padding, weights drawn from the default initializer
+    pub fn new(
+        in_channels: usize,
+        input: (usize, usize),
+        out_channels: usize,
+        kernel: (usize, usize),
+    ) -> Conv2d<T> {
+        Conv2d::new_by_init(
+            in_channels,
+            input,
+            out_channels,
+            kernel,
+            Initializer::default(),
+            &mut rand::thread_rng(),
+        )
+    }
+
+    pub fn new_by_init<R: Rng + ?Sized>(
+        in_channels: usize,
+        input: (usize, usize),
+        out_channels: usize,
+        kernel: (usize, usize),
+        initializer: Initializer,
+        rng: &mut R,
+    ) -> Conv2d<T> {
+        assert!(in_channels > 0 && out_channels > 0);
+        assert!(kernel.0 > 0 && kernel.1 > 0);
+        let conv = Conv2d {
+            in_channels,
+            input,
+            out_channels,
+            kernel,
+            stride: 1,
+            padding: 0,
+            weights_matrix: initializer.init(out_channels, in_channels * kernel.0 * kernel.1, rng),
+            bias: Matrix::zeros(out_channels, 1),
+            columns: Vec::new(),
+            gradients: Vec::new(),
+        };
+        conv.check_geometry();
+        conv
+    }
+
+    pub fn with_stride(mut self, stride: usize) -> Conv2d<T> {
+        assert!(stride > 0);
+        self.stride = stride;
+        self.check_geometry();
+        self
+    }
+
+    pub fn with_padding(mut self, padding: usize) -> Conv2d<T> {
+        self.padding = padding;
+        self.check_geometry();
+        self
+    }
+
+    pub fn with_weights(mut self, weights: Matrix<T>, bias: Matrix<T>) -> Conv2d<T> {
+        assert_eq!(weights.shape(), self.weights_matrix.shape());
+        assert_eq!(bias.shape(), self.bias.shape());
+        self.weights_matrix = weights;
+        self.bias = bias;
+        self
+    }
+
+    fn check_geometry(&self) {
+        let (height, width) = self.input;
+        assert!(
+            height + 2 * self.padding >= self.kernel.0 && width + 2 * self.padding >= self.kernel.1,
+            "kernel larger than the padded input"
+        );
+    }
+
+    pub fn in_channels(&self) -> usize {
+        self.in_channels
+    }
+
+    pub fn out_channels(&self) -> usize {
+        self.out_channels
+    }
+
+    pub fn input_shape(&self) -> (usize, usize) {
+        self.input
+    }
+
+    pub fn kernel(&self) -> (usize, usize) {
+        self.kernel
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn padding(&self) -> usize {
+        self.padding
+    }
+
+    pub fn weights(&self) -> &Matrix<T> {
+        &self.weights_matrix
+    }
+
+    pub fn bias(&self) -> &Matrix<T> {
+        &self.bias
+    }
+
+    // (height, width) of every output channel
+    pub fn output_shape(&self) -> (usize, usize) {
+        let (height, width) = self.input;
+        (
+            (height + 2 * self.padding - self.kernel.0) / self.stride + 1,
+            (width + 2 * self.padding - self.kernel.1) / self.stride + 1,
+        )
+    }
+
+    pub fn convert<U: Scalar>(&self) -> Conv2d<U> {
+        Conv2d {
+            in_channels: self.in_channels,
+            input: self.input,
+            out_channels: self.out_channels,
+            kernel: self.kernel,
+            stride: self.stride,
+            padding: self.padding,
+            weights_matrix: self.weights_matrix.convert(),
+            bias: self.bias.convert(),
+            columns: self.columns.iter().map(|col| col.convert()).collect(),
+            gradients: self.gradients.iter().map(|grad| grad.convert()).collect(),
+        }
+    }
+
+    // index in the sample of row `row` (channel and kernel offset) of the
+    // patch at output position `col`, None where it falls on the padding
+    fn source(&self, row: usize, col: usize) -> Option<usize> {
+        let (kh, kw) = self.kernel;
+        let (height, width) = self.input;
+        let (_, out_width) = self.output_shape();
+        let channel = row / (kh * kw);
+        let (ky, kx) = (row / kw % kh, row % kw);
+        let (oy, ox) = (col / out_width, col % out_width);
+        let y = (oy * self.stride + ky).checked_sub(self.padding)?;
+        let x = (ox * self.stride + kx).checked_sub(self.padding)?;
+        if y >= height || x >= width {
+            return None;
+        }
+        Some((channel * height + y) * width + x)
+    }
+
+    // (channels * kh * kw) x (output height * output width) patches of sample
+    // `sample` of the input
+    fn im2col(&self, input: &Matrix<T>, sample: usize) -> Matrix<T> {
+        let (out_height, out_width) = self.output_shape();
+        let rows = self.weights_matrix.cols;
+        let cols = out_height * out_width;
+        let mut res = Matrix::zeros(rows, cols);
+        for row in 0..rows {
+            for col in 0..cols {
+                if let Some(index) = self.source(row, col) {
+                    res.data[row * cols + col] = input[(index, sample)];
+                }
+            }
+        }
+        res
+    }
+
+    // adds the patches back onto sample `sample` of `res`, overlapping patches
+    // accumulate
+    fn col2im(&self, columns: &Matrix<T>, res: &mut Matrix<T>, sample: usize) {
+        for row in 0..columns.rows {
+            for col in 0..columns.cols {
+                if let Some(index) = self.source(row, col) {
+                    res[(index, sample)] += columns.data[row * columns.cols + col];
+                }
+            }
+        }
+    }
+
+    fn convolve(&self, columns: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        let mut res = self.weights_matrix.try_product(columns)?;
+        for (row, values) in res.data.chunks_mut(columns.cols).enumerate() {
+            for value in values.iter_mut() {
+                *value += self.bias.data[row];
+            }
+        }
+        Ok(res)
+    }
+
+    fn sample_size(&self) -> usize {
+        self.in_channels * self.input.0 * self.input.1
+    }
+
+    // output of one sample, out_channels x positions, written as column `sample`
+    fn write_sample(output: &mut Matrix<T>, sample: usize, values: &Matrix<T>) {
+        for (index, value) in values.data.iter().enumerate() {
+            output[(index, sample)] = *value;
+        }
+    }
+
+    // gradient of one sample of the output as out_channels x positions
+    fn read_sample(grad: &Matrix<T>, sample: usize, rows: usize, cols: usize) -> Matrix<T> {
+        let data = (0..rows * cols)
+            .map(|index| grad[(index, sample)])
+            .collect();
+        Matrix::from_vec(rows, cols, data)
+    }
+
+    fn parameter_gradients(&mut self, grad: &Matrix<T>) -> Result<Vec<Matrix<T>>, MatrixError> {
+        let (out_height, out_width) = self.output_shape();
+        let positions = out_height * out_width;
+        check_input(grad, self.out_channels * positions, "conv2d backward")?;
+        if grad.cols != self.columns.len() {
+            return Err(MatrixError::ShapeMismatch {
+                op: "conv2d backward",
+                left: (grad.rows, self.columns.len()),
+                right: grad.shape(),
+            });
+        }
+        let mut dweights = Matrix::zeros(self.weights_matrix.rows, self.weights_matrix.cols);
+        let mut dbias = Matrix::zeros(self.out_channels, 1);
+        let mut douts = Vec::with_capacity(grad.cols);
+        for (sample, columns) in self.columns.iter().enumerate() {
+            let dout = Self::read_sample(grad, sample, self.out_channels, positions);
+            dweights.try_add_assign(&dout.try_product_transposed(columns)?)?;
+            dbias.try_add_assign(&dout.sum_columns())?;
+            douts.push(dout);
+        }
+        self.gradients = vec![dweights, dbias];
+        Ok(douts)
+    }
+}
+
+impl<T: Scalar> Layer<T> for Conv2d<T> {
+    fn name(&self) -> &'static str {
+        "conv2d"
+    }
+
+    fn input_size(&self) -> Option<usize> {
+        Some(self.sample_size())
+    }
+
+    fn output_size(&self, _input_size: usize) -> usize {
+        let (height, width) = self.output_shape();
+        self.out_channels * height * width
+    }
+
+    fn forward(&self, input: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        check_input(input, self.sample_size(), "conv2d")?;
+        let mut output = Matrix::zeros(self.output_size(input.rows), input.cols);
+        for sample in 0..input.cols {
+            let values = self.convolve(&self.im2col(input, sample))?;
+            Self::write_sample(&mut output, sample, &values);
+        }
+        Ok(output)
+    }
+
+    fn forward_train(&mut self, input: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        check_input(input, self.sample_size(), "conv2d")?;
+        let mut output = Matrix::zeros(self.output_size(input.rows), input.cols);
+        self.columns.clear();
+        for sample in 0..input.cols {
+            let columns = self.im2col(input, sample);
+            let values = self.convolve(&columns)?;
+            Self::write_sample(&mut output, sample, &values);
+            self.columns.push(columns);
+        }
+        Ok(output)
+    }
+
+    fn backward(&mut self, grad: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        let douts = self.parameter_gradients(grad)?;
+        let transposed = self.weights_matrix.transpose();
+        let mut res = Matrix::zeros(self.sample_size(), grad.cols);
+        for (sample, dout) in douts.iter().enumerate() {
+            let dcolumns = transposed.try_product(dout)?;
+            self.col2im(&dcolumns, &mut res, sample);
+        }
+        Ok(res)
+    }
+
+    fn backward_parameters(&mut self, grad: &Matrix<T>) -> Result<(), MatrixError> {
+        self.parameter_gradients(grad).map(|_| ())
+    }
+
+    fn parameters(&self) -> Vec<&Matrix<T>> {
+        vec![&self.weights_matrix, &self.bias]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix<T>> {
+        vec![&mut self.weights_matrix, &mut self.bias]
+    }
+
+    fn gradients(&self) -> Vec<&Matrix<T>> {
+        self.gradients.iter().collect()
+    }
+
+    fn gradients_mut(&mut self) -> Vec<&mut Matrix<T>> {
+        self.gradients.iter_mut().collect()
+    }
+
+    fn show(&self) {
+        let (height, width) = self.output_shape();
+        println!(
+            "[Layer] conv2d: {}x{}x{} -> {}x{}x{}",
+            self.in_channels, self.input.0, self.input.1, self.out_channels, height, width
+        );
+        println!(
+            "[Layer] kernel: {}x{}, stride: {}, padding: {}",
+            self.kernel.0, self.kernel.1, self.stride, self.padding
+        );
+    }
+
+    fn box_clone(&self) -> Box<dyn Layer<T>> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod conv_tests {
+    use super::Conv2d;
+    use crate::init::{seeded_rng, Initializer};
+    use crate::layer::Layer;
+    use crate::matrix::{Matrix, MatrixOps};
+    use crate::test_util::{assert_gradient, loss_weights, numeric_gradient};
+
+    #[test]
+    fn test_forward() {
+        // one 3x3 channel, a 2x2 kernel summing the top row and subtracting
+        // the bottom row
+        let conv: Conv2d = Conv2d::new(1, (3, 3), 1, (2, 2)).with_weights(
+            Matrix::new(vec![vec![1.0, 1.0, -1.0, -1.0]]),
+            Matrix::new(vec![vec![0.5]]),
+        );
+        let input =
+            Matrix::new(vec![vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]]).transpose();
+        let output = conv.forward(&input).unwrap();
+        output.show();
+        assert_eq!(conv.output_shape(), (2, 2));
+        assert_eq!(output.as_slice(), &[-5.5, -5.5, -5.5, -5.5]);
+
+        // zero padding and stride 2 over the padded 5x5 input
+        let conv = conv.with_padding(1).with_stride(2);
+        assert_eq!(conv.output_shape(), (2, 2));
+        let output = conv.forward(&input).unwrap();
+        // top left window only sees the 1
+        assert_eq!(output[(0, 0)], -0.5);
+        assert_eq!(output[(3, 0)], 5.0 + 6.0 - 8.0 - 9.0 + 0.5);
+        assert!(conv.forward(&Matrix::ones(8, 1)).is_err());
+    }
+
+    fn loss(conv: &Conv2d, input: &Matrix) -> f64 {
+        let output = conv.forward(input).unwrap();
+        output.dot(&loss_weights(output.rows, output.cols))
+    }
+
+    #[test]
+    fn test_gradients() {
+        let mut rng = seeded_rng(3);
+        let mut conv: Conv2d =
+            Conv2d::new_by_init(2, (4, 5), 3, (3, 2), Initializer::default(), &mut rng)
+                .with_stride(2)
+                .with_padding(1);
+        conv.bias = Matrix::new(vec![vec![0.1], vec![-0.2], vec![0.3]]);
+        let input: Matrix = Initializer::default().init(40, 3, &mut rng);
+        let output = conv.forward_train(&input).unwrap();
+        assert_eq!(output.shape(), (3 * 2 * 3, 3));
+        let dinput = conv
+            .backward(&loss_weights(output.rows, output.cols))
+            .unwrap();
+        let numeric = numeric_gradient(&input, 1e-6, |input| loss(&conv, input));
+        assert_gradient("input", &numeric, &dinput, 1e-6);
+        let gradients = conv.gradients();
+        assert_eq!(gradients.len(), 2);
+        for (index, (param, gradient)) in conv.parameters().into_iter().zip(gradients).enumerate() {
+            let numeric = numeric_gradient(param, 1e-6, |param| {
+                let mut conv = conv.clone();
+                *conv.parameters_mut()[index] = param.clone();
+                loss(&conv, &input)
+            });
+            assert_gradient(&format!("parameter {}", index), &numeric, gradient, 1e-6);
+        }
+    }
+}
@@ -1,41 +1,634 @@
-use crate::matrix::{Matrix, MatrixOps};
+use crate::matrix::{Matrix, MatrixError, MatrixOps};
+use crate::scalar::Scalar;
+use rand::seq::SliceRandom;
+use rand::Rng;
 use std::error::Error;
+use std::fmt;
+use std::path::Path;
 
-pub fn read_csv_by_path(file_path: &str) -> Result<(Vec<Matrix>, Vec<Matrix>), Box<dyn Error>> {
-    let mut rdr = csv::Reader::from_path(file_path)?;
-    let mut label_matrix_vec = Vec::new();
-    let mut data_matrix_vec = Vec::new();
+// (labels, data), one row vector per sample
+pub type LabeledData<T = f64> = (Vec<Matrix<T>>, Vec<Matrix<T>>);
 
+// MNIST csv: label in column 0, 784 pixel columns, no header row
+pub fn read_csv_by_path<T: Scalar>(file_path: &str) -> Result<LabeledData<T>, Box<dyn Error>> {
+    Ok(read_csv(file_path, &CsvOptions::default())?)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    // a single integer label column, one-hot encoded into this many classes
+    Classes(usize),
+    // the label columns are copied as real valued targets
+    Regression,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Normalization {
+    None,
+    // x / 255 * 0.99 + 0.01, the MNIST pixel scaling
+    Pixel,
+    // linear map of [min, max] onto [0, 1]
+    Range(f64, f64),
+    // Range with the minimum and maximum of the column in the loaded file
+    MinMax,
+    // zero mean and unit variance over the loaded file
+    ZScore,
+}
+
+// Schema of a csv dataset. Columns are 0-based indices into each record,
+// every column that is not a label column is a feature.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvOptions {
+    pub(crate) label_columns: Vec<usize>,
+    pub(crate) target: Target,
+    pub(crate) has_header: bool,
+    pub(crate) delimiter: u8,
+    pub(crate) normalization: Normalization,
+    pub(crate) column_normalization: Vec<(usize, Normalization)>,
+}
+
+impl Default for CsvOptions {
+    // the bundled MNIST files
+    fn default() -> CsvOptions {
+        CsvOptions {
+            label_columns: vec![0],
+            target: Target::Classes(10),
+            has_header: false,
+            delimiter: b',',
+            normalization: Normalization::Pixel,
+            column_normalization: Vec::new(),
+        }
+    }
+}
+
+impl CsvOptions {
+    pub fn new() -> CsvOptions {
+        CsvOptions::default()
+    }
+
+    pub fn with_label_columns(mut self, columns: Vec<usize>) -> CsvOptions {
+        self.label_columns = columns;
+        self
+    }
+
+    pub fn with_target(mut self, target: Target) -> CsvOptions {
+        self.target = target;
+        self
+    }
+
+    pub fn with_header(mut self, has_header: bool) -> CsvOptions {
+        self.has_header = has_header;
+        self
+    }
+
+    pub fn with_delimiter(mut self, delimiter: u8) -> CsvOptions {
+        self.delimiter = delimiter;
+        self
+    }
+
+    // normalization of every feature column without its own setting
+    pub fn with_normalization(mut self, normalization: Normalization) -> CsvOptions {
+        self.normalization = normalization;
+        self
+    }
+
+    pub fn with_column_normalization(
+        mut self,
+        column: usize,
+        normalization: Normalization,
+    ) -> CsvOptions {
+        self.column_normalization.retain(|(c, _)| *c != column);
+        self.column_normalization.push((column, normalization));
+        self
+    }
+
+    fn normalization_of(&self, column: usize) -> Normalization {
+        self.column_normalization
+            .iter()
+            .find(|(c, _)| *c == column)
+            .map(|(_, n)| *n)
+            .unwrap_or(self.normalization)
+    }
+}
+
+// Lines are 1-based line numbers of the file (a header counts as line 1),
+// columns are the 0-based indices used in CsvOptions.
+#[derive(Debug)]
+pub enum CsvError {
+    Csv(csv::Error),
+    InvalidOptions(String),
+    Empty,
+    RaggedRow {
+        line: u64,
+        expected: usize,
+        found: usize,
+    },
+    MissingColumn {
+        line: u64,
+        column: usize,
+        found: usize,
+    },
+    InvalidNumber {
+        line: u64,
+        column: usize,
+        value: String,
+    },
+    InvalidLabel {
+        line: u64,
+        column: usize,
+        value: String,
+        classes: usize,
+    },
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CsvError::Csv(err) => write!(f, "csv error: {}", err),
+            CsvError::InvalidOptions(msg) => write!(f, "invalid csv options: {}", msg),
+            CsvError::Empty => write!(f, "csv file has no data rows"),
+            CsvError::RaggedRow {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: expected {} columns but found {}",
+                line, expected, found
+            ),
+            CsvError::MissingColumn {
+                line,
+                column,
+                found,
+            } => write!(
+                f,
+                "line {}: column {} is missing, the row has {} columns",
+                line, column, found
+            ),
+            CsvError::InvalidNumber {
+                line,
+                column,
+                value,
+            } => write!(
+                f,
+                "line {}, column {}: cannot parse {:?} as a number",
+                line, column, value
+            ),
+            CsvError::InvalidLabel {
+                line,
+                column,
+                value,
+                classes,
+            } => write!(
+                f,
+                "line {}, column {}: {:?} is not a class label in 0..{}",
+                line, column, value, classes
+            ),
+        }
+    }
+}
+
+impl Error for CsvError {}
+
+impl From<csv::Error> for CsvError {
+    fn from(err: csv::Error) -> CsvError {
+        CsvError::Csv(err)
+    }
+}
+
+fn check_options(options: &CsvOptions) -> Result<(), CsvError> {
+    let labels = &options.label_columns;
+    if labels.is_empty() {
+        return Err(CsvError::InvalidOptions("no label columns".to_string()));
+    }
+    if (1..labels.len()).any(|i| labels[..i].contains(&labels[i])) {
+        return Err(CsvError::InvalidOptions(
+            "duplicate label column".to_string(),
+        ));
+    }
+    match options.target {
+        Target::Classes(0) => Err(CsvError::InvalidOptions(
+            "classification needs at least one class".to_string(),
+        )),
+        Target::Classes(_) if labels.len() != 1 => Err(CsvError::InvalidOptions(
+            "classification needs exactly one label column".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Reads a csv dataset as (labels, data) with one row vector per sample.
+// MinMax and ZScore statistics are taken from this file only, constant
+// columns are left at zero instead of dividing by zero.
+pub fn read_csv<T: Scalar, P: AsRef<Path>>(
+    path: P,
+    options: &CsvOptions,
+) -> Result<LabeledData<T>, CsvError> {
+    check_options(options)?;
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(options.has_header)
+        .delimiter(options.delimiter)
+        .flexible(true)
+        .from_path(path)?;
+
+    let mut labels = Vec::new();
+    let mut features: Vec<Vec<f64>> = Vec::new();
+    let mut feature_columns = Vec::new();
+    let mut width = 0;
     for result in rdr.records() {
         let record = result?;
+        let line = record.position().map_or(0, |p| p.line());
+        if features.is_empty() {
+            width = record.len();
+            if let Some(&column) = options.label_columns.iter().find(|&&c| c >= width) {
+                return Err(CsvError::MissingColumn {
+                    line,
+                    column,
+                    found: width,
+                });
+            }
+            feature_columns = (0..width)
+                .filter(|c| !options.label_columns.contains(c))
+                .collect();
+        } else if record.len() != width {
+            return Err(CsvError::RaggedRow {
+                line,
+                expected: width,
+                found: record.len(),
+            });
+        }
+
+        let cell = |column: usize| record.get(column).unwrap().trim();
+        let number = |column: usize| {
+            cell(column)
+                .parse::<f64>()
+                .map_err(|_| CsvError::InvalidNumber {
+                    line,
+                    column,
+                    value: cell(column).to_string(),
+                })
+        };
+        labels.push(match options.target {
+            Target::Classes(classes) => {
+                let column = options.label_columns[0];
+                match cell(column).parse::<usize>() {
+                    Ok(label) if label < classes => one_hot(label, classes),
+                    _ => {
+                        return Err(CsvError::InvalidLabel {
+                            line,
+                            column,
+                            value: cell(column).to_string(),
+                            classes,
+                        })
+                    }
+                }
+            }
+            Target::Regression => {
+                let mut row = Vec::new();
+                for &column in options.label_columns.iter() {
+                    row.push(T::from_f64(number(column)?));
+                }
+                Matrix::from_vec(1, row.len(), row)
+            }
+        });
+        let mut row = Vec::with_capacity(feature_columns.len());
+        for &column in feature_columns.iter() {
+            row.push(number(column)?);
+        }
+        features.push(row);
+    }
+    if features.is_empty() {
+        return Err(CsvError::Empty);
+    }
+
+    for (i, &column) in feature_columns.iter().enumerate() {
+        let values = features.iter().map(|row| row[i]);
+        let (scale, offset) = match options.normalization_of(column) {
+            Normalization::None => (1.0, 0.0),
+            Normalization::Pixel => {
+                for row in features.iter_mut() {
+                    row[i] = scale_pixel(row[i]);
+                }
+                continue;
+            }
+            Normalization::Range(min, max) => range_transform(min, max),
+            Normalization::MinMax => {
+                let min = values.clone().fold(f64::INFINITY, f64::min);
+                let max = values.fold(f64::NEG_INFINITY, f64::max);
+                range_transform(min, max)
+            }
+            Normalization::ZScore => {
+                let n = features.len() as f64;
+                let mean = values.clone().sum::<f64>() / n;
+                let std = (values.map(|x| (x - mean) * (x - mean)).sum::<f64>() / n).sqrt();
+                if std == 0.0 {
+                    (0.0, 0.0)
+                } else {
+                    (1.0 / std, -mean / std)
+                }
+            }
+        };
+        for row in features.iter_mut() {
+            row[i] = row[i] * scale + offset;
+        }
+    }
+    let data = features
+        .into_iter()
+        .map(|row| Matrix::from_vec(1, row.len(), row.into_iter().map(T::from_f64).collect()))
+        .collect();
+    Ok((labels, data))
+}
+
+// x * scale + offset mapping [min, max] onto [0, 1]
+fn range_transform(min: f64, max: f64) -> (f64, f64) {
+    if max == min {
+        (0.0, 0.0)
+    } else {
+        (1.0 / (max - min), -min / (max - min))
+    }
+}
+
+// 1 x classes row vector with 0.99 at the label and 0.01 elsewhere
+pub(crate) fn one_hot<T: Scalar>(label: usize, classes: usize) -> Matrix<T> {
+    let mut row = vec![T::from_f64(0.01); classes];
+    row[label] = T::from_f64(0.99);
+    Matrix::from_vec(1, classes, row)
+}
+
+// maps a 0..=255 pixel into [0.01, 1.0] so that no input is exactly zero
+pub(crate) fn scale_pixel<T: Scalar>(pixel: f64) -> T {
+    T::from_f64(pixel / 255.0 * 0.99 + 0.01)
+}
+
+// Inputs paired with their labels, both stored as one row vector per sample
+// like read_csv_by_path returns them. The order only changes through
+// shuffle, so a seeded rng gives the same batches on every run.
+#[derive(Debug, Clone)]
+pub struct Dataset<T = f64> {
+    pub(crate) inputs: Vec<Matrix<T>>,
+    pub(crate) labels: Vec<Matrix<T>>,
+}
+
+impl<T: Scalar> Dataset<T> {
+    // every input and every label must have the shape of the first one
+    pub fn new(inputs: Vec<Matrix<T>>, labels: Vec<Matrix<T>>) -> Result<Dataset<T>, MatrixError> {
+        if inputs.len() != labels.len() {
+            return Err(MatrixError::ShapeMismatch {
+                op: "dataset",
+                left: (inputs.len(), 1),
+                right: (labels.len(), 1),
+            });
+        }
+        for samples in [&inputs, &labels].iter() {
+            if let Some(first) = samples.first() {
+                for sample in samples.iter() {
+                    if sample.shape() != first.shape() {
+                        return Err(MatrixError::ShapeMismatch {
+                            op: "dataset",
+                            left: first.shape(),
+                            right: sample.shape(),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(Dataset { inputs, labels })
+    }
+
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    pub fn inputs(&self) -> &[Matrix<T>] {
+        &self.inputs
+    }
+
+    pub fn labels(&self) -> &[Matrix<T>] {
+        &self.labels
+    }
+
+    pub fn get(&self, index: usize) -> (&Matrix<T>, &Matrix<T>) {
+        (&self.inputs[index], &self.labels[index])
+    }
+
+    // class of a sample is the position of the largest label value
+    pub fn class(&self, index: usize) -> usize {
+        let label = self.labels[index].as_slice();
+        let mut best = 0;
+        for (i, value) in label.iter().enumerate() {
+            if *value > label[best] {
+                best = i;
+            }
+        }
+        best
+    }
+
+    fn subset(&self, indices: &[usize]) -> Dataset<T> {
+        Dataset {
+            inputs: indices.iter().map(|&i| self.inputs[i].clone()).collect(),
+            labels: indices.iter().map(|&i| self.labels[i].clone()).collect(),
+        }
+    }
+
+    // permutes the samples, call once per epoch with the same rng for a
+    // reproducible order
+    pub fn shuffle<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        let mut order: Vec<usize> = (0..self.len()).collect();
+        order.shuffle(rng);
+        *self = self.subset(&order);
+    }
+
+    // column-stacked (inputs, labels) batches in the current order, the last
+    // one is smaller when the batch size does not divide the length
+    pub fn batches(&self, batch_size: usize) -> Batches<'_, T> {
+        assert!(batch_size > 0);
+        Batches {
+            dataset: self,
+            batch_size,
+            start: 0,
+        }
+    }
+
+    // random (train, validation, test) split with the given fractions
+    pub fn split<R: Rng + ?Sized>(
+        &self,
+        validation: f64,
+        test: f64,
+        rng: &mut R,
+    ) -> (Dataset<T>, Dataset<T>, Dataset<T>) {
+        check_fractions(validation, test);
+        let mut order: Vec<usize> = (0..self.len()).collect();
+        order.shuffle(rng);
+        let (train, val, test) = split_indices(&order, validation, test);
+        (self.subset(&train), self.subset(&val), self.subset(&test))
+    }
+
+    // like split, but every class keeps its share in each part
+    pub fn split_stratified<R: Rng + ?Sized>(
+        &self,
+        validation: f64,
+        test: f64,
+        rng: &mut R,
+    ) -> (Dataset<T>, Dataset<T>, Dataset<T>) {
+        check_fractions(validation, test);
+        let mut parts = (Vec::new(), Vec::new(), Vec::new());
+        for mut class in self.class_indices() {
+            class.shuffle(rng);
+            let (train, val, test) = split_indices(&class, validation, test);
+            parts.0.extend(train);
+            parts.1.extend(val);
+            parts.2.extend(test);
+        }
+        // mix the classes again so batches are not sorted by class
+        for part in [&mut parts.0, &mut parts.1, &mut parts.2].iter_mut() {
+            part.shuffle(rng);
+        }
+        (
+            self.subset(&parts.0),
+            self.subset(&parts.1),
+            self.subset(&parts.2),
+        )
+    }
+
+    // k consecutive folds of the current order, shuffle first for random folds
+    pub fn k_fold(&self, k: usize) -> KFold<'_, T> {
+        assert!(k >= 2 && k <= self.len());
+        let folds = (0..self.len()).map(|i| i * k / self.len()).collect();
+        KFold {
+            dataset: self,
+            folds,
+            k,
+            fold: 0,
+        }
+    }
+
+    // k folds with every class spread evenly over them
+    pub fn stratified_k_fold<R: Rng + ?Sized>(&self, k: usize, rng: &mut R) -> KFold<'_, T> {
+        assert!(k >= 2 && k <= self.len());
+        let mut folds = vec![0; self.len()];
+        let mut next = 0;
+        for mut class in self.class_indices() {
+            class.shuffle(rng);
+            for i in class {
+                folds[i] = next % k;
+                next += 1;
+            }
+        }
+        KFold {
+            dataset: self,
+            folds,
+            k,
+            fold: 0,
+        }
+    }
+
+    // sample indices grouped by class in ascending class order
+    fn class_indices(&self) -> Vec<Vec<usize>> {
+        let mut classes: Vec<Vec<usize>> = Vec::new();
+        for i in 0..self.len() {
+            let class = self.class(i);
+            if classes.len() <= class {
+                classes.resize(class + 1, Vec::new());
+            }
+            classes[class].push(i);
+        }
+        classes
+    }
+}
 
-        let mut label_vec = vec![0.01, 0.01, 0.01, 0.01, 0.01, 0.01, 0.01, 0.01, 0.01, 0.01];
-        let label: usize = record.get(0).unwrap().to_string().parse().unwrap();
-        label_vec[label] = 0.99;
-        label_matrix_vec.push(Matrix::new(vec![label_vec]));
+fn check_fractions(validation: f64, test: f64) {
+    assert!(validation >= 0.0 && test >= 0.0 && validation + test <= 1.0);
+}
+
+// the first round(test * n) indices go to test, the next round(validation * n)
+// to validation and the rest to train
+fn split_indices(
+    order: &[usize],
+    validation: f64,
+    test: f64,
+) -> (Vec<usize>, Vec<usize>, Vec<usize>) {
+    let n = order.len() as f64;
+    let test_end = ((test * n).round() as usize).min(order.len());
+    let val_end = (test_end + (validation * n).round() as usize).min(order.len());
+    (
+        order[val_end..].to_vec(),
+        order[test_end..val_end].to_vec(),
+        order[..test_end].to_vec(),
+    )
+}
 
-        let mut data_vec = Vec::new();
-        for i in 1..record.len() {
-            let data: f64 = record.get(i).unwrap().to_string().parse().unwrap();
-            data_vec.push(data / 255.0 * 0.99 + 0.01)
+impl<T: Scalar> From<LabeledData<T>> for Dataset<T> {
+    // panics if the loaded samples do not all have the same shape
+    fn from((labels, inputs): LabeledData<T>) -> Dataset<T> {
+        match Dataset::new(inputs, labels) {
+            Ok(dataset) => dataset,
+            Err(err) => panic!("{}", err),
         }
-        data_matrix_vec.push(Matrix::new(vec![data_vec]))
     }
-    Ok((label_matrix_vec, data_matrix_vec))
 }
 
-pub fn show_result(predict: Matrix, label: Matrix) {
+pub struct Batches<'a, T> {
+    dataset: &'a Dataset<T>,
+    batch_size: usize,
+    start: usize,
+}
+
+impl<'a, T: Scalar> Iterator for Batches<'a, T> {
+    // features x batch inputs and outputs x batch labels
+    type Item = (Matrix<T>, Matrix<T>);
+
+    fn next(&mut self) -> Option<(Matrix<T>, Matrix<T>)> {
+        if self.start >= self.dataset.len() {
+            return None;
+        }
+        let end = (self.start + self.batch_size).min(self.dataset.len());
+        let range = self.start..end;
+        self.start = end;
+        Some((
+            Matrix::vstack(&self.dataset.inputs[range.clone()]).transpose(),
+            Matrix::vstack(&self.dataset.labels[range]).transpose(),
+        ))
+    }
+}
+
+// yields (train, validation) pairs, fold i is the validation set of step i
+pub struct KFold<'a, T> {
+    dataset: &'a Dataset<T>,
+    folds: Vec<usize>,
+    k: usize,
+    fold: usize,
+}
+
+impl<'a, T: Scalar> Iterator for KFold<'a, T> {
+    type Item = (Dataset<T>, Dataset<T>);
+
+    fn next(&mut self) -> Option<(Dataset<T>, Dataset<T>)> {
+        if self.fold >= self.k {
+            return None;
+        }
+        let (val, train): (Vec<usize>, Vec<usize>) =
+            (0..self.folds.len()).partition(|&i| self.folds[i] == self.fold);
+        self.fold += 1;
+        Some((self.dataset.subset(&train), self.dataset.subset(&val)))
+    }
+}
+
+pub fn show_result<T: Scalar>(predict: Matrix<T>, label: Matrix<T>) {
     let mut predict_ans = 0;
-    let mut max = predict.data[0][0];
-    for i in 1..predict.data[0].len() {
-        if max < predict.data[0][i] {
+    let mut max = predict.get(0, 0);
+    for i in 1..predict.cols {
+        if max < predict.get(0, i) {
             predict_ans = i;
-            max = predict.data[0][i];
+            max = predict.get(0, i);
         }
     }
     let mut label_ans = 0;
     for i in 0..10 {
-        if label.data[0][i] == 0.99 {
+        if label.get(0, i) == T::from_f64(0.99) {
             label_ans = i;
         }
     }
@@ -43,17 +636,312 @@ pub fn show_result(predict: Matrix, label: Matrix) {
 }
 #[cfg(test)]
 mod dataset_test {
-    use super::read_csv_by_path;
-    use crate::matrix::MatrixOps;
+    use super::{
+        one_hot, read_csv, read_csv_by_path, CsvError, CsvOptions, Dataset, Normalization, Target,
+    };
+    use crate::init::seeded_rng;
+    use crate::matrix::{Matrix, MatrixError, MatrixOps};
+    use crate::test_util::temp_file;
 
     #[test]
     fn test_read_csv_by_path() {
         println!("********[TEST] Test Dataset Read CSV By Path Function********");
-        let (label, data) = read_csv_by_path("data/mnist_test_10.csv").unwrap();
+        let (label, data) = read_csv_by_path::<f64>("data/mnist_test_10.csv").unwrap();
         for i in 0..label.len() {
             label[i].show();
             data[i].show();
         }
+        // the file has no header, the first line is a sample
+        assert_eq!(label.len(), 10);
+        assert_eq!(label[0].get(0, 7), 0.99);
+        assert_eq!(data[0].shape(), (1, 784));
         println!("********************************");
     }
+
+    fn read(
+        name: &str,
+        contents: &str,
+        options: &CsvOptions,
+    ) -> Result<(Vec<Matrix>, Vec<Matrix>), CsvError> {
+        let path = temp_file(&format!("{}.csv", name), contents.as_bytes());
+        let result = read_csv(&path, options);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_read_csv_options() {
+        let contents = "x;y;class\n1;10;2\n2;20;0\n3;40;1\n";
+        let options = CsvOptions::new()
+            .with_header(true)
+            .with_delimiter(b';')
+            .with_label_columns(vec![2])
+            .with_target(Target::Classes(3))
+            .with_normalization(Normalization::None);
+        let (labels, data) = read("options", contents, &options).unwrap();
+        assert_eq!(labels.len(), 3);
+        assert_eq!(labels[0].as_slice(), &[0.01, 0.01, 0.99][..]);
+        assert_eq!(labels[1].as_slice(), &[0.99, 0.01, 0.01][..]);
+        assert_eq!(data[2].as_slice(), &[3.0, 40.0][..]);
+
+        let options = options
+            .with_normalization(Normalization::MinMax)
+            .with_column_normalization(1, Normalization::ZScore);
+        let (_, data) = read("minmax", contents, &options).unwrap();
+        let x: Vec<f64> = data.iter().map(|row| row.get(0, 0)).collect();
+        assert_eq!(x, vec![0.0, 0.5, 1.0]);
+        let y: Vec<f64> = data.iter().map(|row| row.get(0, 1)).collect();
+        let mean = y.iter().sum::<f64>() / 3.0;
+        let var = y.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / 3.0;
+        assert!(mean.abs() < 1e-12);
+        assert!((var - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_read_csv_regression() {
+        // two targets in the middle, one constant feature column
+        let contents = "0.5, 1.5, -2, 7\n1.0, 2.5, 4, 7\n";
+        let options = CsvOptions::new()
+            .with_label_columns(vec![1, 2])
+            .with_target(Target::Regression)
+            .with_normalization(Normalization::Range(0.0, 2.0))
+            .with_column_normalization(3, Normalization::MinMax);
+        let (labels, data) = read("regression", contents, &options).unwrap();
+        assert_eq!(labels[0].as_slice(), &[1.5, -2.0][..]);
+        assert_eq!(labels[1].as_slice(), &[2.5, 4.0][..]);
+        assert_eq!(data[0].as_slice(), &[0.25, 0.0][..]);
+        assert_eq!(data[1].as_slice(), &[0.5, 0.0][..]);
+    }
+
+    #[test]
+    fn test_read_csv_errors() {
+        let options = CsvOptions::new().with_target(Target::Classes(3));
+        let cases = [
+            (
+                "bad_number",
+                "1,2,3\n0,x,5\n",
+                "line 2, column 1: cannot parse \"x\" as a number",
+            ),
+            (
+                "empty_cell",
+                "1,2,3\n0,,5\n",
+                "line 2, column 1: cannot parse \"\" as a number",
+            ),
+            (
+                "bad_label",
+                "1,2,3\n3,4,5\n",
+                "line 2, column 0: \"3\" is not a class label in 0..3",
+            ),
+            (
+                "float_label",
+                "1.5,2,3\n",
+                "line 1, column 0: \"1.5\" is not a class label in 0..3",
+            ),
+            (
+                "ragged",
+                "1,2,3\n0,1\n",
+                "line 2: expected 3 columns but found 2",
+            ),
+            ("empty", "", "csv file has no data rows"),
+        ];
+        for (name, contents, message) in cases {
+            let err = read(name, contents, &options).unwrap_err();
+            println!("{}", err);
+            assert_eq!(err.to_string(), message);
+        }
+
+        let header = CsvOptions::new()
+            .with_header(true)
+            .with_target(Target::Classes(3));
+        let err = read("header_line", "a,b\n1,2\n1,b\n", &header).unwrap_err();
+        assert!(matches!(
+            err,
+            CsvError::InvalidNumber {
+                line: 3,
+                column: 1,
+                ..
+            }
+        ));
+
+        let missing = CsvOptions::new()
+            .with_label_columns(vec![4])
+            .with_target(Target::Regression);
+        let err = read("missing", "1,2,3\n", &missing).unwrap_err();
+        assert!(matches!(
+            err,
+            CsvError::MissingColumn {
+                line: 1,
+                column: 4,
+                found: 3
+            }
+        ));
+
+        let invalid = [
+            CsvOptions::new().with_label_columns(vec![]),
+            CsvOptions::new().with_label_columns(vec![0, 1]),
+            CsvOptions::new().with_target(Target::Classes(0)),
+            CsvOptions::new()
+                .with_label_columns(vec![1, 1])
+                .with_target(Target::Regression),
+        ];
+        for options in invalid.iter() {
+            let err = read("invalid", "0,1,2\n", options).unwrap_err();
+            println!("{}", err);
+            assert!(matches!(err, CsvError::InvalidOptions(_)));
+        }
+
+        let err = read_csv::<f64, _>("data/missing.csv", &CsvOptions::new()).unwrap_err();
+        assert!(matches!(err, CsvError::Csv(_)));
+    }
+
+    // sample i has input [i, class] and a one-hot label, the classes have
+    // 30, 20 and 10 samples
+    fn synthetic() -> Dataset {
+        let classes: Vec<usize> = (0..60)
+            .map(|i| {
+                if i < 30 {
+                    0
+                } else if i < 50 {
+                    1
+                } else {
+                    2
+                }
+            })
+            .collect();
+        let inputs = classes
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| Matrix::new(vec![vec![i as f64, c as f64]]))
+            .collect();
+        let labels = classes.iter().map(|&c| one_hot(c, 3)).collect();
+        Dataset::new(inputs, labels).unwrap()
+    }
+
+    fn ids(dataset: &Dataset) -> Vec<usize> {
+        (0..dataset.len())
+            .map(|i| {
+                let (input, _) = dataset.get(i);
+                // inputs and labels must still belong together
+                assert_eq!(input.get(0, 1) as usize, dataset.class(i));
+                input.get(0, 0) as usize
+            })
+            .collect()
+    }
+
+    fn class_counts(dataset: &Dataset) -> Vec<usize> {
+        let mut counts = vec![0; 3];
+        for i in 0..dataset.len() {
+            counts[dataset.class(i)] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn test_dataset_new() {
+        let a: Matrix = Matrix::zeros(1, 3);
+        let b: Matrix = Matrix::zeros(1, 2);
+        let err = Dataset::new(vec![a.clone(), b], vec![a.clone(), a.clone()]).unwrap_err();
+        assert_eq!(
+            err,
+            MatrixError::ShapeMismatch {
+                op: "dataset",
+                left: (1, 3),
+                right: (1, 2),
+            }
+        );
+        assert!(Dataset::new(vec![a.clone()], vec![]).is_err());
+        assert!(Dataset::<f64>::new(vec![], vec![]).unwrap().is_empty());
+
+        let dataset: Dataset = read_csv_by_path("data/mnist_test_10.csv").unwrap().into();
+        assert_eq!(dataset.len(), 10);
+        assert_eq!(dataset.class(0), 7);
+    }
+
+    #[test]
+    fn test_shuffle_is_seeded() {
+        let mut a = synthetic();
+        let mut b = synthetic();
+        let mut rng_a = seeded_rng(5);
+        let mut rng_b = seeded_rng(5);
+        a.shuffle(&mut rng_a);
+        b.shuffle(&mut rng_b);
+        let first = ids(&a);
+        assert_eq!(first, ids(&b));
+        assert_ne!(first, (0..60).collect::<Vec<_>>());
+        // the next epoch gets a different order, again the same for both
+        a.shuffle(&mut rng_a);
+        b.shuffle(&mut rng_b);
+        assert_eq!(ids(&a), ids(&b));
+        assert_ne!(ids(&a), first);
+        let mut sorted = ids(&a);
+        sorted.sort();
+        assert_eq!(sorted, (0..60).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_batches() {
+        let dataset = synthetic();
+        let batches: Vec<(Matrix, Matrix)> = dataset.batches(25).collect();
+        assert_eq!(batches.len(), 3);
+        assert_eq!(batches[0].0.shape(), (2, 25));
+        assert_eq!(batches[0].1.shape(), (3, 25));
+        assert_eq!(batches[2].0.shape(), (2, 10));
+        // column j of batch b is sample 25 * b + j
+        assert_eq!(batches[1].0.get(0, 3), 28.0);
+        assert_eq!(batches[2].1.get(2, 9), 0.99);
+        assert_eq!(
+            batches[0].0,
+            Matrix::vstack(&dataset.inputs()[..25]).transpose()
+        );
+    }
+
+    #[test]
+    fn test_split() {
+        let dataset = synthetic();
+        let (train, val, test) = dataset.split(0.2, 0.1, &mut seeded_rng(1));
+        assert_eq!((train.len(), val.len(), test.len()), (42, 12, 6));
+        let mut all: Vec<usize> = [ids(&train), ids(&val), ids(&test)].concat();
+        all.sort();
+        assert_eq!(all, (0..60).collect::<Vec<_>>());
+        let (_, again, _) = dataset.split(0.2, 0.1, &mut seeded_rng(1));
+        assert_eq!(ids(&val), ids(&again));
+
+        let (train, val, test) = dataset.split_stratified(0.2, 0.1, &mut seeded_rng(1));
+        assert_eq!(class_counts(&train), vec![21, 14, 7]);
+        assert_eq!(class_counts(&val), vec![6, 4, 2]);
+        assert_eq!(class_counts(&test), vec![3, 2, 1]);
+        // the classes are mixed again
+        assert_ne!(ids(&train), {
+            let mut sorted = ids(&train);
+            sorted.sort();
+            sorted
+        });
+
+        let (train, val, test) = dataset.split(0.0, 0.0, &mut seeded_rng(1));
+        assert_eq!((train.len(), val.len(), test.len()), (60, 0, 0));
+    }
+
+    #[test]
+    fn test_k_fold() {
+        let dataset = synthetic();
+        let mut seen = Vec::new();
+        for (train, val) in dataset.k_fold(4) {
+            assert_eq!(train.len() + val.len(), 60);
+            assert_eq!(val.len(), 15);
+            seen.extend(ids(&val));
+        }
+        assert_eq!(seen, (0..60).collect::<Vec<_>>());
+
+        let mut seen = Vec::new();
+        let folds: Vec<(Dataset, Dataset)> =
+            dataset.stratified_k_fold(5, &mut seeded_rng(2)).collect();
+        assert_eq!(folds.len(), 5);
+        for (train, val) in folds.iter() {
+            assert_eq!(class_counts(val), vec![6, 4, 2]);
+            assert_eq!(train.len(), 48);
+            seen.extend(ids(val));
+        }
+        seen.sort();
+        assert_eq!(seen, (0..60).collect::<Vec<_>>());
+    }
 }
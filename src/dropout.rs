@@ -0,0 +1,152 @@
+use crate::init::seeded_rng;
+use crate::layer::Layer;
+use crate::matrix::{Matrix, MatrixError, MatrixOps};
+use crate::scalar::Scalar;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::any::Any;
+
+// Inverted dropout: while training every activation is zeroed with
+// probability `rate` and the survivors are scaled by 1 / (1 - rate), so
+// inference is the identity. The mask of the last training forward pass is
+// kept for the backward pass.
+#[derive(Debug, Clone)]
+pub struct Dropout<T = f64> {
+    pub(crate) rate: f64,
+    pub(crate) rng: StdRng,
+    pub(crate) mask: Option<Matrix<T>>,
+}
+
+impl<T: Scalar> Dropout<T> {
+    pub fn new(rate: f64) -> Dropout<T> {
+        assert!((0.0..1.0).contains(&rate));
+        Dropout {
+            rate,
+            rng: StdRng::from_entropy(),
+            mask: None,
+        }
+    }
+
+    // the masks only depend on the seed and the number of forward passes
+    pub fn with_seed(mut self, seed: u64) -> Dropout<T> {
+        self.rng = seeded_rng(seed);
+        self
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    pub fn mask(&self) -> Option<&Matrix<T>> {
+        self.mask.as_ref()
+    }
+
+    pub fn convert<U: Scalar>(&self) -> Dropout<U> {
+        Dropout {
+            rate: self.rate,
+            rng: self.rng.clone(),
+            mask: self.mask.as_ref().map(|mask| mask.convert()),
+        }
+    }
+}
+
+impl<T: Scalar> Layer<T> for Dropout<T> {
+    fn name(&self) -> &'static str {
+        "dropout"
+    }
+
+    fn input_size(&self) -> Option<usize> {
+        None
+    }
+
+    fn output_size(&self, input_size: usize) -> usize {
+        input_size
+    }
+
+    fn forward(&self, input: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        Ok(input.clone())
+    }
+
+    fn forward_train(&mut self, input: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        if self.rate == 0.0 {
+            self.mask = None;
+            return Ok(input.clone());
+        }
+        let scale = T::from_f64(1.0 / (1.0 - self.rate));
+        let mut mask = Matrix::zeros(input.rows, input.cols);
+        for value in mask.data.iter_mut() {
+            if self.rng.gen::<f64>() >= self.rate {
+                *value = scale;
+            }
+        }
+        let output = input.mul(&mask);
+        self.mask = Some(mask);
+        Ok(output)
+    }
+
+    fn backward(&mut self, grad: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        match &self.mask {
+            Some(mask) => grad.try_mul(mask),
+            None => Ok(grad.clone()),
+        }
+    }
+
+    fn show(&self) {
+        println!("[Layer] dropout: {}", self.rate);
+    }
+
+    fn box_clone(&self) -> Box<dyn Layer<T>> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod dropout_tests {
+    use super::Dropout;
+    use crate::layer::Layer;
+    use crate::matrix::{Matrix, MatrixOps};
+
+    #[test]
+    fn test_inference_is_identity() {
+        let input: Matrix = Matrix::new(vec![vec![0.5, -1.0], vec![2.0, 0.25]]);
+        let dropout = Dropout::new(0.5).with_seed(1);
+        assert_eq!(dropout.forward(&input).unwrap(), input);
+        assert!(dropout.mask().is_none());
+    }
+
+    #[test]
+    fn test_train_mask() {
+        let input: Matrix = Matrix::ones(100, 100);
+        let mut dropout = Dropout::new(0.2).with_seed(7);
+        let output = dropout.forward_train(&input).unwrap();
+        let dropped = output.as_slice().iter().filter(|v| **v == 0.0).count();
+        println!("dropped {} of 10000", dropped);
+        assert!((1800..2200).contains(&dropped));
+        assert!(output
+            .as_slice()
+            .iter()
+            .all(|v| *v == 0.0 || (*v - 1.25).abs() < 1e-12));
+        // the expected activation is unchanged
+        let mean = output.as_slice().iter().sum::<f64>() / 10000.0;
+        assert!((mean - 1.0).abs() < 0.05);
+
+        // the backward pass uses the cached mask
+        let grad = Matrix::ones(100, 100).mul_const(2.0);
+        let back = dropout.backward(&grad).unwrap();
+        assert_eq!(back, output.mul_const(2.0));
+
+        // same seed, same masks
+        let mut other = Dropout::new(0.2).with_seed(7);
+        assert_eq!(other.forward_train(&input).unwrap(), output);
+        assert_ne!(other.forward_train(&input).unwrap(), output);
+        assert_eq!(Dropout::new(0.0).forward_train(&input).unwrap(), input);
+    }
+}
@@ -0,0 +1,208 @@
+use crate::matrix::Matrix;
+use crate::scalar::Scalar;
+
+// General matrix multiply kernels behind MatrixOps::product.
+//
+// `blocked` tiles the i-k-j loop so a KC x NC panel of B stays in cache while
+// every row of A streams over it. With the `parallel` feature, bands of MC
+// output rows are computed on separate threads. Each output element still
+// accumulates its k terms in ascending order, so the result is bit-identical
+// to `naive` regardless of blocking or threading.
+
+// rows of the output computed per task
+const MC: usize = 64;
+// depth of a B panel
+const KC: usize = 128;
+// width of a B panel
+const NC: usize = 256;
+// outputs narrower than this use plain dot products
+const NARROW: usize = 4;
+// below this many multiply-adds threads cost more than they save
+#[cfg(feature = "parallel")]
+const PARALLEL_THRESHOLD: usize = 1 << 18;
+
+// reference triple loop, kept for testing and benchmarks
+pub fn naive<T: Scalar>(a: &Matrix<T>, b: &Matrix<T>) -> Matrix<T> {
+    assert_eq!(a.cols, b.rows);
+    let mut data = vec![T::zero(); a.rows * b.cols];
+    for row in 0..a.rows {
+        for col in 0..b.cols {
+            let mut res = T::zero();
+            for k in 0..a.cols {
+                res += a.data[row * a.cols + k] * b.data[k * b.cols + col];
+            }
+            data[row * b.cols + col] = res;
+        }
+    }
+    Matrix::from_vec(a.rows, b.cols, data)
+}
+
+pub fn blocked<T: Scalar>(a: &Matrix<T>, b: &Matrix<T>) -> Matrix<T> {
+    assert_eq!(a.cols, b.rows);
+    let mut data = vec![T::zero(); a.rows * b.cols];
+    let band = MC * b.cols;
+
+    #[cfg(feature = "parallel")]
+    {
+        if a.rows * a.cols * b.cols >= PARALLEL_THRESHOLD && a.rows > MC {
+            use rayon::prelude::*;
+            data.par_chunks_mut(band)
+                .enumerate()
+                .for_each(|(i, out)| blocked_band(a, b, i * MC, out));
+            return Matrix::from_vec(a.rows, b.cols, data);
+        }
+    }
+
+    for (i, out) in data.chunks_mut(band).enumerate() {
+        blocked_band(a, b, i * MC, out);
+    }
+    Matrix::from_vec(a.rows, b.cols, data)
+}
+
+// computes output rows row_start.. into `out`, which holds whole rows
+fn blocked_band<T: Scalar>(a: &Matrix<T>, b: &Matrix<T>, row_start: usize, out: &mut [T]) {
+    let n = b.cols;
+    let rows = out.len() / n;
+    if n < NARROW {
+        // too few columns to amortize the tiling, e.g. a single input vector
+        for i in 0..rows {
+            let a_row = a.row(row_start + i);
+            for j in 0..n {
+                let mut res = T::zero();
+                for (k, a_ik) in a_row.iter().enumerate() {
+                    res += *a_ik * b.data[k * n + j];
+                }
+                out[i * n + j] = res;
+            }
+        }
+        return;
+    }
+    for jj in (0..n).step_by(NC) {
+        let j_end = (jj + NC).min(n);
+        for kk in (0..a.cols).step_by(KC) {
+            let k_end = (kk + KC).min(a.cols);
+            for i in 0..rows {
+                let a_row = &a.data[(row_start + i) * a.cols..(row_start + i + 1) * a.cols];
+                let out_row = &mut out[i * n + jj..i * n + j_end];
+                for (k, a_ik) in a_row.iter().enumerate().take(k_end).skip(kk) {
+                    let b_row = &b.data[k * n + jj..k * n + j_end];
+                    for (res, b_kj) in out_row.iter_mut().zip(b_row.iter()) {
+                        *res += *a_ik * *b_kj;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// a * b^T where `b_t` is already stored transposed, so both operands are read
+// along contiguous rows. Four partial sums are kept per dot product, so results
+// differ from `naive` by rounding only.
+pub fn product_transposed<T: Scalar>(a: &Matrix<T>, b_t: &Matrix<T>) -> Matrix<T> {
+    assert_eq!(a.cols, b_t.cols);
+    let mut data = vec![T::zero(); a.rows * b_t.rows];
+    let band = MC * b_t.rows;
+
+    #[cfg(feature = "parallel")]
+    {
+        if a.rows * a.cols * b_t.rows >= PARALLEL_THRESHOLD && a.rows > MC {
+            use rayon::prelude::*;
+            data.par_chunks_mut(band)
+                .enumerate()
+                .for_each(|(i, out)| transposed_band(a, b_t, i * MC, out));
+            return Matrix::from_vec(a.rows, b_t.rows, data);
+        }
+    }
+
+    for (i, out) in data.chunks_mut(band).enumerate() {
+        transposed_band(a, b_t, i * MC, out);
+    }
+    Matrix::from_vec(a.rows, b_t.rows, data)
+}
+
+fn transposed_band<T: Scalar>(a: &Matrix<T>, b_t: &Matrix<T>, row_start: usize, out: &mut [T]) {
+    let n = b_t.rows;
+    for (i, out_row) in out.chunks_mut(n).enumerate() {
+        let a_row = a.row(row_start + i);
+        for (j, res) in out_row.iter_mut().enumerate() {
+            *res = dot(a_row, b_t.row(j));
+        }
+    }
+}
+
+fn dot<T: Scalar>(x: &[T], y: &[T]) -> T {
+    let mut acc = [T::zero(); 4];
+    let chunks = x.len() / 4;
+    for c in 0..chunks {
+        for (lane, sum) in acc.iter_mut().enumerate() {
+            *sum += x[c * 4 + lane] * y[c * 4 + lane];
+        }
+    }
+    let mut res = (acc[0] + acc[1]) + (acc[2] + acc[3]);
+    for k in chunks * 4..x.len() {
+        res += x[k] * y[k];
+    }
+    res
+}
+
+#[cfg(test)]
+mod gemm_tests {
+    use super::{blocked, naive, product_transposed};
+    use crate::matrix::{Matrix, MatrixOps};
+
+    fn max_abs_diff(a: &Matrix, b: &Matrix) -> f64 {
+        assert_eq!(a.rows, b.rows);
+        assert_eq!(a.cols, b.cols);
+        let mut max: f64 = 0.0;
+        for (x, y) in a.data.iter().zip(b.data.iter()) {
+            max = max.max((x - y).abs());
+        }
+        max
+    }
+
+    const SHAPES: [(usize, usize, usize); 7] = [
+        (1, 1, 1),
+        (3, 5, 2),
+        (100, 784, 1),
+        (10, 100, 1),
+        (100, 784, 32),
+        (67, 300, 259),
+        (130, 129, 70),
+    ];
+
+    #[test]
+    fn test_blocked_matches_naive() {
+        for &(m, k, n) in SHAPES.iter() {
+            let a: Matrix = Matrix::new_by_rand(m, k);
+            let b = Matrix::new_by_rand(k, n);
+            let expected = naive(&a, &b);
+            assert_eq!(blocked(&a, &b).data, expected.data);
+            assert_eq!(a.product(&b).data, expected.data);
+        }
+    }
+
+    #[test]
+    fn test_transposed_matches_naive() {
+        for &(m, k, n) in SHAPES.iter() {
+            let a: Matrix = Matrix::new_by_rand(m, k);
+            let b = Matrix::new_by_rand(k, n);
+            let expected = naive(&a, &b);
+            let res = product_transposed(&a, &b.transpose());
+            assert_eq!(res.rows, m);
+            assert_eq!(res.cols, n);
+            // entries are sums of k terms of magnitude <= 0.25
+            assert!(max_abs_diff(&res, &expected) <= 1e-15 * k as f64);
+            let res = a.product_transposed(&b.transpose());
+            assert!(max_abs_diff(&res, &expected) <= 1e-15 * k as f64);
+        }
+    }
+
+    #[test]
+    fn test_known_product() {
+        let a = Matrix::new(vec![vec![1.0, 2.0], vec![3.0, 4.0], vec![5.0, 6.0]]);
+        let b = Matrix::new(vec![vec![1.0, 0.0, -1.0], vec![2.0, 1.0, 0.5]]);
+        let expected = vec![5.0, 2.0, 0.0, 11.0, 4.0, -1.0, 17.0, 6.0, -2.0];
+        assert_eq!(blocked(&a, &b).data, expected);
+        assert_eq!(product_transposed(&a, &b.transpose()).data, expected);
+    }
+}
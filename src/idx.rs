@@ -0,0 +1,419 @@
+use crate::dataset::{one_hot, scale_pixel, LabeledData};
+use crate::matrix::Matrix;
+use crate::scalar::Scalar;
+use flate2::read::GzDecoder;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+// IDX layout (all integers big-endian):
+//   0x00 0x00 | type u8 | dimension count u8 | dimension count * u32 | data
+// Only the unsigned byte type (0x08) used by MNIST is supported. Images are
+// IDX3 (count x rows x cols), labels IDX1 (count). Files starting with the
+// gzip magic 0x1f 0x8b are decompressed on the fly.
+pub const UNSIGNED_BYTE: u8 = 0x08;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(Debug)]
+pub enum IdxError {
+    Io(io::Error),
+    // the first two bytes are not zero
+    InvalidMagic([u8; 2]),
+    UnsupportedType(u8),
+    DimensionMismatch {
+        expected: usize,
+        found: usize,
+    },
+    Truncated {
+        expected: usize,
+        found: usize,
+    },
+    TrailingData,
+    // an image header with zero rows or columns
+    EmptyImages {
+        rows: usize,
+        cols: usize,
+    },
+    CountMismatch {
+        images: usize,
+        labels: usize,
+    },
+    LabelOutOfRange {
+        index: usize,
+        label: u8,
+        classes: usize,
+    },
+}
+
+impl fmt::Display for IdxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IdxError::Io(err) => write!(f, "io error: {}", err),
+            IdxError::InvalidMagic(magic) => {
+                write!(f, "not an idx file (magic bytes {:?})", magic)
+            }
+            IdxError::UnsupportedType(tag) => {
+                write!(
+                    f,
+                    "unsupported idx data type 0x{:02x}, expected unsigned byte",
+                    tag
+                )
+            }
+            IdxError::DimensionMismatch { expected, found } => write!(
+                f,
+                "expected an idx file with {} dimensions but found {}",
+                expected, found
+            ),
+            IdxError::Truncated { expected, found } => write!(
+                f,
+                "idx data truncated, expected {} bytes but found {}",
+                expected, found
+            ),
+            IdxError::TrailingData => write!(f, "unexpected data after the idx payload"),
+            IdxError::EmptyImages { rows, cols } => {
+                write!(f, "idx images are {}x{} pixels", rows, cols)
+            }
+            IdxError::CountMismatch { images, labels } => {
+                write!(f, "{} images but {} labels", images, labels)
+            }
+            IdxError::LabelOutOfRange {
+                index,
+                label,
+                classes,
+            } => write!(
+                f,
+                "label {} of sample {} is out of range for {} classes",
+                label, index, classes
+            ),
+        }
+    }
+}
+
+impl Error for IdxError {}
+
+impl From<io::Error> for IdxError {
+    fn from(err: io::Error) -> IdxError {
+        IdxError::Io(err)
+    }
+}
+
+// decoded idx file, data is row-major over dims
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdxArray {
+    pub dims: Vec<usize>,
+    pub data: Vec<u8>,
+}
+
+fn read_header_bytes<R: Read>(r: &mut R, buf: &mut [u8]) -> Result<(), IdxError> {
+    r.read_exact(buf).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => IdxError::Truncated {
+            expected: buf.len(),
+            found: 0,
+        },
+        _ => IdxError::Io(err),
+    })
+}
+
+pub fn read_idx<R: Read>(r: &mut R) -> Result<IdxArray, IdxError> {
+    let mut header = [0u8; 4];
+    read_header_bytes(r, &mut header)?;
+    if header[0] != 0 || header[1] != 0 {
+        return Err(IdxError::InvalidMagic([header[0], header[1]]));
+    }
+    if header[2] != UNSIGNED_BYTE {
+        return Err(IdxError::UnsupportedType(header[2]));
+    }
+    let mut dims = Vec::new();
+    for _ in 0..header[3] {
+        let mut dim = [0u8; 4];
+        read_header_bytes(r, &mut dim)?;
+        dims.push(u32::from_be_bytes(dim) as usize);
+    }
+    // no preallocation, a corrupt header must not trigger a huge allocation
+    let len = dims
+        .iter()
+        .fold(1usize, |acc, &dim| acc.saturating_mul(dim));
+    let mut data = Vec::new();
+    r.take(len as u64).read_to_end(&mut data)?;
+    if data.len() < len {
+        return Err(IdxError::Truncated {
+            expected: len,
+            found: data.len(),
+        });
+    }
+    if r.read(&mut [0u8; 1])? != 0 {
+        return Err(IdxError::TrailingData);
+    }
+    Ok(IdxArray { dims, data })
+}
+
+// plain or gzip-compressed idx file
+pub fn read_idx_file<P: AsRef<Path>>(path: P) -> Result<IdxArray, IdxError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        read_idx(&mut GzDecoder::new(reader))
+    } else {
+        read_idx(&mut reader)
+    }
+}
+
+fn expect_dims(array: &IdxArray, expected: usize) -> Result<(), IdxError> {
+    if array.dims.len() != expected {
+        return Err(IdxError::DimensionMismatch {
+            expected,
+            found: array.dims.len(),
+        });
+    }
+    Ok(())
+}
+
+// IDX3 images as 1 x (rows * cols) row vectors scaled like read_csv_by_path
+pub fn read_idx_images<T: Scalar, P: AsRef<Path>>(path: P) -> Result<Vec<Matrix<T>>, IdxError> {
+    let array = read_idx_file(path)?;
+    expect_dims(&array, 3)?;
+    let size = array.dims[1] * array.dims[2];
+    if size == 0 {
+        return Err(IdxError::EmptyImages {
+            rows: array.dims[1],
+            cols: array.dims[2],
+        });
+    }
+    Ok(array
+        .data
+        .chunks(size)
+        .map(|image| {
+            let pixels = image.iter().map(|&p| scale_pixel(p as f64)).collect();
+            Matrix::from_vec(1, size, pixels)
+        })
+        .collect())
+}
+
+// IDX1 labels as 1 x classes row vectors with 0.99 at the label and 0.01 elsewhere
+pub fn read_idx_labels<T: Scalar, P: AsRef<Path>>(
+    path: P,
+    classes: usize,
+) -> Result<Vec<Matrix<T>>, IdxError> {
+    let array = read_idx_file(path)?;
+    expect_dims(&array, 1)?;
+    array
+        .data
+        .iter()
+        .enumerate()
+        .map(|(index, &label)| {
+            if label as usize >= classes {
+                return Err(IdxError::LabelOutOfRange {
+                    index,
+                    label,
+                    classes,
+                });
+            }
+            Ok(one_hot(label as usize, classes))
+        })
+        .collect()
+}
+
+// (labels, data) of an MNIST style image and label file pair, the same shape
+// read_csv_by_path returns
+pub fn read_mnist_idx<T: Scalar, P: AsRef<Path>, Q: AsRef<Path>>(
+    images_path: P,
+    labels_path: Q,
+) -> Result<LabeledData<T>, IdxError> {
+    let images = read_idx_images(images_path)?;
+    let labels = read_idx_labels(labels_path, 10)?;
+    if images.len() != labels.len() {
+        return Err(IdxError::CountMismatch {
+            images: images.len(),
+            labels: labels.len(),
+        });
+    }
+    Ok((labels, images))
+}
+
+#[cfg(test)]
+mod idx_tests {
+    use super::{read_idx, read_idx_file, read_idx_images, read_mnist_idx, IdxArray, IdxError};
+    use crate::dataset::read_csv_by_path;
+    use crate::matrix::MatrixOps;
+    use crate::test_util::{temp_file, temp_path};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn encode(dims: &[u32], data: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0, 0, 0x08, dims.len() as u8];
+        for dim in dims {
+            bytes.extend_from_slice(&dim.to_be_bytes());
+        }
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    // three 2x2 images and their labels
+    fn fixture() -> (Vec<u8>, Vec<u8>) {
+        let pixels = [0, 255, 0, 255, 51, 102, 153, 204, 255, 255, 255, 255];
+        (encode(&[3, 2, 2], &pixels), encode(&[3], &[7, 0, 9]))
+    }
+
+    #[test]
+    fn test_read_idx() {
+        let (images, _) = fixture();
+        let array = read_idx(&mut images.as_slice()).unwrap();
+        assert_eq!(array.dims, vec![3, 2, 2]);
+        assert_eq!(array.data.len(), 12);
+        assert_eq!(
+            read_idx(&mut encode(&[0], &[]).as_slice()).unwrap(),
+            IdxArray {
+                dims: vec![0],
+                data: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_mnist_plain_and_gzip() {
+        let (images, labels) = fixture();
+        let plain = (
+            temp_file("images.idx3", &images),
+            temp_file("labels.idx1", &labels),
+        );
+        let gz = (
+            temp_file("images.idx3.gz", &gzip(&images)),
+            temp_file("labels.idx1.gz", &gzip(&labels)),
+        );
+        let (label, data) = read_mnist_idx::<f64, _, _>(&plain.0, &plain.1).unwrap();
+        let from_gz = read_mnist_idx::<f64, _, _>(&gz.0, &gz.1).unwrap();
+        for path in [plain.0, plain.1, gz.0, gz.1].iter() {
+            std::fs::remove_file(path).unwrap();
+        }
+        assert_eq!((label.clone(), data.clone()), from_gz);
+
+        assert_eq!(data.len(), 3);
+        assert_eq!(data[0].shape(), (1, 4));
+        data[0].show();
+        assert_eq!(data[0].as_slice(), &[0.01, 1.0, 0.01, 1.0][..]);
+        assert!((data[1].get(0, 0) - (0.2 * 0.99 + 0.01)).abs() < 1e-12);
+        assert_eq!(label[0].shape(), (1, 10));
+        assert_eq!(label[0].get(0, 7), 0.99);
+        assert_eq!(label[1].get(0, 0), 0.99);
+        assert_eq!(label[2].get(0, 9), 0.99);
+        assert_eq!(label[2].get(0, 0), 0.01);
+    }
+
+    #[test]
+    fn test_matches_csv() {
+        // rebuild the csv samples as idx files, both loaders must agree
+        let (csv_labels, csv_data) = read_csv_by_path::<f64>("data/mnist_test_10.csv").unwrap();
+        let mut pixels = Vec::new();
+        for image in csv_data.iter() {
+            for &x in image.as_slice() {
+                pixels.push(((x - 0.01) / 0.99 * 255.0).round() as u8);
+            }
+        }
+        let labels: Vec<u8> = csv_labels
+            .iter()
+            .map(|l| l.as_slice().iter().position(|&x| x == 0.99).unwrap() as u8)
+            .collect();
+        let count = labels.len() as u32;
+        let images_path = temp_file("csv.idx3.gz", &gzip(&encode(&[count, 28, 28], &pixels)));
+        let labels_path = temp_file("csv.idx1", &encode(&[count], &labels));
+        let (idx_labels, idx_data) =
+            read_mnist_idx::<f64, _, _>(&images_path, &labels_path).unwrap();
+        std::fs::remove_file(&images_path).unwrap();
+        std::fs::remove_file(&labels_path).unwrap();
+        assert_eq!(idx_labels, csv_labels);
+        for (a, b) in idx_data.iter().zip(csv_data.iter()) {
+            assert!(a.approx_eq(b, 1e-12));
+        }
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let (images, labels) = fixture();
+        let mut bad_magic = images.clone();
+        bad_magic[0] = 1;
+        assert!(matches!(
+            read_idx(&mut bad_magic.as_slice()),
+            Err(IdxError::InvalidMagic([1, 0]))
+        ));
+        let mut bad_type = images.clone();
+        bad_type[2] = 0x0d;
+        assert!(matches!(
+            read_idx(&mut bad_type.as_slice()),
+            Err(IdxError::UnsupportedType(0x0d))
+        ));
+        assert!(matches!(
+            read_idx(&mut &images[..images.len() - 1]),
+            Err(IdxError::Truncated {
+                expected: 12,
+                found: 11
+            })
+        ));
+        assert!(matches!(
+            read_idx(&mut &images[..6]),
+            Err(IdxError::Truncated { .. })
+        ));
+        let mut trailing = labels.clone();
+        trailing.push(0);
+        assert!(matches!(
+            read_idx(&mut trailing.as_slice()),
+            Err(IdxError::TrailingData)
+        ));
+
+        let images_path = temp_file("bad.idx3", &images);
+        let labels_path = temp_file("bad.idx1", &labels);
+        let swapped = read_mnist_idx::<f64, _, _>(&labels_path, &images_path);
+        let short_labels = temp_file("short.idx1", &encode(&[2], &[1, 2]));
+        let count = read_mnist_idx::<f64, _, _>(&images_path, &short_labels);
+        let big_label = temp_file("big.idx1", &encode(&[3], &[1, 2, 10]));
+        let range = read_mnist_idx::<f64, _, _>(&images_path, &big_label);
+        let empty_images = temp_file("empty.idx3", &encode(&[3, 0, 2], &[]));
+        let empty = read_idx_images::<f64, _>(&empty_images);
+        let missing = read_idx_file(temp_path("missing.idx"));
+        for path in [
+            images_path,
+            labels_path,
+            short_labels,
+            big_label,
+            empty_images,
+        ]
+        .iter()
+        {
+            std::fs::remove_file(path).unwrap();
+        }
+        assert!(matches!(
+            swapped,
+            Err(IdxError::DimensionMismatch {
+                expected: 3,
+                found: 1
+            })
+        ));
+        assert!(matches!(
+            count,
+            Err(IdxError::CountMismatch {
+                images: 3,
+                labels: 2
+            })
+        ));
+        assert!(matches!(
+            range,
+            Err(IdxError::LabelOutOfRange {
+                index: 2,
+                label: 10,
+                classes: 10
+            })
+        ));
+        assert!(matches!(
+            empty,
+            Err(IdxError::EmptyImages { rows: 0, cols: 2 })
+        ));
+        assert!(matches!(missing, Err(IdxError::Io(_))));
+    }
+}
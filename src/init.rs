@@ -0,0 +1,247 @@
+use crate::matrix::{Matrix, MatrixOps};
+use crate::scalar::Scalar;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use std::f64::consts::PI;
+
+// Weight initialization schemes. A weights matrix is output x input, so
+// fan_in is its number of columns and fan_out its number of rows.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Initializer {
+    // uniform in [low, high)
+    Uniform(f64, f64),
+    // normal with mean and standard deviation
+    Normal(f64, f64),
+    XavierUniform,
+    XavierNormal,
+    HeUniform,
+    HeNormal,
+    #[serde(rename = "lecun_uniform")]
+    LeCunUniform,
+    #[serde(rename = "lecun_normal")]
+    LeCunNormal,
+    // orthonormal rows or columns scaled by the gain
+    Orthogonal(f64),
+    Zeros,
+    Constant(f64),
+}
+
+impl Default for Initializer {
+    // the original uniform [-0.5, 0.5) initialization
+    fn default() -> Initializer {
+        Initializer::Uniform(-0.5, 0.5)
+    }
+}
+
+// Reproducible generator, the same seed always yields the same weights.
+pub fn seeded_rng(seed: u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
+
+fn uniform<R: Rng + ?Sized>(rng: &mut R, low: f64, high: f64) -> f64 {
+    let value: f64 = rng.gen();
+    low + (high - low) * value
+}
+
+// Box-Muller transform, 1 - gen() keeps the logarithm argument in (0, 1]
+fn normal<R: Rng + ?Sized>(rng: &mut R, mean: f64, std: f64) -> f64 {
+    let u1 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    mean + std * (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+impl Initializer {
+    pub fn init<T: Scalar, R: Rng + ?Sized>(
+        &self,
+        rows: usize,
+        cols: usize,
+        rng: &mut R,
+    ) -> Matrix<T> {
+        let (fan_in, fan_out) = (cols as f64, rows as f64);
+        let data = match *self {
+            Initializer::Uniform(low, high) => sample(rows * cols, || uniform(rng, low, high)),
+            Initializer::Normal(mean, std) => sample(rows * cols, || normal(rng, mean, std)),
+            Initializer::XavierUniform => {
+                let limit = (6.0 / (fan_in + fan_out)).sqrt();
+                sample(rows * cols, || uniform(rng, -limit, limit))
+            }
+            Initializer::XavierNormal => {
+                let std = (2.0 / (fan_in + fan_out)).sqrt();
+                sample(rows * cols, || normal(rng, 0.0, std))
+            }
+            Initializer::HeUniform => {
+                let limit = (6.0 / fan_in).sqrt();
+                sample(rows * cols, || uniform(rng, -limit, limit))
+            }
+            Initializer::HeNormal => {
+                let std = (2.0 / fan_in).sqrt();
+                sample(rows * cols, || normal(rng, 0.0, std))
+            }
+            Initializer::LeCunUniform => {
+                let limit = (3.0 / fan_in).sqrt();
+                sample(rows * cols, || uniform(rng, -limit, limit))
+            }
+            Initializer::LeCunNormal => {
+                let std = (1.0 / fan_in).sqrt();
+                sample(rows * cols, || normal(rng, 0.0, std))
+            }
+            Initializer::Orthogonal(gain) => orthogonal(rows, cols, gain, rng),
+            Initializer::Zeros => vec![0.0; rows * cols],
+            Initializer::Constant(value) => vec![value; rows * cols],
+        };
+        Matrix::from_vec(rows, cols, data.into_iter().map(T::from_f64).collect())
+    }
+}
+
+fn sample<F: FnMut() -> f64>(len: usize, mut f: F) -> Vec<f64> {
+    (0..len).map(|_| f()).collect()
+}
+
+// Modified Gram-Schmidt on the rows of a wide gaussian matrix. The rows end
+// up orthonormal; a tall matrix uses the transpose so that its columns are
+// orthonormal instead.
+fn orthogonal<R: Rng + ?Sized>(rows: usize, cols: usize, gain: f64, rng: &mut R) -> Vec<f64> {
+    let (short, long) = (rows.min(cols), rows.max(cols));
+    let mut q = sample(short * long, || normal(rng, 0.0, 1.0));
+    for j in 0..short {
+        let (done, rest) = q.split_at_mut(j * long);
+        let row = &mut rest[..long];
+        for prev in done.chunks(long) {
+            let projection: f64 = prev.iter().zip(row.iter()).map(|(p, x)| p * x).sum();
+            for (x, p) in row.iter_mut().zip(prev) {
+                *x -= projection * p;
+            }
+        }
+        let norm = row.iter().map(|x| x * x).sum::<f64>().sqrt();
+        for x in row.iter_mut() {
+            *x /= norm;
+        }
+    }
+    for x in q.iter_mut() {
+        *x *= gain;
+    }
+    if rows <= cols {
+        q
+    } else {
+        Matrix::from_vec(short, long, q).transpose().data
+    }
+}
+
+#[cfg(test)]
+mod init_tests {
+    use crate::init::{seeded_rng, Initializer};
+    use crate::matrix::{Matrix, MatrixOps};
+    use crate::scalar::Fixed;
+
+    const ALL: [Initializer; 11] = [
+        Initializer::Uniform(-0.5, 0.5),
+        Initializer::Normal(0.0, 1.0),
+        Initializer::XavierUniform,
+        Initializer::XavierNormal,
+        Initializer::HeUniform,
+        Initializer::HeNormal,
+        Initializer::LeCunUniform,
+        Initializer::LeCunNormal,
+        Initializer::Orthogonal(1.0),
+        Initializer::Zeros,
+        Initializer::Constant(0.1),
+    ];
+
+    fn mean_std(m: &Matrix) -> (f64, f64) {
+        let n = m.data.len() as f64;
+        let mean = m.data.iter().sum::<f64>() / n;
+        let var = m.data.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / n;
+        (mean, var.sqrt())
+    }
+
+    #[test]
+    fn test_same_seed_same_weights() {
+        for init in ALL.iter() {
+            let a: Matrix = init.init(20, 30, &mut seeded_rng(42));
+            let b: Matrix = init.init(20, 30, &mut seeded_rng(42));
+            assert_eq!(a, b);
+            assert_eq!(a.shape(), (20, 30));
+        }
+        let a: Matrix = Initializer::XavierNormal.init(20, 30, &mut seeded_rng(1));
+        let b: Matrix = Initializer::XavierNormal.init(20, 30, &mut seeded_rng(2));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_uniform_limits() {
+        let cases = [
+            (Initializer::Uniform(-0.5, 0.5), 0.5),
+            (Initializer::XavierUniform, (6.0f64 / 300.0).sqrt()),
+            (Initializer::HeUniform, (6.0f64 / 100.0).sqrt()),
+            (Initializer::LeCunUniform, (3.0f64 / 100.0).sqrt()),
+        ];
+        let mut rng = seeded_rng(7);
+        for (init, limit) in cases.iter() {
+            let m: Matrix = init.init(200, 100, &mut rng);
+            assert!(m.data.iter().all(|x| x.abs() <= *limit));
+            // the samples should spread over most of the range
+            let max = m.data.iter().fold(0.0f64, |acc, x| acc.max(x.abs()));
+            assert!(max > 0.95 * limit);
+        }
+    }
+
+    #[test]
+    fn test_normal_statistics() {
+        let cases = [
+            (Initializer::Normal(1.0, 0.5), 1.0, 0.5),
+            (Initializer::XavierNormal, 0.0, (2.0f64 / 300.0).sqrt()),
+            (Initializer::HeNormal, 0.0, (2.0f64 / 100.0).sqrt()),
+            (Initializer::LeCunNormal, 0.0, (1.0f64 / 100.0).sqrt()),
+        ];
+        let mut rng = seeded_rng(7);
+        for (init, mean, std) in cases.iter() {
+            let m: Matrix = init.init(200, 100, &mut rng);
+            let (m_mean, m_std) = mean_std(&m);
+            println!("{:?}: mean {} std {}", init, m_mean, m_std);
+            assert!((m_mean - mean).abs() < 0.05 * std.max(1.0));
+            assert!((m_std - std).abs() < 0.05 * std);
+        }
+    }
+
+    #[test]
+    fn test_orthogonal() {
+        let mut rng = seeded_rng(3);
+        for &(rows, cols) in [(6, 4), (4, 6), (5, 5)].iter() {
+            let m: Matrix = Initializer::Orthogonal(2.0).init(rows, cols, &mut rng);
+            // the shorter side is orthonormal up to the gain
+            let gram = if rows >= cols {
+                m.transpose().product(&m)
+            } else {
+                m.product(&m.transpose())
+            };
+            let n = rows.min(cols);
+            let mut expected: Matrix = Matrix::zeros(n, n);
+            for i in 0..n {
+                expected[(i, i)] = 4.0;
+            }
+            gram.show();
+            assert!(gram.approx_eq(&expected, 1e-9));
+        }
+    }
+
+    #[test]
+    fn test_zeros_and_constant() {
+        let mut rng = seeded_rng(0);
+        let zeros: Matrix = Initializer::Zeros.init(2, 3, &mut rng);
+        assert_eq!(zeros, Matrix::zeros(2, 3));
+        let constant: Matrix = Initializer::Constant(0.1).init(2, 3, &mut rng);
+        assert!(constant.data.iter().all(|x| *x == 0.1));
+        assert_eq!(Initializer::default(), Initializer::Uniform(-0.5, 0.5));
+    }
+
+    #[test]
+    fn test_precisions_share_samples() {
+        let a: Matrix = Initializer::HeNormal.init(8, 5, &mut seeded_rng(9));
+        let b: Matrix<f32> = Initializer::HeNormal.init(8, 5, &mut seeded_rng(9));
+        let c: Matrix<Fixed> = Initializer::HeNormal.init(8, 5, &mut seeded_rng(9));
+        assert_eq!(b, a.convert::<f32>());
+        assert!(c.convert::<f64>().approx_eq(&a, 1e-9));
+    }
+}
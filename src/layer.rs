@@ -1,30 +1,310 @@
-use crate::matrix::{Matrix, MatrixOps};
+use crate::activation::Activation;
+use crate::conv::Conv2d;
+use crate::dropout::Dropout;
+use crate::init::Initializer;
+use crate::matrix::{Matrix, MatrixError, MatrixOps};
+use crate::normalization::{BatchNorm, LayerNorm};
+use crate::regularization::{apply_max_norm, Regularizer};
+use crate::scalar::Scalar;
+use rand::Rng;
+use std::any::Any;
+use std::fmt;
 
-#[derive(Debug)]
-pub struct Layer {
-    input_size: usize,
-    output_size: usize,
-    pub(crate) weights_matrix: Matrix,
+// One step of the network. Every column of an input or output matrix is one
+// sample. `forward` is inference; `forward_train` keeps what the following
+// `backward` needs, which turns dL/d(output) into dL/d(input) and stores the
+// gradients of the layer's parameters in the order of `parameters`.
+pub trait Layer<T: Scalar = f64>: fmt::Debug {
+    fn name(&self) -> &'static str;
+    // number of input values per sample, None if the layer takes any size
+    fn input_size(&self) -> Option<usize>;
+    // number of output values per sample for inputs of `input_size` values
+    fn output_size(&self, input_size: usize) -> usize;
+    fn forward(&self, input: &Matrix<T>) -> Result<Matrix<T>, MatrixError>;
+    fn forward_train(&mut self, input: &Matrix<T>) -> Result<Matrix<T>, MatrixError>;
+    fn backward(&mut self, grad: &Matrix<T>) -> Result<Matrix<T>, MatrixError>;
+    // like backward when dL/d(input) is not needed, the first layer of a network
+    // can skip that work
+    fn backward_parameters(&mut self, grad: &Matrix<T>) -> Result<(), MatrixError> {
+        self.backward(grad).map(|_| ())
+    }
+    fn parameters(&self) -> Vec<&Matrix<T>> {
+        Vec::new()
+    }
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix<T>> {
+        Vec::new()
+    }
+    // gradients from the last backward pass, in the order of `parameters`
+    fn gradients(&self) -> Vec<&Matrix<T>> {
+        Vec::new()
+    }
+    fn gradients_mut(&mut self) -> Vec<&mut Matrix<T>> {
+        Vec::new()
+    }
+    // regularization penalty added to the loss
+    fn penalty(&self) -> f64 {
+        0.0
+    }
+    // constraints on the parameters, applied after every update
+    fn constrain(&mut self) {}
+    fn show(&self);
+    fn box_clone(&self) -> Box<dyn Layer<T>>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+// Layers like dropout and batch norm behave differently while training
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    Train,
+    #[default]
+    Inference,
+}
+
+impl<T: Scalar> Clone for Box<dyn Layer<T>> {
+    fn clone(&self) -> Box<dyn Layer<T>> {
+        self.box_clone()
+    }
 }
 
-impl Layer {
-    pub fn new(data: Matrix) -> Layer {
-        Layer {
-            input_size: data.rows,
-            output_size: data.cols,
+// copy of a layer in another precision, None for layer types defined outside
+// this crate
+pub(crate) fn convert_layer<T: Scalar, U: Scalar>(
+    layer: &dyn Layer<T>,
+) -> Option<Box<dyn Layer<U>>> {
+    let any = layer.as_any();
+    if let Some(layer) = any.downcast_ref::<Dense<T>>() {
+        Some(Box::new(layer.convert::<U>()))
+    } else if let Some(layer) = any.downcast_ref::<ActivationLayer<T>>() {
+        Some(Box::new(ActivationLayer::<U>::new(layer.activation)))
+    } else if let Some(layer) = any.downcast_ref::<Dropout<T>>() {
+        Some(Box::new(layer.convert::<U>()))
+    } else if let Some(layer) = any.downcast_ref::<BatchNorm<T>>() {
+        Some(Box::new(layer.convert::<U>()))
+    } else if let Some(layer) = any.downcast_ref::<LayerNorm<T>>() {
+        Some(Box::new(layer.convert::<U>()))
+    } else if let Some(layer) = any.downcast_ref::<Conv2d<T>>() {
+        Some(Box::new(layer.convert::<U>()))
+    } else {
+        any.downcast_ref::<Reshape>()
+            .map(|layer| -> Box<dyn Layer<U>> { Box::new(layer.clone()) })
+    }
+}
+
+// shape check shared by the layers with a fixed input size
+pub(crate) fn check_input<T: Scalar>(
+    input: &Matrix<T>,
+    size: usize,
+    op: &'static str,
+) -> Result<(), MatrixError> {
+    if input.rows != size {
+        return Err(MatrixError::ShapeMismatch {
+            op,
+            left: (size, input.cols),
+            right: input.shape(),
+        });
+    }
+    Ok(())
+}
+
+// Fully connected layer: Wx + b
+#[derive(Debug, Clone)]
+pub struct Dense<T = f64> {
+    pub(crate) input_size: usize,
+    pub(crate) output_size: usize,
+    pub(crate) weights_matrix: Matrix<T>,
+    pub(crate) bias: Option<Matrix<T>>,
+    pub(crate) regularizer: Regularizer,
+    // maximum L2 norm of each unit's incoming weights, enforced after updates
+    pub(crate) max_norm: Option<f64>,
+    // input of the last training forward pass
+    pub(crate) input: Option<Matrix<T>>,
+    pub(crate) gradients: Vec<Matrix<T>>,
+}
+
+impl<T: Scalar> Dense<T> {
+    pub fn new(data: Matrix<T>) -> Dense<T> {
+        Dense {
+            input_size: data.cols,
+            output_size: data.rows,
             weights_matrix: data,
+            bias: None,
+            regularizer: Regularizer::default(),
+            max_norm: None,
+            input: None,
+            gradients: Vec::new(),
+        }
+    }
+
+    pub fn new_with_bias(data: Matrix<T>, bias: Matrix<T>) -> Dense<T> {
+        assert_eq!(bias.rows, data.rows);
+        assert_eq!(bias.cols, 1);
+        Dense {
+            bias: Some(bias),
+            ..Dense::new(data)
         }
     }
 
-    pub fn new_by_rand(input_size: usize, output_size: usize) -> Layer {
-        Layer {
+    pub fn new_by_rand(input_size: usize, output_size: usize) -> Dense<T> {
+        Dense::new_by_init(
             input_size,
             output_size,
-            weights_matrix: Matrix::new_by_rand(output_size, input_size),
+            Initializer::default(),
+            &mut rand::thread_rng(),
+        )
+    }
+
+    pub fn new_by_rand_without_bias(input_size: usize, output_size: usize) -> Dense<T> {
+        Dense {
+            bias: None,
+            ..Dense::new_by_rand(input_size, output_size)
+        }
+    }
+
+    // weights drawn from the initializer, bias starts at zero
+    pub fn new_by_init<R: Rng + ?Sized>(
+        input_size: usize,
+        output_size: usize,
+        initializer: Initializer,
+        rng: &mut R,
+    ) -> Dense<T> {
+        Dense::new_with_bias(
+            initializer.init(output_size, input_size, rng),
+            Matrix::zeros(output_size, 1),
+        )
+    }
+
+    pub fn with_regularizer(mut self, regularizer: Regularizer) -> Dense<T> {
+        self.regularizer = regularizer;
+        self
+    }
+
+    pub fn regularizer(&self) -> Regularizer {
+        self.regularizer
+    }
+
+    pub fn with_max_norm(mut self, max_norm: f64) -> Dense<T> {
+        assert!(max_norm > 0.0);
+        self.max_norm = Some(max_norm);
+        self
+    }
+
+    pub fn max_norm(&self) -> Option<f64> {
+        self.max_norm
+    }
+
+    pub fn has_bias(&self) -> bool {
+        self.bias.is_some()
+    }
+
+    pub fn weights(&self) -> &Matrix<T> {
+        &self.weights_matrix
+    }
+
+    pub fn bias(&self) -> Option<&Matrix<T>> {
+        self.bias.as_ref()
+    }
+
+    pub fn convert<U: Scalar>(&self) -> Dense<U> {
+        Dense {
+            input_size: self.input_size,
+            output_size: self.output_size,
+            weights_matrix: self.weights_matrix.convert(),
+            bias: self.bias.as_ref().map(|bias| bias.convert()),
+            regularizer: self.regularizer,
+            max_norm: self.max_norm,
+            input: self.input.as_ref().map(|input| input.convert()),
+            gradients: self.gradients.iter().map(|grad| grad.convert()).collect(),
+        }
+    }
+
+    // dL/dW with the regularization term and dL/db
+    fn parameter_gradients(&mut self, grad: &Matrix<T>) -> Result<(), MatrixError> {
+        let input = self
+            .input
+            .as_ref()
+            .expect("backward before a training forward pass");
+        let mut gradient = grad.try_product_transposed(input)?;
+        if !self.regularizer.is_none() {
+            gradient += self.regularizer.gradient(&self.weights_matrix);
+        }
+        self.gradients.clear();
+        self.gradients.push(gradient);
+        if self.bias.is_some() {
+            self.gradients.push(grad.sum_columns());
+        }
+        Ok(())
+    }
+}
+
+impl<T: Scalar> Layer<T> for Dense<T> {
+    fn name(&self) -> &'static str {
+        "dense"
+    }
+
+    fn input_size(&self) -> Option<usize> {
+        Some(self.input_size)
+    }
+
+    fn output_size(&self, _input_size: usize) -> usize {
+        self.output_size
+    }
+
+    fn forward(&self, input: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        let mut z = self.weights_matrix.try_product(input)?;
+        if let Some(bias) = &self.bias {
+            z.try_add_column_assign(bias)?;
+        }
+        Ok(z)
+    }
+
+    fn forward_train(&mut self, input: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        let z = self.forward(input)?;
+        self.input = Some(input.clone());
+        Ok(z)
+    }
+
+    fn backward(&mut self, grad: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        self.parameter_gradients(grad)?;
+        self.weights_matrix.transpose().try_product(grad)
+    }
+
+    fn backward_parameters(&mut self, grad: &Matrix<T>) -> Result<(), MatrixError> {
+        self.parameter_gradients(grad)
+    }
+
+    fn parameters(&self) -> Vec<&Matrix<T>> {
+        let mut parameters = vec![&self.weights_matrix];
+        parameters.extend(self.bias.as_ref());
+        parameters
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix<T>> {
+        let mut parameters = vec![&mut self.weights_matrix];
+        parameters.extend(self.bias.as_mut());
+        parameters
+    }
+
+    fn gradients(&self) -> Vec<&Matrix<T>> {
+        self.gradients.iter().collect()
+    }
+
+    fn gradients_mut(&mut self) -> Vec<&mut Matrix<T>> {
+        self.gradients.iter_mut().collect()
+    }
+
+    fn penalty(&self) -> f64 {
+        self.regularizer.penalty(&self.weights_matrix)
+    }
+
+    fn constrain(&mut self) {
+        if let Some(max_norm) = self.max_norm {
+            apply_max_norm(&mut self.weights_matrix, max_norm);
         }
     }
 
-    pub fn show(&self) {
+    fn show(&self) {
+        println!("[Layer] dense");
         println!("[Layer] input size: {}", self.input_size);
         println!("[Layer] output size: {}", self.output_size);
         println!(
@@ -32,23 +312,188 @@ impl Layer {
             self.weights_matrix.rows, self.weights_matrix.cols
         );
         // self.weights_matrix.show();
+        match &self.bias {
+            Some(bias) => println!("[Layer] bias: {}x{}", bias.rows, bias.cols),
+            None => println!("[Layer] bias: none"),
+        }
+    }
+
+    fn box_clone(&self) -> Box<dyn Layer<T>> {
+        Box::new(self.clone())
     }
 
-    pub fn call(&self, input: &Matrix) -> Matrix {
-        let mut res = self.weights_matrix.product(input);
-        res.activate_sigmoid();
-        res
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// Element-wise activation, or softmax over every column
+#[derive(Debug, Clone)]
+pub struct ActivationLayer<T = f64> {
+    pub(crate) activation: Activation,
+    // input and output of the last training forward pass
+    pub(crate) cache: Option<(Matrix<T>, Matrix<T>)>,
+}
+
+impl<T: Scalar> ActivationLayer<T> {
+    pub fn new(activation: Activation) -> ActivationLayer<T> {
+        ActivationLayer {
+            activation,
+            cache: None,
+        }
+    }
+
+    pub fn activation(&self) -> Activation {
+        self.activation
+    }
+}
+
+impl<T: Scalar> Layer<T> for ActivationLayer<T> {
+    fn name(&self) -> &'static str {
+        self.activation.name()
+    }
+
+    fn input_size(&self) -> Option<usize> {
+        None
+    }
+
+    fn output_size(&self, input_size: usize) -> usize {
+        input_size
+    }
+
+    fn forward(&self, input: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        Ok(self.activation.forward(input))
+    }
+
+    fn forward_train(&mut self, input: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        let output = self.activation.forward(input);
+        self.cache = Some((input.clone(), output.clone()));
+        Ok(output)
+    }
+
+    fn backward(&mut self, grad: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        let (input, output) = self
+            .cache
+            .as_ref()
+            .expect("backward before a training forward pass");
+        if grad.shape() != output.shape() {
+            return Err(MatrixError::ShapeMismatch {
+                op: "activation",
+                left: output.shape(),
+                right: grad.shape(),
+            });
+        }
+        Ok(self.activation.backward(input, output, grad))
+    }
+
+    fn show(&self) {
+        println!("[Layer] activation: {}", self.activation.name());
+    }
+
+    fn box_clone(&self) -> Box<dyn Layer<T>> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// Reinterprets every sample as another shape with the same number of values,
+// e.g. [784] as [1, 28, 28] in front of a convolution. Samples are stored
+// flattened, so the data passes through unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reshape {
+    pub(crate) input_shape: Vec<usize>,
+    pub(crate) output_shape: Vec<usize>,
+}
+
+impl Reshape {
+    pub fn new(input_shape: Vec<usize>, output_shape: Vec<usize>) -> Reshape {
+        assert_eq!(
+            input_shape.iter().product::<usize>(),
+            output_shape.iter().product::<usize>()
+        );
+        Reshape {
+            input_shape,
+            output_shape,
+        }
+    }
+
+    pub fn input_shape(&self) -> &[usize] {
+        &self.input_shape
+    }
+
+    pub fn output_shape(&self) -> &[usize] {
+        &self.output_shape
+    }
+}
+
+impl<T: Scalar> Layer<T> for Reshape {
+    fn name(&self) -> &'static str {
+        "reshape"
+    }
+
+    fn input_size(&self) -> Option<usize> {
+        Some(self.input_shape.iter().product())
+    }
+
+    fn output_size(&self, input_size: usize) -> usize {
+        input_size
+    }
+
+    fn forward(&self, input: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        check_input(input, self.input_shape.iter().product(), "reshape")?;
+        Ok(input.clone())
+    }
+
+    fn forward_train(&mut self, input: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        Layer::<T>::forward(self, input)
+    }
+
+    fn backward(&mut self, grad: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        Ok(grad.clone())
+    }
+
+    fn show(&self) {
+        println!(
+            "[Layer] reshape: {:?} -> {:?}",
+            self.input_shape, self.output_shape
+        );
+    }
+
+    fn box_clone(&self) -> Box<dyn Layer<T>> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
     }
 }
 
 #[cfg(test)]
 mod layer_tests {
-    use crate::layer::Layer;
-    use crate::matrix::{Matrix, MatrixOps};
+    use crate::activation::Activation;
+    use crate::init::{seeded_rng, Initializer};
+    use crate::layer::{ActivationLayer, Dense, Layer, Reshape};
+    use crate::matrix::{Matrix, MatrixError, MatrixOps};
+    use crate::regularization::Regularizer;
 
     #[test]
     fn test_show() {
-        let layer = Layer::new_by_rand(3, 3);
+        let layer: Dense = Dense::new_by_rand(3, 3);
         layer.show();
     }
 
@@ -59,12 +504,105 @@ mod layer_tests {
             vec![0.2, 0.8, 0.2],
             vec![0.1, 0.5, 0.6],
         ]);
-        let layer = Layer::new(weights);
+        let layer: Dense = Dense::new(weights);
 
         let inputs = Matrix::new(vec![vec![0.9, 0.1, 0.8]]);
         let inputs = inputs.transpose();
         println!("Inputs:");
-        let result = layer.call(&inputs);
+        let result = layer.forward(&inputs).unwrap();
         result.show();
+        assert!((result.get(0, 0) - 1.16).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_call_with_bias() {
+        let weights = Matrix::new(vec![vec![0.9, 0.3, 0.4], vec![0.2, 0.8, 0.2]]);
+        let bias = Matrix::new(vec![vec![0.5], vec![-0.5]]);
+        let no_bias = Dense::new(weights.clone());
+        let layer = Dense::new_with_bias(weights, bias);
+        assert!(layer.has_bias());
+        assert!(!no_bias.has_bias());
+        assert_eq!(layer.input_size(), Some(3));
+        assert_eq!(layer.output_size(3), 2);
+        assert_eq!(layer.parameters().len(), 2);
+        assert_eq!(no_bias.parameters().len(), 1);
+
+        let inputs = Matrix::new(vec![vec![0.9, 0.1, 0.8]]).transpose();
+        let z = layer.forward(&inputs).unwrap();
+        let result = ActivationLayer::new(Activation::Sigmoid)
+            .forward(&z)
+            .unwrap();
+        result.show();
+        // sigmoid(1.16 + 0.5) and sigmoid(0.42 - 0.5)
+        assert!((result.get(0, 0) - Matrix::<f64>::sigmoid(1.66)).abs() < 1e-12);
+        assert!((result.get(1, 0) - Matrix::<f64>::sigmoid(-0.08)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_call_with_activation() {
+        let weights = Matrix::new(vec![vec![1.0, -2.0], vec![-1.0, 0.5]]);
+        let layer = Dense::new(weights);
+        let relu = ActivationLayer::new(Activation::Relu);
+        assert_eq!(relu.activation(), Activation::Relu);
+        assert_eq!(Layer::<f64>::name(&relu), "relu");
+        let inputs = Matrix::new(vec![vec![0.5, 1.0]]).transpose();
+        let z = layer.forward(&inputs).unwrap();
+        let result = relu.forward(&z).unwrap();
+        assert_eq!(z.data, vec![-1.5, 0.0]);
+        assert_eq!(result.data, vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_new_by_init() {
+        let layer: Dense = Dense::new_by_init(4, 3, Initializer::XavierUniform, &mut seeded_rng(5));
+        layer.show();
+        assert_eq!(layer.weights_matrix.shape(), (3, 4));
+        assert_eq!(layer.bias, Some(Matrix::zeros(3, 1)));
+        let limit = (6.0f64 / 7.0).sqrt();
+        assert!(layer.weights_matrix.data.iter().all(|w| w.abs() <= limit));
+
+        let same: Dense = Dense::new_by_init(4, 3, Initializer::XavierUniform, &mut seeded_rng(5));
+        assert_eq!(layer.weights_matrix, same.weights_matrix);
+    }
+
+    #[test]
+    fn test_dense_backward() {
+        let weights = Matrix::new(vec![vec![1.0, -2.0, 0.5], vec![-1.0, 0.5, 2.0]]);
+        let bias = Matrix::new(vec![vec![0.1], vec![-0.1]]);
+        let mut layer =
+            Dense::new_with_bias(weights.clone(), bias).with_regularizer(Regularizer::l2(0.5));
+        let inputs = Matrix::new(vec![vec![0.5, 1.0, -1.0], vec![2.0, 0.0, 1.0]]).transpose();
+        let grad = Matrix::new(vec![vec![1.0, 0.5], vec![-1.0, 2.0]]).transpose();
+        layer.forward_train(&inputs).unwrap();
+        let input_grad = layer.backward(&grad).unwrap();
+        assert_eq!(input_grad, weights.transpose().product(&grad));
+        let gradients = layer.gradients();
+        // dW = grad * x^T + 2 * 0.5 * W
+        let expected = grad.product_transposed(&inputs).add(&weights);
+        assert_eq!(gradients[0], &expected);
+        assert_eq!(gradients[1], &grad.sum_columns());
+
+        // the first layer skips dL/d(input) but stores the same gradients
+        let mut first = layer.clone();
+        first.gradients.clear();
+        first.backward_parameters(&grad).unwrap();
+        assert_eq!(first.gradients, layer.gradients);
+
+        let wrong = Matrix::new(vec![vec![0.5, 1.0]]).transpose();
+        match layer.forward(&wrong) {
+            Err(MatrixError::ShapeMismatch { .. }) => {}
+            other => panic!("expected shape error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_reshape() {
+        let mut reshape = Reshape::new(vec![6], vec![1, 2, 3]);
+        assert_eq!(Layer::<f64>::input_size(&reshape), Some(6));
+        assert_eq!(Layer::<f64>::output_size(&reshape, 6), 6);
+        let inputs: Matrix = Matrix::ones(6, 2);
+        assert_eq!(reshape.forward_train(&inputs).unwrap(), inputs);
+        assert_eq!(reshape.backward(&inputs).unwrap(), inputs);
+        assert!(reshape.forward(&Matrix::<f64>::ones(5, 2)).is_err());
     }
 }
@@ -1,4 +1,24 @@
+pub mod activation;
+pub mod autograd;
+pub mod builder;
+pub mod config;
+pub mod conv;
 pub mod dataset;
+pub mod dropout;
+pub mod gemm;
+pub mod idx;
+pub mod init;
 pub mod layer;
+pub mod loss;
 pub mod matrix;
+pub mod metrics;
 pub mod nn;
+pub mod normalization;
+pub mod optimizer;
+pub mod regularization;
+pub mod scalar;
+pub mod schedule;
+pub mod serialization;
+#[cfg(test)]
+mod test_util;
+pub mod trainer;
@@ -0,0 +1,241 @@
+use crate::matrix::Matrix;
+use crate::scalar::Scalar;
+use serde::Deserialize;
+
+// Each column of output/target is one sample. `loss` is the per-sample loss
+// summed over the output units and averaged over the batch, `gradient` is
+// dL/d(output) for that averaged loss.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Loss {
+    // 0.5 * sum((o - y)^2), so the gradient is simply o - y
+    Mse,
+    Mae,
+    Huber(f64),
+    BinaryCrossEntropy,
+    CategoricalCrossEntropy,
+    // targets are -1 or 1
+    Hinge,
+}
+
+// keeps log() finite for saturated outputs
+const EPSILON: f64 = 1e-12;
+
+// EPSILON, or the precision of T if that is coarser
+fn epsilon<T: Scalar>() -> T {
+    T::from_f64(EPSILON).max(T::epsilon())
+}
+
+impl Loss {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Loss::Mse => "mse",
+            Loss::Mae => "mae",
+            Loss::Huber(_) => "huber",
+            Loss::BinaryCrossEntropy => "binary_cross_entropy",
+            Loss::CategoricalCrossEntropy => "categorical_cross_entropy",
+            Loss::Hinge => "hinge",
+        }
+    }
+
+    pub fn loss<T: Scalar>(&self, output: &Matrix<T>, target: &Matrix<T>) -> f64 {
+        assert_eq!(output.rows, target.rows);
+        assert_eq!(output.cols, target.cols);
+        let mut sum = 0.0;
+        for (o, y) in output.data.iter().zip(target.data.iter()) {
+            sum += self.element_loss(*o, *y).to_f64();
+        }
+        sum / output.cols as f64
+    }
+
+    pub fn gradient<T: Scalar>(&self, output: &Matrix<T>, target: &Matrix<T>) -> Matrix<T> {
+        assert_eq!(output.rows, target.rows);
+        assert_eq!(output.cols, target.cols);
+        let batch_size = T::from_f64(output.cols as f64);
+        if let Loss::Mse = self {
+            return (output - target) / batch_size;
+        }
+        let mut res = output.clone();
+        for (o, y) in res.data.iter_mut().zip(target.data.iter()) {
+            *o = self.element_gradient(*o, *y) / batch_size;
+        }
+        res
+    }
+
+    fn element_loss<T: Scalar>(&self, o: T, y: T) -> T {
+        let one = T::one();
+        let half = T::from_f64(0.5);
+        match *self {
+            Loss::Mse => half * (o - y) * (o - y),
+            Loss::Mae => (o - y).abs(),
+            Loss::Huber(delta) => {
+                let delta = T::from_f64(delta);
+                let d = (o - y).abs();
+                if d <= delta {
+                    half * d * d
+                } else {
+                    delta * (d - half * delta)
+                }
+            }
+            Loss::BinaryCrossEntropy => {
+                let o = o.clamp(epsilon(), one - epsilon());
+                -(y * o.ln() + (one - y) * (one - o).ln())
+            }
+            Loss::CategoricalCrossEntropy => -y * o.max(epsilon()).ln(),
+            Loss::Hinge => (one - y * o).max(T::zero()),
+        }
+    }
+
+    fn element_gradient<T: Scalar>(&self, o: T, y: T) -> T {
+        let one = T::one();
+        match *self {
+            Loss::Mse => o - y,
+            Loss::Mae => {
+                if o > y {
+                    one
+                } else if o < y {
+                    -one
+                } else {
+                    T::zero()
+                }
+            }
+            Loss::Huber(delta) => {
+                let delta = T::from_f64(delta);
+                (o - y).clamp(-delta, delta)
+            }
+            Loss::BinaryCrossEntropy => {
+                let o = o.clamp(epsilon(), one - epsilon());
+                (o - y) / (o * (one - o))
+            }
+            Loss::CategoricalCrossEntropy => -y / o.max(epsilon()),
+            Loss::Hinge => {
+                if y * o < one {
+                    -y
+                } else {
+                    T::zero()
+                }
+            }
+        }
+    }
+}
+
+// categorical cross-entropy of softmax(logits), computed with log-sum-exp
+pub fn softmax_cross_entropy<T: Scalar>(logits: &Matrix<T>, target: &Matrix<T>) -> f64 {
+    assert_eq!(logits.rows, target.rows);
+    assert_eq!(logits.cols, target.cols);
+    let mut sum = 0.0;
+    for col in 0..logits.cols {
+        let mut max = logits.get(0, col);
+        for row in 1..logits.rows {
+            max = max.max(logits.get(row, col));
+        }
+        let mut exp_sum = T::zero();
+        for row in 0..logits.rows {
+            exp_sum += (logits.get(row, col) - max).exp();
+        }
+        let log_sum = max + exp_sum.ln();
+        for row in 0..logits.rows {
+            sum -= (target.get(row, col) * (logits.get(row, col) - log_sum)).to_f64();
+        }
+    }
+    sum / logits.cols as f64
+}
+
+// dL/d(logits) of softmax_cross_entropy given the softmax output:
+// p * sum(y) - y, which is p - y for one-hot targets
+pub fn softmax_cross_entropy_gradient<T: Scalar>(
+    output: &Matrix<T>,
+    target: &Matrix<T>,
+) -> Matrix<T> {
+    let batch_size = T::from_f64(output.cols as f64);
+    let mut res = output.clone();
+    for col in 0..output.cols {
+        let mut total = T::zero();
+        for row in 0..output.rows {
+            total += target.get(row, col);
+        }
+        for row in 0..output.rows {
+            *res.get_mut(row, col) =
+                (output.get(row, col) * total - target.get(row, col)) / batch_size;
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod loss_tests {
+    use super::{softmax_cross_entropy, softmax_cross_entropy_gradient, Loss};
+    use crate::activation::Activation;
+    use crate::matrix::{Matrix, MatrixOps};
+    use crate::test_util::{assert_gradient, numeric_gradient};
+
+    fn check_gradient(loss: &Loss, output: &Matrix, target: &Matrix) {
+        let analytic = loss.gradient(output, target);
+        let numeric = numeric_gradient(output, 1e-7, |output| loss.loss(output, target));
+        assert_gradient(loss.name(), &numeric, &analytic, 1e-5);
+    }
+
+    #[test]
+    fn test_gradient_check() {
+        let output = Matrix::new(vec![vec![0.2, 0.7], vec![0.9, 0.4], vec![0.35, 0.05]]);
+        let target = Matrix::new(vec![vec![0.0, 1.0], vec![1.0, 0.0], vec![0.0, 0.0]]);
+        for loss in [
+            Loss::Mse,
+            Loss::Mae,
+            Loss::Huber(0.5),
+            Loss::BinaryCrossEntropy,
+            Loss::CategoricalCrossEntropy,
+        ]
+        .iter()
+        {
+            check_gradient(loss, &output, &target);
+        }
+
+        let target = Matrix::new(vec![vec![-1.0, 1.0], vec![1.0, -1.0], vec![-1.0, 1.0]]);
+        check_gradient(&Loss::Hinge, &output, &target);
+    }
+
+    #[test]
+    fn test_mse_matches_squared_error() {
+        let output = Matrix::new(vec![vec![0.5], vec![0.25]]);
+        let target = Matrix::new(vec![vec![1.0], vec![0.0]]);
+        assert_eq!(Loss::Mse.loss(&output, &target), 0.5 * (0.25 + 0.0625));
+        assert_eq!(
+            Loss::Mse.gradient(&output, &target).data,
+            (&output - &target).data
+        );
+    }
+
+    #[test]
+    fn test_huber_is_linear_outside_delta() {
+        let output = Matrix::new(vec![vec![3.0]]);
+        let target = Matrix::new(vec![vec![0.0]]);
+        assert_eq!(Loss::Huber(1.0).loss(&output, &target), 2.5);
+        assert_eq!(Loss::Huber(1.0).gradient(&output, &target).data, vec![1.0]);
+    }
+
+    #[test]
+    fn test_softmax_cross_entropy_matches_unfused() {
+        let logits = Matrix::new(vec![vec![2.0, -1.0], vec![0.5, 0.3], vec![-0.7, 1.2]]);
+        let target = Matrix::new(vec![vec![1.0, 0.0], vec![0.0, 0.0], vec![0.0, 1.0]]);
+        let output = Activation::Softmax.forward(&logits);
+
+        let fused = softmax_cross_entropy(&logits, &target);
+        let unfused = Loss::CategoricalCrossEntropy.loss(&output, &target);
+        assert!((fused - unfused).abs() < 1e-12);
+
+        let fused = softmax_cross_entropy_gradient(&output, &target);
+        let grad = Loss::CategoricalCrossEntropy.gradient(&output, &target);
+        let unfused = Activation::Softmax.backward(&logits, &output, &grad);
+        assert!(fused.approx_eq(&unfused, 1e-10));
+    }
+
+    #[test]
+    fn test_softmax_cross_entropy_is_stable() {
+        let logits = Matrix::new(vec![vec![1000.0], vec![-1000.0]]);
+        let target = Matrix::new(vec![vec![0.0], vec![1.0]]);
+        let loss = softmax_cross_entropy(&logits, &target);
+        assert!(loss.is_finite());
+        assert!((loss - 2000.0).abs() < 1e-9);
+    }
+}
@@ -1,40 +1,36 @@
-use neuralnetwork::dataset::{read_csv_by_path};
-use neuralnetwork::matrix::MatrixOps;
+use neuralnetwork::config::Config;
+use neuralnetwork::dataset::{read_csv_by_path, Dataset};
 use neuralnetwork::nn::NeuralNetwork;
+use std::error::Error;
 
-fn main() {
+fn main() -> Result<(), Box<dyn Error>> {
     // read train data
     println!("Reading train data ...");
-    let (train_label, train_data) = read_csv_by_path("data/mnist_train_100.csv").unwrap();
+    let (train_label, train_data) = read_csv_by_path("data/mnist_train_100.csv")?;
+    let train = Dataset::new(train_data, train_label)?;
 
     // read test data
     println!("Reading test data ...");
-    let (test_label, test_data) = read_csv_by_path("data/mnist_test_10.csv").unwrap();
+    let (test_label, test_data) = read_csv_by_path("data/mnist_test_10.csv")?;
+    let test = Dataset::new(test_data, test_label)?;
 
-    // new neural network
-    let mut nn = NeuralNetwork::new(vec![784, 100, 10]);
+    // new neural network and trainer, the architecture and hyperparameters live in the config
+    let config = Config::load("data/mnist_mlp.toml")?;
+    let mut nn: NeuralNetwork<f64> = config.build()?;
     nn.show();
-
-    // train
+    let mut trainer = config.trainer()?;
     println!("Start train ...");
-    for j in 0..10 {
-        println!("Epoch {}", j);
-        for i in 0..train_data.len() {
-            nn.train(&train_data[i].transpose(), &train_label[i].transpose());
-        }
-        // start eval
-        println!("Start eval ...");
-        for i in 0..test_data.len() {
-            nn.eval(&test_data[i].transpose(), &test_label[i]);
-        }
-        println!("End eval");
-    }
+    trainer.fit(&mut nn, &train, Some(&test))?;
     println!("End train");
 
     // start eval
     println!("Start eval ...");
-    for i in 0..test_data.len() {
-        nn.eval(&test_data[i].transpose(), &test_label[i]);
-    }
+    let report = nn.evaluate(test.inputs(), test.labels())?;
+    println!("{}", report);
     println!("End eval");
+
+    // save model
+    println!("Saving model ...");
+    nn.save("data/mnist_model.snnm")?;
+    Ok(())
 }
@@ -1,13 +1,94 @@
+// Row-major storage: element (row, col) lives at data[row * cols + col]
 #[derive(Debug)]
-pub struct Matrix {
-    pub(crate) data: Vec<Vec<f64>>,
+pub struct Matrix<T = f64> {
+    pub(crate) data: Vec<T>,
     pub(crate) rows: usize,
     pub(crate) cols: usize,
 }
 
+use crate::gemm;
+use crate::scalar::Scalar;
 use rand::prelude::*;
-impl Clone for Matrix {
-    fn clone(&self) -> Matrix {
+use std::error::Error;
+use std::fmt;
+use std::ops;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatrixError {
+    ShapeMismatch {
+        op: &'static str,
+        left: (usize, usize),
+        right: (usize, usize),
+    },
+    Empty,
+    RaggedRows {
+        row: usize,
+        expected: usize,
+        found: usize,
+    },
+    InvalidLength {
+        rows: usize,
+        cols: usize,
+        len: usize,
+    },
+    // a set of samples and a set of labels of different sizes
+    CountMismatch {
+        samples: usize,
+        labels: usize,
+    },
+    // a class index that is not below the number of classes
+    ClassOutOfRange {
+        class: usize,
+        num_classes: usize,
+    },
+}
+
+impl fmt::Display for MatrixError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatrixError::ShapeMismatch { op, left, right } => write!(
+                f,
+                "shape mismatch in {}: {}x{} and {}x{}",
+                op, left.0, left.1, right.0, right.1
+            ),
+            MatrixError::Empty => write!(f, "matrix must have at least one row and one column"),
+            MatrixError::RaggedRows {
+                row,
+                expected,
+                found,
+            } => write!(
+                f,
+                "row {} has {} columns but row 0 has {}",
+                row, found, expected
+            ),
+            MatrixError::InvalidLength { rows, cols, len } => {
+                write!(f, "{} values cannot fill a {}x{} matrix", len, rows, cols)
+            }
+            MatrixError::CountMismatch { samples, labels } => {
+                write!(f, "{} samples but {} labels", samples, labels)
+            }
+            MatrixError::ClassOutOfRange { class, num_classes } => {
+                write!(
+                    f,
+                    "class {} out of range for {} classes",
+                    class, num_classes
+                )
+            }
+        }
+    }
+}
+
+impl Error for MatrixError {}
+
+fn or_panic<T>(res: Result<T, MatrixError>) -> T {
+    match res {
+        Ok(value) => value,
+        Err(err) => panic!("{}", err),
+    }
+}
+
+impl<T: Clone> Clone for Matrix<T> {
+    fn clone(&self) -> Matrix<T> {
         Matrix {
             data: self.data.clone(),
             rows: self.rows,
@@ -16,103 +97,311 @@ impl Clone for Matrix {
     }
 }
 
-pub trait MatrixOps {
-    fn new(data: Vec<Vec<f64>>) -> Matrix;
-    fn new_by_rand(row: usize, col: usize) -> Matrix;
-    fn zeros(row: usize, col: usize) -> Matrix;
-    fn ones(row: usize, col: usize) -> Matrix;
+impl<T: Scalar> Matrix<T> {
+    pub fn from_vec(rows: usize, cols: usize, data: Vec<T>) -> Matrix<T> {
+        or_panic(Matrix::try_from_vec(rows, cols, data))
+    }
+
+    pub fn try_from_vec(rows: usize, cols: usize, data: Vec<T>) -> Result<Matrix<T>, MatrixError> {
+        if rows == 0 || cols == 0 {
+            return Err(MatrixError::Empty);
+        }
+        if data.len() != rows * cols {
+            return Err(MatrixError::InvalidLength {
+                rows,
+                cols,
+                len: data.len(),
+            });
+        }
+        Ok(Matrix { data, rows, cols })
+    }
+
+    pub fn shape(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    // element-wise conversion to another precision, e.g. matrix.convert::<f32>()
+    pub fn convert<U: Scalar>(&self) -> Matrix<U> {
+        Matrix {
+            data: self.data.iter().map(|x| U::from_f64(x.to_f64())).collect(),
+            rows: self.rows,
+            cols: self.cols,
+        }
+    }
+
+    fn check_same_shape(&self, b: &Matrix<T>, op: &'static str) -> Result<(), MatrixError> {
+        if self.rows != b.rows || self.cols != b.cols {
+            return Err(MatrixError::ShapeMismatch {
+                op,
+                left: self.shape(),
+                right: b.shape(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    #[inline]
+    pub fn get(&self, row: usize, col: usize) -> T {
+        assert!(row < self.rows && col < self.cols);
+        self.data[row * self.cols + col]
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, row: usize, col: usize) -> &mut T {
+        assert!(row < self.rows && col < self.cols);
+        &mut self.data[row * self.cols + col]
+    }
+
+    #[inline]
+    pub fn set(&mut self, row: usize, col: usize, value: T) {
+        *self.get_mut(row, col) = value;
+    }
+
+    pub fn row(&self, row: usize) -> &[T] {
+        &self.data[row * self.cols..(row + 1) * self.cols]
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+
+    fn zip_with<F: Fn(T, T) -> T>(
+        &self,
+        b: &Matrix<T>,
+        op: &'static str,
+        f: F,
+    ) -> Result<Matrix<T>, MatrixError> {
+        self.check_same_shape(b, op)?;
+        let data = self
+            .data
+            .iter()
+            .zip(b.data.iter())
+            .map(|(x, y)| f(*x, *y))
+            .collect();
+        Ok(Matrix {
+            data,
+            rows: self.rows,
+            cols: self.cols,
+        })
+    }
+
+    fn zip_assign<F: Fn(&mut T, T)>(
+        &mut self,
+        b: &Matrix<T>,
+        op: &'static str,
+        f: F,
+    ) -> Result<(), MatrixError> {
+        self.check_same_shape(b, op)?;
+        for (x, y) in self.data.iter_mut().zip(b.data.iter()) {
+            f(x, *y);
+        }
+        Ok(())
+    }
+
+    // In-place variants of the element-wise operations, they reuse the
+    // buffer of self instead of allocating a new matrix.
+    pub fn try_add_assign(&mut self, b: &Matrix<T>) -> Result<(), MatrixError> {
+        self.zip_assign(b, "add", |x, y| *x += y)
+    }
+
+    pub fn try_sub_assign(&mut self, b: &Matrix<T>) -> Result<(), MatrixError> {
+        self.zip_assign(b, "sub", |x, y| *x -= y)
+    }
+
+    pub fn try_mul_assign(&mut self, b: &Matrix<T>) -> Result<(), MatrixError> {
+        self.zip_assign(b, "mul", |x, y| *x *= y)
+    }
+
+    // self += alpha * b
+    pub fn try_add_scaled(&mut self, alpha: T, b: &Matrix<T>) -> Result<(), MatrixError> {
+        self.zip_assign(b, "add_scaled", |x, y| *x += alpha * y)
+    }
+
+    pub fn add_scaled(&mut self, alpha: T, b: &Matrix<T>) {
+        or_panic(self.try_add_scaled(alpha, b))
+    }
+
+    pub fn try_add_column_assign(&mut self, b: &Matrix<T>) -> Result<(), MatrixError> {
+        if self.rows != b.rows || b.cols != 1 {
+            return Err(MatrixError::ShapeMismatch {
+                op: "add_column",
+                left: self.shape(),
+                right: b.shape(),
+            });
+        }
+        for (row, value) in b.data.iter().enumerate() {
+            for x in self.data[row * self.cols..(row + 1) * self.cols].iter_mut() {
+                *x += *value;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn map_in_place<F: Fn(T) -> T>(&mut self, f: F) {
+        for x in self.data.iter_mut() {
+            *x = f(*x);
+        }
+    }
+
+    // largest element-wise difference between two matrices of the same shape
+    pub fn max_abs_diff(&self, b: &Matrix<T>) -> Result<f64, MatrixError> {
+        self.check_same_shape(b, "max_abs_diff")?;
+        let mut max: f64 = 0.0;
+        for (x, y) in self.data.iter().zip(b.data.iter()) {
+            max = max.max((*x - *y).to_f64().abs());
+        }
+        Ok(max)
+    }
+
+    // same shape and every element within `tolerance`
+    pub fn approx_eq(&self, b: &Matrix<T>, tolerance: f64) -> bool {
+        match self.max_abs_diff(b) {
+            Ok(diff) => diff <= tolerance,
+            Err(_) => false,
+        }
+    }
+
+    // like approx_eq, but the tolerance scales with the magnitude of the elements
+    pub fn relative_eq(&self, b: &Matrix<T>, tolerance: f64) -> bool {
+        if self.shape() != b.shape() {
+            return false;
+        }
+        self.data.iter().zip(b.data.iter()).all(|(x, y)| {
+            let (x, y) = (x.to_f64(), y.to_f64());
+            (x - y).abs() <= tolerance * x.abs().max(y.abs()).max(1.0)
+        })
+    }
+}
+
+// The plain methods panic on bad shapes, the try_* variants return a MatrixError instead.
+pub trait MatrixOps<T: Scalar>: Sized {
+    fn new(data: Vec<Vec<T>>) -> Self;
+    fn try_new(data: Vec<Vec<T>>) -> Result<Self, MatrixError>;
+    fn new_by_rand(row: usize, col: usize) -> Self;
+    fn try_new_by_rand(row: usize, col: usize) -> Result<Self, MatrixError>;
+    fn zeros(row: usize, col: usize) -> Self;
+    fn try_zeros(row: usize, col: usize) -> Result<Self, MatrixError>;
+    fn ones(row: usize, col: usize) -> Self;
+    fn try_ones(row: usize, col: usize) -> Result<Self, MatrixError>;
     fn activate_sigmoid(&mut self);
-    fn sigmoid(x: f64) -> f64;
-    fn transpose(&self) -> Matrix;
-    fn dot(&self, b: &Matrix) -> f64;
-    fn dot_const(&self, b: &f64) -> f64;
-    fn product(&self, b: &Matrix) -> Matrix;
-    fn mul(&self, b: &Matrix) -> Matrix;
-    fn mul_const(&self, b: f64) -> Matrix;
-    fn add(&self, b: &Matrix) -> Matrix;
-    fn sub(&self, b: &Matrix) -> Matrix;
-    fn div_by_const(&self, b: f64) -> Matrix;
+    fn sigmoid(x: T) -> T;
+    fn transpose(&self) -> Matrix<T>;
+    fn dot(&self, b: &Matrix<T>) -> T;
+    fn try_dot(&self, b: &Matrix<T>) -> Result<T, MatrixError>;
+    fn dot_const(&self, b: &T) -> T;
+    fn product(&self, b: &Matrix<T>) -> Matrix<T>;
+    fn try_product(&self, b: &Matrix<T>) -> Result<Matrix<T>, MatrixError>;
+    fn product_transposed(&self, b: &Matrix<T>) -> Matrix<T>;
+    fn try_product_transposed(&self, b: &Matrix<T>) -> Result<Matrix<T>, MatrixError>;
+    fn mul(&self, b: &Matrix<T>) -> Matrix<T>;
+    fn try_mul(&self, b: &Matrix<T>) -> Result<Matrix<T>, MatrixError>;
+    fn mul_const(&self, b: T) -> Matrix<T>;
+    fn add(&self, b: &Matrix<T>) -> Matrix<T>;
+    fn try_add(&self, b: &Matrix<T>) -> Result<Matrix<T>, MatrixError>;
+    fn sub(&self, b: &Matrix<T>) -> Matrix<T>;
+    fn try_sub(&self, b: &Matrix<T>) -> Result<Matrix<T>, MatrixError>;
+    fn div_by_const(&self, b: T) -> Matrix<T>;
+    fn map<F: Fn(T) -> T>(&self, f: F) -> Matrix<T>;
+    fn add_column(&self, b: &Matrix<T>) -> Matrix<T>;
+    fn try_add_column(&self, b: &Matrix<T>) -> Result<Matrix<T>, MatrixError>;
+    fn sum_columns(&self) -> Matrix<T>;
+    fn hstack(matrices: &[Matrix<T>]) -> Self;
+    fn try_hstack(matrices: &[Matrix<T>]) -> Result<Self, MatrixError>;
+    fn vstack(matrices: &[Matrix<T>]) -> Self;
+    fn try_vstack(matrices: &[Matrix<T>]) -> Result<Self, MatrixError>;
     fn show(&self);
 }
 
-impl MatrixOps for Matrix {
-    fn new(data: Vec<Vec<f64>>) -> Matrix {
+impl<T: Scalar> MatrixOps<T> for Matrix<T> {
+    fn new(data: Vec<Vec<T>>) -> Matrix<T> {
+        or_panic(Matrix::try_new(data))
+    }
+
+    fn try_new(data: Vec<Vec<T>>) -> Result<Matrix<T>, MatrixError> {
         let rows = data.len();
-        assert!(rows > 0);
+        if rows == 0 || data[0].is_empty() {
+            return Err(MatrixError::Empty);
+        }
         let cols = data[0].len();
-        assert!(cols > 0);
-        Matrix { data, rows, cols }
+        let mut flat = Vec::with_capacity(rows * cols);
+        for (row, line) in data.iter().enumerate() {
+            if line.len() != cols {
+                return Err(MatrixError::RaggedRows {
+                    row,
+                    expected: cols,
+                    found: line.len(),
+                });
+            }
+            flat.extend_from_slice(line);
+        }
+        Ok(Matrix {
+            data: flat,
+            rows,
+            cols,
+        })
+    }
+
+    fn new_by_rand(rows: usize, cols: usize) -> Matrix<T> {
+        or_panic(Matrix::try_new_by_rand(rows, cols))
     }
 
-    fn new_by_rand(rows: usize, cols: usize) -> Matrix {
-        assert!(rows > 0);
-        assert!(cols > 0);
+    fn try_new_by_rand(rows: usize, cols: usize) -> Result<Matrix<T>, MatrixError> {
+        if rows == 0 || cols == 0 {
+            return Err(MatrixError::Empty);
+        }
         let mut rand = rand::thread_rng();
-        let mut data = Vec::new();
-        for _row in 0..rows {
-            let mut row_data = Vec::new();
-            for _col in 0..cols {
-                let data: f64 = rand.gen();
-                row_data.push(data - 0.5);
-            }
-            data.push(row_data);
+        let mut data = Vec::with_capacity(rows * cols);
+        for _i in 0..rows * cols {
+            let value: f64 = rand.gen();
+            data.push(T::from_f64(value - 0.5));
         }
-        Matrix::new(data)
+        Matrix::try_from_vec(rows, cols, data)
     }
 
-    fn zeros(rows: usize, cols: usize) -> Matrix {
-        assert!(rows > 0);
-        assert!(cols > 0);
-        let mut data = Vec::new();
-        for _row in 0..rows {
-            let mut row_data = Vec::new();
-            for _col in 0..cols {
-                row_data.push(0.0);
-            }
-            data.push(row_data);
-        }
-        Matrix::new(data)
+    fn zeros(rows: usize, cols: usize) -> Matrix<T> {
+        or_panic(Matrix::try_zeros(rows, cols))
     }
 
-    fn ones(rows: usize, cols: usize) -> Matrix {
-        assert!(rows > 0);
-        assert!(cols > 0);
-        let mut data = Vec::new();
-        for _row in 0..rows {
-            let mut row_data = Vec::new();
-            for _col in 0..cols {
-                row_data.push(1.0);
-            }
-            data.push(row_data);
-        }
-        Matrix::new(data)
+    fn try_zeros(rows: usize, cols: usize) -> Result<Matrix<T>, MatrixError> {
+        Matrix::try_from_vec(rows, cols, vec![T::zero(); rows * cols])
+    }
+
+    fn ones(rows: usize, cols: usize) -> Matrix<T> {
+        or_panic(Matrix::try_ones(rows, cols))
+    }
+
+    fn try_ones(rows: usize, cols: usize) -> Result<Matrix<T>, MatrixError> {
+        Matrix::try_from_vec(rows, cols, vec![T::one(); rows * cols])
     }
 
     fn activate_sigmoid(&mut self) {
-        for row in 0..self.rows {
-            for col in 0..self.cols {
-                self.data[row][col] = Matrix::sigmoid(self.data[row][col]);
-            }
+        for value in self.data.iter_mut() {
+            *value = Self::sigmoid(*value);
         }
     }
 
-    fn sigmoid(x: f64) -> f64 {
-        let e = std::f64::consts::E;
-        let res = 1.0 / (1.0 + e.powf(-1.0 * x));
-        res
+    fn sigmoid(x: T) -> T {
+        let e = T::from_f64(std::f64::consts::E);
+        T::one() / (T::one() + e.powf(-x))
     }
 
-    fn transpose(&self) -> Matrix {
+    fn transpose(&self) -> Matrix<T> {
         let new_row = self.cols;
         let new_col = self.rows;
-        let mut new_data = Vec::new();
+        let mut new_data = vec![T::zero(); self.data.len()];
 
-        for row in 0..new_row {
-            let mut line = Vec::new();
-            for col in 0..new_col {
-                line.push(self.data[col][row]);
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                new_data[col * new_col + row] = self.data[row * self.cols + col];
             }
-            new_data.push(line);
         }
 
         Matrix {
@@ -122,162 +411,372 @@ impl MatrixOps for Matrix {
         }
     }
 
-    fn dot(&self, b: &Matrix) -> f64 {
-        assert_eq!(self.rows, b.rows);
-        assert_eq!(self.cols, b.cols);
-        let mut res = 0.0;
-        for i in 0..self.rows {
-            for j in 0..self.cols {
-                res += self.data[i][j] * b.data[i][j];
-            }
+    fn dot(&self, b: &Matrix<T>) -> T {
+        or_panic(self.try_dot(b))
+    }
+
+    fn try_dot(&self, b: &Matrix<T>) -> Result<T, MatrixError> {
+        self.check_same_shape(b, "dot")?;
+        let mut res = T::zero();
+        for (x, y) in self.data.iter().zip(b.data.iter()) {
+            res += *x * *y;
         }
-        res
+        Ok(res)
     }
 
-    fn dot_const(&self, b: &f64) -> f64 {
-        let mut res = 0.0;
-        for i in 0..self.rows {
-            for j in 0..self.cols {
-                res += self.data[i][j] * b;
-            }
+    fn dot_const(&self, b: &T) -> T {
+        let mut res = T::zero();
+        for x in self.data.iter() {
+            res += *x * *b;
         }
         res
     }
 
-    fn product(&self, b: &Matrix) -> Matrix {
-        assert_eq!(self.cols, b.rows);
-        let output_rows = self.rows;
-        let output_cols = b.cols;
-        let mut new_data: Vec<Vec<f64>> = Vec::new();
-        for row in 0..output_rows {
-            let mut new_line: Vec<f64> = Vec::new();
-            for col in 0..output_cols {
-                let mut res: f64 = 0.0;
-                for row_self in 0..self.cols {
-                    res += self.data[row][row_self] * b.data[row_self][col]
-                }
-                new_line.push(res);
-            }
-            new_data.push(new_line);
-        }
+    fn product(&self, b: &Matrix<T>) -> Matrix<T> {
+        or_panic(self.try_product(b))
+    }
 
-        Matrix {
-            data: new_data,
-            rows: output_rows,
-            cols: output_cols,
+    fn try_product(&self, b: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        if self.cols != b.rows {
+            return Err(MatrixError::ShapeMismatch {
+                op: "product",
+                left: self.shape(),
+                right: b.shape(),
+            });
         }
+        Ok(gemm::blocked(self, b))
     }
 
-    fn mul(&self, b: &Matrix) -> Matrix {
-        assert_eq!(self.rows, b.rows);
-        assert_eq!(self.cols, b.cols);
-        let mut data = Vec::new();
+    // self * b^T without materializing the transpose
+    fn product_transposed(&self, b: &Matrix<T>) -> Matrix<T> {
+        or_panic(self.try_product_transposed(b))
+    }
 
-        for row in 0..self.rows {
-            let mut line = Vec::new();
-            for col in 0..self.cols {
-                line.push(self.data[row][col] * b.data[row][col]);
-            }
-            data.push(line);
-        }
-        Matrix {
-            data,
-            rows: self.rows,
-            cols: self.cols,
+    fn try_product_transposed(&self, b: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        if self.cols != b.cols {
+            return Err(MatrixError::ShapeMismatch {
+                op: "product_transposed",
+                left: self.shape(),
+                right: b.shape(),
+            });
         }
+        Ok(gemm::product_transposed(self, b))
     }
 
-    fn mul_const(&self, b: f64) -> Matrix {
-        let mut data = Vec::new();
+    fn mul(&self, b: &Matrix<T>) -> Matrix<T> {
+        or_panic(self.try_mul(b))
+    }
 
-        for row in 0..self.rows {
-            let mut line = Vec::new();
-            for col in 0..self.cols {
-                line.push(self.data[row][col] * b);
-            }
-            data.push(line);
-        }
+    fn try_mul(&self, b: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        self.zip_with(b, "mul", |x, y| x * y)
+    }
+
+    fn mul_const(&self, b: T) -> Matrix<T> {
+        self.map(|x| x * b)
+    }
+
+    fn add(&self, b: &Matrix<T>) -> Matrix<T> {
+        or_panic(self.try_add(b))
+    }
+
+    fn try_add(&self, b: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        self.zip_with(b, "add", |x, y| x + y)
+    }
+
+    fn sub(&self, b: &Matrix<T>) -> Matrix<T> {
+        or_panic(self.try_sub(b))
+    }
+
+    fn try_sub(&self, b: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        self.zip_with(b, "sub", |x, y| x - y)
+    }
+
+    fn div_by_const(&self, b: T) -> Matrix<T> {
+        self.map(|x| x / b)
+    }
+
+    fn map<F: Fn(T) -> T>(&self, f: F) -> Matrix<T> {
         Matrix {
-            data,
+            data: self.data.iter().map(|x| f(*x)).collect(),
             rows: self.rows,
             cols: self.cols,
         }
     }
 
-    fn add(&self, b: &Matrix) -> Matrix {
-        assert_eq!(self.rows, b.rows);
-        assert_eq!(self.cols, b.cols);
+    fn add_column(&self, b: &Matrix<T>) -> Matrix<T> {
+        or_panic(self.try_add_column(b))
+    }
 
-        let mut data = Vec::new();
-        for row in 0..self.rows {
-            let mut line = Vec::new();
-            for col in 0..self.cols {
-                line.push(self.data[row][col] + b.data[row][col]);
+    fn try_add_column(&self, b: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        let mut res = self.clone();
+        res.try_add_column_assign(b)?;
+        Ok(res)
+    }
+
+    fn sum_columns(&self) -> Matrix<T> {
+        let mut data = Vec::with_capacity(self.rows);
+        for line in self.data.chunks(self.cols) {
+            let mut sum = T::zero();
+            for x in line.iter() {
+                sum += *x;
             }
-            data.push(line);
+            data.push(sum);
         }
         Matrix {
             data,
             rows: self.rows,
-            cols: self.cols,
+            cols: 1,
         }
     }
 
-    fn sub(&self, b: &Matrix) -> Matrix {
-        assert_eq!(self.rows, b.rows);
-        assert_eq!(self.cols, b.cols);
+    fn hstack(matrices: &[Matrix<T>]) -> Matrix<T> {
+        or_panic(Matrix::try_hstack(matrices))
+    }
 
-        let mut data = Vec::new();
-        for row in 0..self.rows {
-            let mut line = Vec::new();
-            for col in 0..self.cols {
-                line.push(self.data[row][col] - b.data[row][col]);
+    fn try_hstack(matrices: &[Matrix<T>]) -> Result<Matrix<T>, MatrixError> {
+        if matrices.is_empty() {
+            return Err(MatrixError::Empty);
+        }
+        let rows = matrices[0].rows;
+        let mut cols = 0;
+        for matrix in matrices.iter() {
+            if matrix.rows != rows {
+                return Err(MatrixError::ShapeMismatch {
+                    op: "hstack",
+                    left: matrices[0].shape(),
+                    right: matrix.shape(),
+                });
             }
-            data.push(line);
+            cols += matrix.cols;
         }
-        Matrix {
-            data,
-            rows: self.rows,
-            cols: self.cols,
+        let mut data = Vec::with_capacity(rows * cols);
+        for row in 0..rows {
+            for matrix in matrices.iter() {
+                data.extend_from_slice(matrix.row(row));
+            }
         }
+        Matrix::try_from_vec(rows, cols, data)
     }
 
-    fn div_by_const(&self, b: f64) -> Matrix {
-        let mut data = Vec::new();
+    fn vstack(matrices: &[Matrix<T>]) -> Matrix<T> {
+        or_panic(Matrix::try_vstack(matrices))
+    }
 
-        for row in 0..self.rows {
-            let mut line = Vec::new();
-            for col in 0..self.cols {
-                line.push(self.data[row][col] / b);
-            }
-            data.push(line);
+    fn try_vstack(matrices: &[Matrix<T>]) -> Result<Matrix<T>, MatrixError> {
+        if matrices.is_empty() {
+            return Err(MatrixError::Empty);
         }
-        Matrix {
-            data,
-            rows: self.rows,
-            cols: self.cols,
+        let cols = matrices[0].cols;
+        let mut data = Vec::new();
+        for matrix in matrices.iter() {
+            if matrix.cols != cols {
+                return Err(MatrixError::ShapeMismatch {
+                    op: "vstack",
+                    left: matrices[0].shape(),
+                    right: matrix.shape(),
+                });
+            }
+            data.extend_from_slice(&matrix.data);
         }
+        let rows = data.len() / cols;
+        Matrix::try_from_vec(rows, cols, data)
     }
 
     fn show(&self) {
-        print!(
-            "[Matrix] Matrix Shape: {}x{} Data:\n[",
-            self.rows, self.cols
+        println!(
+            "[Matrix] Matrix Shape: {}x{} Data:\n{}",
+            self.rows, self.cols, self
         );
+    }
+}
+
+impl<T: Scalar> fmt::Display for Matrix<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
         for row in 0..self.rows {
-            print!("[");
+            write!(f, "[")?;
             for col in 0..self.cols {
-                print!("{}", self.data[row][col]);
+                write!(f, "{}", self.get(row, col))?;
                 if col != self.cols - 1 {
-                    print!(",");
+                    write!(f, ",")?;
                 }
             }
-            print!("]");
+            write!(f, "]")?;
             if row != self.rows - 1 {
-                print!(",\n");
+                writeln!(f, ",")?;
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+// exact comparison, see approx_eq and relative_eq for floating point tolerance
+impl<T: Scalar> PartialEq for Matrix<T> {
+    fn eq(&self, other: &Matrix<T>) -> bool {
+        self.rows == other.rows && self.cols == other.cols && self.data == other.data
+    }
+}
+
+impl<T: Scalar> ops::Index<(usize, usize)> for Matrix<T> {
+    type Output = T;
+
+    fn index(&self, (row, col): (usize, usize)) -> &T {
+        assert!(row < self.rows && col < self.cols);
+        &self.data[row * self.cols + col]
+    }
+}
+
+impl<T: Scalar> ops::IndexMut<(usize, usize)> for Matrix<T> {
+    fn index_mut(&mut self, (row, col): (usize, usize)) -> &mut T {
+        self.get_mut(row, col)
+    }
+}
+
+// Element-wise operators. Like the MatrixOps methods they panic on a shape
+// mismatch; owned left operands are updated in place instead of allocating.
+macro_rules! elementwise_op {
+    ($op:ident, $method:ident, $op_assign:ident, $method_assign:ident, $try_assign:ident) => {
+        impl<T: Scalar> ops::$op_assign<&Matrix<T>> for Matrix<T> {
+            fn $method_assign(&mut self, b: &Matrix<T>) {
+                or_panic(self.$try_assign(b))
+            }
+        }
+
+        impl<T: Scalar> ops::$op_assign<Matrix<T>> for Matrix<T> {
+            fn $method_assign(&mut self, b: Matrix<T>) {
+                or_panic(self.$try_assign(&b))
+            }
+        }
+
+        impl<T: Scalar> ops::$op_assign<T> for Matrix<T> {
+            fn $method_assign(&mut self, b: T) {
+                for x in self.data.iter_mut() {
+                    ops::$op_assign::$method_assign(x, b);
+                }
+            }
+        }
+
+        impl<T: Scalar> ops::$op<&Matrix<T>> for Matrix<T> {
+            type Output = Matrix<T>;
+
+            fn $method(mut self, b: &Matrix<T>) -> Matrix<T> {
+                ops::$op_assign::$method_assign(&mut self, b);
+                self
+            }
+        }
+
+        impl<T: Scalar> ops::$op<Matrix<T>> for Matrix<T> {
+            type Output = Matrix<T>;
+
+            fn $method(mut self, b: Matrix<T>) -> Matrix<T> {
+                ops::$op_assign::$method_assign(&mut self, &b);
+                self
             }
         }
-        print!("]\n");
+
+        impl<T: Scalar> ops::$op<&Matrix<T>> for &Matrix<T> {
+            type Output = Matrix<T>;
+
+            fn $method(self, b: &Matrix<T>) -> Matrix<T> {
+                ops::$op::$method(self.clone(), b)
+            }
+        }
+
+        impl<T: Scalar> ops::$op<Matrix<T>> for &Matrix<T> {
+            type Output = Matrix<T>;
+
+            fn $method(self, b: Matrix<T>) -> Matrix<T> {
+                ops::$op::$method(self.clone(), &b)
+            }
+        }
+
+        impl<T: Scalar> ops::$op<T> for Matrix<T> {
+            type Output = Matrix<T>;
+
+            fn $method(mut self, b: T) -> Matrix<T> {
+                ops::$op_assign::$method_assign(&mut self, b);
+                self
+            }
+        }
+
+        impl<T: Scalar> ops::$op<T> for &Matrix<T> {
+            type Output = Matrix<T>;
+
+            fn $method(self, b: T) -> Matrix<T> {
+                ops::$op::$method(self.clone(), b)
+            }
+        }
+    };
+}
+
+elementwise_op!(Add, add, AddAssign, add_assign, try_add_assign);
+elementwise_op!(Sub, sub, SubAssign, sub_assign, try_sub_assign);
+elementwise_op!(Mul, mul, MulAssign, mul_assign, try_mul_assign);
+
+// scalar * matrix, only for the primitive floats since T * Matrix<T> can not
+// be implemented for a generic T
+macro_rules! scalar_lhs_mul {
+    ($t:ty) => {
+        impl ops::Mul<&Matrix<$t>> for $t {
+            type Output = Matrix<$t>;
+
+            fn mul(self, b: &Matrix<$t>) -> Matrix<$t> {
+                b * self
+            }
+        }
+
+        impl ops::Mul<Matrix<$t>> for $t {
+            type Output = Matrix<$t>;
+
+            fn mul(self, b: Matrix<$t>) -> Matrix<$t> {
+                b * self
+            }
+        }
+    };
+}
+
+scalar_lhs_mul!(f32);
+scalar_lhs_mul!(f64);
+
+impl<T: Scalar> ops::DivAssign<T> for Matrix<T> {
+    fn div_assign(&mut self, b: T) {
+        for x in self.data.iter_mut() {
+            *x /= b;
+        }
+    }
+}
+
+impl<T: Scalar> ops::Div<T> for Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn div(mut self, b: T) -> Matrix<T> {
+        self /= b;
+        self
+    }
+}
+
+impl<T: Scalar> ops::Div<T> for &Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn div(self, b: T) -> Matrix<T> {
+        self.clone() / b
+    }
+}
+
+impl<T: Scalar> ops::Neg for Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn neg(mut self) -> Matrix<T> {
+        self.map_in_place(|x| -x);
+        self
+    }
+}
+
+impl<T: Scalar> ops::Neg for &Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn neg(self) -> Matrix<T> {
+        -self.clone()
     }
 }
 
@@ -285,7 +784,8 @@ impl MatrixOps for Matrix {
 mod matrix_tests {
 
     use super::Matrix;
-    use crate::matrix::MatrixOps;
+    use crate::matrix::{MatrixError, MatrixOps};
+    use crate::scalar::{Fixed, Scalar};
 
     #[test]
     fn test_show() {
@@ -460,4 +960,264 @@ mod matrix_tests {
         c.show();
         println!("********************************");
     }
+
+    #[test]
+    fn test_map() {
+        println!("********[TEST] Test Matrix Map Function********");
+        let a = Matrix::new(vec![vec![1.0, 4.0], vec![9.0, 16.0]]);
+        let c = a.map(f64::sqrt);
+        c.show();
+        assert_eq!(c.data, vec![1.0, 2.0, 3.0, 4.0]);
+        println!("********************************");
+    }
+
+    #[test]
+    fn test_add_column() {
+        println!("********[TEST] Test Matrix Add Column Function********");
+        let a = Matrix::new(vec![vec![1.0, 2.0], vec![3.0, 4.0]]);
+        let b = Matrix::new(vec![vec![0.5], vec![-1.0]]);
+        let c = a.add_column(&b);
+        c.show();
+        assert_eq!(c.data, vec![1.5, 2.5, 2.0, 3.0]);
+        println!("********************************");
+    }
+
+    #[test]
+    fn test_sum_columns() {
+        println!("********[TEST] Test Matrix Sum Columns Function********");
+        let a = Matrix::new(vec![vec![1.0, 2.0, 3.0], vec![-1.0, 0.5, 0.5]]);
+        let c = a.sum_columns();
+        c.show();
+        assert_eq!(c.data, vec![6.0, 0.0]);
+        println!("********************************");
+    }
+
+    #[test]
+    fn test_stack() {
+        println!("********[TEST] Test Matrix Stack Function********");
+        let a = Matrix::new(vec![vec![1.0, 2.0]]);
+        let b = Matrix::new(vec![vec![3.0, 4.0]]);
+        let v = Matrix::vstack(&[a.clone(), b.clone()]);
+        v.show();
+        assert_eq!(v.data, vec![1.0, 2.0, 3.0, 4.0]);
+        let h = Matrix::hstack(&[a.transpose(), b.transpose()]);
+        h.show();
+        assert_eq!(h.data, vec![1.0, 3.0, 2.0, 4.0]);
+        println!("********************************");
+    }
+
+    #[test]
+    fn test_try_shape_errors() {
+        println!("********[TEST] Test Matrix Try Functions********");
+        let a = Matrix::new(vec![vec![1.0, 2.0, 3.0]]);
+        let b = Matrix::new(vec![vec![1.0], vec![2.0]]);
+        let err = a.try_add(&b).unwrap_err();
+        println!("{}", err);
+        assert_eq!(
+            err,
+            MatrixError::ShapeMismatch {
+                op: "add",
+                left: (1, 3),
+                right: (2, 1)
+            }
+        );
+        assert!(a.try_sub(&b).is_err());
+        assert!(a.try_mul(&b).is_err());
+        assert!(a.try_dot(&b).is_err());
+        assert!(a.try_product_transposed(&b).is_err());
+        assert!(a.try_add_column(&b).is_err());
+        assert!(Matrix::try_vstack(&[a.clone(), b.clone()]).is_err());
+        assert!(Matrix::try_hstack(&[a.clone(), b.clone()]).is_err());
+        match a.try_product(&b) {
+            Err(MatrixError::ShapeMismatch { op, left, right }) => {
+                assert_eq!(op, "product");
+                assert_eq!(left, (1, 3));
+                assert_eq!(right, (2, 1));
+            }
+            other => panic!("expected shape mismatch, got {:?}", other),
+        }
+        assert_eq!(a.try_product(&a.transpose()).unwrap().data, vec![14.0]);
+        println!("********************************");
+    }
+
+    #[test]
+    fn test_try_new_errors() {
+        println!("********[TEST] Test Matrix Try New Function********");
+        assert_eq!(
+            Matrix::<f64>::try_new(vec![]).unwrap_err(),
+            MatrixError::Empty
+        );
+        assert_eq!(
+            Matrix::<f64>::try_new(vec![vec![]]).unwrap_err(),
+            MatrixError::Empty
+        );
+        assert_eq!(
+            Matrix::<f64>::try_zeros(0, 3).unwrap_err(),
+            MatrixError::Empty
+        );
+        assert_eq!(
+            Matrix::try_new(vec![vec![1.0, 2.0], vec![3.0]]).unwrap_err(),
+            MatrixError::RaggedRows {
+                row: 1,
+                expected: 2,
+                found: 1
+            }
+        );
+        assert_eq!(
+            Matrix::try_from_vec(2, 2, vec![1.0]).unwrap_err(),
+            MatrixError::InvalidLength {
+                rows: 2,
+                cols: 2,
+                len: 1
+            }
+        );
+        println!("********************************");
+    }
+
+    #[test]
+    #[should_panic(expected = "shape mismatch in product: 1x3 and 2x1")]
+    fn test_product_panics_with_shapes() {
+        let a = Matrix::new(vec![vec![1.0, 2.0, 3.0]]);
+        let b = Matrix::new(vec![vec![1.0], vec![2.0]]);
+        a.product(&b);
+    }
+
+    #[test]
+    fn test_operators() {
+        println!("********[TEST] Test Matrix Operators********");
+        let a = Matrix::new(vec![vec![1.0, 2.0], vec![3.0, 4.0]]);
+        let b = Matrix::new(vec![vec![0.5, -1.0], vec![2.0, 0.0]]);
+        assert_eq!(&a + &b, a.add(&b));
+        assert_eq!(&a - &b, a.sub(&b));
+        assert_eq!(&a * &b, a.mul(&b));
+        assert_eq!(&a * 2.0, a.mul_const(2.0));
+        assert_eq!(2.0 * &a, a.mul_const(2.0));
+        assert_eq!(&a / 2.0, a.div_by_const(2.0));
+        assert_eq!((&a + 1.0).data, vec![2.0, 3.0, 4.0, 5.0]);
+        assert_eq!((&a - 1.0).data, vec![0.0, 1.0, 2.0, 3.0]);
+        assert_eq!((-&a).data, vec![-1.0, -2.0, -3.0, -4.0]);
+        assert_eq!(a.clone() + b.clone(), &a + &b);
+        assert_eq!(a.clone() - &b, &a - b.clone());
+        println!("{}", &a * &b);
+        println!("********************************");
+    }
+
+    #[test]
+    fn test_assign_operators() {
+        println!("********[TEST] Test Matrix Assign Operators********");
+        let a = Matrix::new(vec![vec![1.0, 2.0], vec![3.0, 4.0]]);
+        let b = Matrix::new(vec![vec![0.5, -1.0], vec![2.0, 0.0]]);
+        let mut c = a.clone();
+        let ptr = c.data.as_ptr();
+        c += &b;
+        c -= &b;
+        c *= &b;
+        c *= 2.0;
+        c /= 2.0;
+        c += 1.0;
+        c -= 1.0;
+        c.add_scaled(0.5, &b);
+        assert_eq!(c, a.mul(&b).add(&b.mul_const(0.5)));
+        // in-place updates and owned operands keep the original buffer
+        let c = -(c + &a);
+        assert_eq!(c.data.as_ptr(), ptr);
+
+        let mut d = a.clone();
+        assert!(d.try_add_assign(&a.transpose()).is_ok());
+        assert!(d.try_sub_assign(&Matrix::zeros(1, 2)).is_err());
+        d.map_in_place(|x| x * 0.0);
+        assert_eq!(d, Matrix::zeros(2, 2));
+        println!("********************************");
+    }
+
+    #[test]
+    #[should_panic(expected = "shape mismatch in add: 2x2 and 1x2")]
+    fn test_operator_panics_with_shapes() {
+        let a = Matrix::new(vec![vec![1.0, 2.0], vec![3.0, 4.0]]);
+        let _ = a + Matrix::zeros(1, 2);
+    }
+
+    #[test]
+    fn test_index() {
+        println!("********[TEST] Test Matrix Index********");
+        let mut a = Matrix::new(vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]]);
+        assert_eq!(a[(1, 2)], 6.0);
+        assert_eq!(a[(0, 1)], a.get(0, 1));
+        a[(1, 0)] += 10.0;
+        assert_eq!(a.get(1, 0), 14.0);
+        println!("********************************");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_index_out_of_bounds() {
+        let a: Matrix = Matrix::zeros(2, 3);
+        let _ = a[(0, 3)];
+    }
+
+    #[test]
+    fn test_equality() {
+        println!("********[TEST] Test Matrix Equality********");
+        let a = Matrix::new(vec![vec![1.0, 2.0, 3.0]]);
+        let b = Matrix::new(vec![vec![1.0 + 1e-12, 2.0, 3.0 - 1e-12]]);
+        assert_eq!(a, a.clone());
+        assert_ne!(a, b);
+        assert_ne!(a, a.transpose());
+        assert!(a.approx_eq(&b, 1e-9));
+        assert!(!a.approx_eq(&b, 1e-15));
+        assert!(!a.approx_eq(&a.transpose(), 1.0));
+        assert!(a.max_abs_diff(&a.transpose()).is_err());
+
+        let big = Matrix::new(vec![vec![1e6, 2e6]]);
+        let close = Matrix::new(vec![vec![1e6 + 0.5, 2e6]]);
+        assert!(big.relative_eq(&close, 1e-6));
+        assert!(!big.approx_eq(&close, 1e-6));
+        println!("********************************");
+    }
+
+    #[test]
+    fn test_display() {
+        let a = Matrix::new(vec![vec![1.0, 2.5], vec![-3.0, 4.0]]);
+        assert_eq!(format!("{}", a), "[[1,2.5],\n[-3,4]]");
+    }
+
+    #[test]
+    fn test_convert() {
+        println!("********[TEST] Test Matrix Convert Function********");
+        let a = Matrix::new(vec![vec![0.1, -2.5], vec![3.0, 1e-3]]);
+        let b: Matrix<f32> = a.convert();
+        b.show();
+        assert_eq!(b.data, vec![0.1f32, -2.5, 3.0, 1e-3]);
+        assert!(b.convert::<f64>().approx_eq(&a, 1e-7));
+        let c: Matrix<Fixed> = a.convert();
+        assert!(c.convert::<f64>().approx_eq(&a, 1e-9));
+        println!("********************************");
+    }
+
+    #[test]
+    fn test_generic_precisions() {
+        println!("********[TEST] Test Matrix Generic Precisions********");
+        let a: Matrix = Matrix::new_by_rand(5, 7);
+        let b: Matrix = Matrix::new_by_rand(7, 3);
+        let expected = a.product(&b);
+
+        let res32 = a.convert::<f32>().product(&b.convert());
+        assert!(res32.convert().approx_eq(&expected, 1e-6));
+        let res32 = a
+            .convert::<f32>()
+            .product_transposed(&b.transpose().convert());
+        assert!(res32.convert().approx_eq(&expected, 1e-6));
+
+        let fixed = a.convert::<Fixed>().product(&b.convert());
+        assert!(fixed.convert().approx_eq(&expected, 1e-8));
+        let sum = (a.convert::<Fixed>() + a.convert::<Fixed>()) * Fixed::from_f64(0.5);
+        assert!(sum.convert().approx_eq(&a, 1e-9));
+
+        let mut s = a.convert::<f32>();
+        s.activate_sigmoid();
+        let mut expected = a.clone();
+        expected.activate_sigmoid();
+        assert!(s.convert().approx_eq(&expected, 1e-6));
+        println!("********************************");
+    }
 }
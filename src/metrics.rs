@@ -0,0 +1,379 @@
+use crate::matrix::{Matrix, MatrixError};
+use crate::scalar::Scalar;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClassMetrics {
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+    // number of samples whose label is this class
+    pub support: usize,
+}
+
+// Classification report over a whole evaluation set. Outputs and labels have
+// one column per sample, the predicted and actual class are the row of the
+// largest value, so both one-hot and 0.01/0.99 labels work.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    // confusion[actual][predicted]
+    pub(crate) confusion: Vec<Vec<usize>>,
+    // position of the actual class when the outputs are sorted descending
+    pub(crate) ranks: Vec<usize>,
+}
+
+// index of the largest value of every column, the first one wins on ties
+pub fn argmax_columns<T: Scalar>(m: &Matrix<T>) -> Vec<usize> {
+    (0..m.cols)
+        .map(|col| {
+            let mut best = 0;
+            for row in 1..m.rows {
+                if m.get(row, col) > m.get(best, col) {
+                    best = row;
+                }
+            }
+            best
+        })
+        .collect()
+}
+
+fn ratio(a: usize, b: usize) -> f64 {
+    if b == 0 {
+        0.0
+    } else {
+        a as f64 / b as f64
+    }
+}
+
+impl Report {
+    pub fn new<T: Scalar>(outputs: &Matrix<T>, labels: &Matrix<T>) -> Result<Report, MatrixError> {
+        if outputs.shape() != labels.shape() {
+            return Err(MatrixError::ShapeMismatch {
+                op: "report",
+                left: outputs.shape(),
+                right: labels.shape(),
+            });
+        }
+        if outputs.rows == 0 {
+            return Err(MatrixError::Empty);
+        }
+        let predicted = argmax_columns(outputs);
+        let actual = argmax_columns(labels);
+        let mut report = Report::from_classes(&predicted, &actual, outputs.rows)?;
+        report.ranks = (0..outputs.cols)
+            .map(|col| {
+                let target = outputs.get(actual[col], col);
+                // ties are resolved in favour of the lower index like argmax
+                (0..outputs.rows)
+                    .filter(|&row| {
+                        let value = outputs.get(row, col);
+                        value > target || (value == target && row < actual[col])
+                    })
+                    .count()
+            })
+            .collect();
+        Ok(report)
+    }
+
+    // report from class indices only, a wrong prediction carries no rank so it
+    // never counts for top-k accuracy
+    pub fn from_classes(
+        predicted: &[usize],
+        actual: &[usize],
+        num_classes: usize,
+    ) -> Result<Report, MatrixError> {
+        if predicted.len() != actual.len() {
+            return Err(MatrixError::CountMismatch {
+                samples: predicted.len(),
+                labels: actual.len(),
+            });
+        }
+        if let Some(&class) = predicted
+            .iter()
+            .chain(actual.iter())
+            .find(|&&class| class >= num_classes)
+        {
+            return Err(MatrixError::ClassOutOfRange { class, num_classes });
+        }
+        let mut confusion = vec![vec![0; num_classes]; num_classes];
+        for (&p, &a) in predicted.iter().zip(actual.iter()) {
+            confusion[a][p] += 1;
+        }
+        let ranks = predicted
+            .iter()
+            .zip(actual.iter())
+            .map(|(p, a)| if p == a { 0 } else { num_classes })
+            .collect();
+        Ok(Report { confusion, ranks })
+    }
+
+    pub fn num_classes(&self) -> usize {
+        self.confusion.len()
+    }
+
+    pub fn total(&self) -> usize {
+        self.ranks.len()
+    }
+
+    pub fn confusion_matrix(&self) -> &[Vec<usize>] {
+        &self.confusion
+    }
+
+    pub fn accuracy(&self) -> f64 {
+        let correct = (0..self.num_classes()).map(|i| self.confusion[i][i]).sum();
+        ratio(correct, self.total())
+    }
+
+    // fraction of samples whose actual class is among the k largest outputs
+    pub fn top_k_accuracy(&self, k: usize) -> f64 {
+        ratio(
+            self.ranks.iter().filter(|&&rank| rank < k).count(),
+            self.total(),
+        )
+    }
+
+    // precision of a class that is never predicted is reported as 0
+    pub fn class(&self, class: usize) -> ClassMetrics {
+        let correct = self.confusion[class][class];
+        let support = self.confusion[class].iter().sum();
+        let predicted = self.confusion.iter().map(|row| row[class]).sum();
+        let precision = ratio(correct, predicted);
+        let recall = ratio(correct, support);
+        let f1 = if precision + recall == 0.0 {
+            0.0
+        } else {
+            2.0 * precision * recall / (precision + recall)
+        };
+        ClassMetrics {
+            precision,
+            recall,
+            f1,
+            support,
+        }
+    }
+
+    pub fn classes(&self) -> Vec<ClassMetrics> {
+        (0..self.num_classes()).map(|i| self.class(i)).collect()
+    }
+
+    // unweighted mean over the classes that occur as label or prediction
+    pub fn macro_avg(&self) -> ClassMetrics {
+        let present: Vec<bool> = (0..self.num_classes())
+            .map(|i| {
+                self.confusion[i].iter().sum::<usize>() > 0
+                    || self.confusion.iter().any(|row| row[i] > 0)
+            })
+            .collect();
+        let count = present.iter().filter(|&&p| p).count();
+        self.average(|class, _| if present[class] { ratio(1, count) } else { 0.0 })
+    }
+
+    // mean weighted by the support of each class
+    pub fn weighted_avg(&self) -> ClassMetrics {
+        self.average(|_, m| ratio(m.support, self.total()))
+    }
+
+    fn average<F: Fn(usize, &ClassMetrics) -> f64>(&self, weight: F) -> ClassMetrics {
+        let mut avg = ClassMetrics {
+            precision: 0.0,
+            recall: 0.0,
+            f1: 0.0,
+            support: self.total(),
+        };
+        for (class, m) in self.classes().iter().enumerate() {
+            let w = weight(class, m);
+            avg.precision += w * m.precision;
+            avg.recall += w * m.recall;
+            avg.f1 += w * m.f1;
+        }
+        avg
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>12} {:>9} {:>9} {:>9} {:>9}",
+            "class", "precision", "recall", "f1", "support"
+        )?;
+        let row = |f: &mut fmt::Formatter, name: &str, m: ClassMetrics| {
+            writeln!(
+                f,
+                "{:>12} {:>9.4} {:>9.4} {:>9.4} {:>9}",
+                name, m.precision, m.recall, m.f1, m.support
+            )
+        };
+        for (i, m) in self.classes().into_iter().enumerate() {
+            row(f, &i.to_string(), m)?;
+        }
+        writeln!(f)?;
+        row(f, "macro avg", self.macro_avg())?;
+        row(f, "weighted avg", self.weighted_avg())?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:>12} {:>9.4} ({} samples)",
+            "accuracy",
+            self.accuracy(),
+            self.total()
+        )?;
+        for &k in [3, 5].iter().filter(|&&k| k < self.num_classes()) {
+            writeln!(
+                f,
+                "{:>12} {:>9.4}",
+                format!("top-{}", k),
+                self.top_k_accuracy(k)
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(f, "confusion matrix (rows: label, columns: predicted)")?;
+        write!(f, "{:>6}", "")?;
+        for i in 0..self.num_classes() {
+            write!(f, "{:>6}", i)?;
+        }
+        for (i, counts) in self.confusion.iter().enumerate() {
+            writeln!(f)?;
+            write!(f, "{:>6}", i)?;
+            for count in counts {
+                write!(f, "{:>6}", count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use crate::dataset::read_csv_by_path;
+    use crate::matrix::{Matrix, MatrixError, MatrixOps};
+    use crate::metrics::{argmax_columns, Report};
+    use crate::nn::NeuralNetwork;
+
+    #[test]
+    fn test_from_classes() {
+        let predicted = vec![0, 0, 1, 1, 2, 2, 0];
+        let actual = vec![0, 1, 1, 1, 2, 0, 0];
+        let report = Report::from_classes(&predicted, &actual, 3).unwrap();
+        println!("{}", report);
+        assert_eq!(
+            report.confusion_matrix(),
+            &[vec![2, 0, 1], vec![1, 2, 0], vec![0, 0, 1]][..]
+        );
+        assert!((report.accuracy() - 5.0 / 7.0).abs() < 1e-12);
+
+        let zero = report.class(0);
+        assert!((zero.precision - 2.0 / 3.0).abs() < 1e-12);
+        assert!((zero.recall - 2.0 / 3.0).abs() < 1e-12);
+        assert_eq!(zero.support, 3);
+        let two = report.class(2);
+        assert!((two.precision - 0.5).abs() < 1e-12);
+        assert!((two.recall - 1.0).abs() < 1e-12);
+        assert!((two.f1 - 2.0 / 3.0).abs() < 1e-12);
+
+        let macro_avg = report.macro_avg();
+        let expected = (2.0 / 3.0 + 1.0 + 0.5) / 3.0;
+        assert!((macro_avg.precision - expected).abs() < 1e-12);
+        // weighted recall is the accuracy
+        assert!((report.weighted_avg().recall - report.accuracy()).abs() < 1e-12);
+        assert_eq!(report.top_k_accuracy(1), report.accuracy());
+
+        // a class that is neither a label nor a prediction does not count
+        let padded = Report::from_classes(&predicted, &actual, 5).unwrap();
+        assert_eq!(padded.macro_avg(), macro_avg);
+
+        // class indices alone cannot place a miss among the top k
+        let misses = Report::from_classes(&[1, 2, 0], &[0, 0, 1], 3).unwrap();
+        assert_eq!(misses.accuracy(), 0.0);
+        assert_eq!(misses.top_k_accuracy(2), 0.0);
+        assert_eq!(misses.top_k_accuracy(3), 0.0);
+
+        assert_eq!(
+            Report::from_classes(&[0, 1], &[0], 3),
+            Err(MatrixError::CountMismatch {
+                samples: 2,
+                labels: 1
+            })
+        );
+        assert_eq!(
+            Report::from_classes(&[5], &[0], 3),
+            Err(MatrixError::ClassOutOfRange {
+                class: 5,
+                num_classes: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_top_k() {
+        // columns are samples
+        let outputs = Matrix::new(vec![
+            vec![0.7, 0.1, 0.2, 0.5],
+            vec![0.2, 0.3, 0.3, 0.5],
+            vec![0.1, 0.6, 0.5, 0.0],
+        ]);
+        let labels = Matrix::new(vec![
+            vec![1.0, 1.0, 0.0, 0.0],
+            vec![0.0, 0.0, 1.0, 1.0],
+            vec![0.0, 0.0, 0.0, 0.0],
+        ]);
+        assert_eq!(argmax_columns(&outputs), vec![0, 2, 2, 0]);
+        let report = Report::new(&outputs, &labels).unwrap();
+        println!("{}", report);
+        assert_eq!(report.ranks, vec![0, 2, 1, 1]);
+        assert_eq!(report.top_k_accuracy(1), 0.25);
+        assert_eq!(report.top_k_accuracy(2), 0.75);
+        assert_eq!(report.top_k_accuracy(3), 1.0);
+    }
+
+    #[test]
+    fn test_shape_mismatch() {
+        let outputs: Matrix = Matrix::zeros(10, 3);
+        let labels: Matrix = Matrix::zeros(10, 2);
+        assert_eq!(
+            Report::new(&outputs, &labels),
+            Err(MatrixError::ShapeMismatch {
+                op: "report",
+                left: (10, 3),
+                right: (10, 2),
+            })
+        );
+    }
+
+    #[test]
+    fn test_mnist_test_10() {
+        let (train_labels, train_data) =
+            read_csv_by_path::<f64>("data/mnist_train_100.csv").unwrap();
+        let (labels, data) = read_csv_by_path::<f64>("data/mnist_test_10.csv").unwrap();
+        let mut nn: NeuralNetwork = NeuralNetwork::new_seeded(vec![784, 32, 10], 1);
+        nn.fit(&train_data, &train_labels, 1, 5).unwrap();
+        let report = nn.evaluate(&data, &labels).unwrap();
+        println!("{}", report);
+
+        assert_eq!(report.total(), data.len());
+        assert_eq!(report.num_classes(), 10);
+        let cells: usize = report.confusion_matrix().iter().flatten().sum();
+        assert_eq!(cells, data.len());
+        // supports are the label counts of the file
+        let actual = argmax_columns(&Matrix::vstack(&labels).transpose());
+        for (class, m) in report.classes().iter().enumerate() {
+            assert_eq!(m.support, actual.iter().filter(|&&a| a == class).count());
+        }
+        // the report agrees with the predictions of inference
+        let outputs = nn.inference(Matrix::vstack(&data).transpose()).unwrap();
+        let predicted = argmax_columns(&outputs);
+        let correct = predicted.iter().zip(actual.iter()).filter(|(p, a)| p == a);
+        assert_eq!(
+            report.accuracy(),
+            correct.count() as f64 / data.len() as f64
+        );
+        assert_eq!(report.top_k_accuracy(1), report.accuracy());
+        assert!(report.top_k_accuracy(3) >= report.accuracy());
+        assert_eq!(report.top_k_accuracy(10), 1.0);
+
+        let text = report.to_string();
+        assert!(text.contains("macro avg"));
+        assert!(text.contains("top-5"));
+        assert!(text.contains("confusion matrix"));
+    }
+}
@@ -1,80 +1,633 @@
+use crate::activation::Activation;
+use crate::autograd::{AutogradError, Tape};
+use crate::builder::NetworkBuilder;
 use crate::dataset::show_result;
-use crate::layer::Layer;
-use crate::matrix::{Matrix, MatrixOps};
+use crate::dropout::Dropout;
+use crate::init::{seeded_rng, Initializer};
+use crate::layer::{convert_layer, ActivationLayer, Dense, Layer, Mode};
+use crate::loss::{softmax_cross_entropy, softmax_cross_entropy_gradient, Loss};
+use crate::matrix::{Matrix, MatrixError, MatrixOps};
+use crate::metrics::Report;
+use crate::normalization::{BatchNorm, LayerNorm};
+use crate::optimizer::{from_state, Optimizer, Sgd};
+use crate::regularization::{Clipping, Regularizer};
+use crate::scalar::Scalar;
+use crate::serialization::{
+    read_checkpoint, read_model, write_checkpoint, write_model, ModelError,
+};
+use rand::Rng;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+pub(crate) const EVAL_BATCH_SIZE: usize = 256;
 
 #[derive(Debug)]
-pub struct NeuralNetwork {
-    lr: f64,
-    layers: Vec<Layer>,
+pub struct NeuralNetwork<T = f64> {
+    pub(crate) layers: Vec<Box<dyn Layer<T>>>,
+    pub(crate) optimizer: Box<dyn Optimizer<T>>,
+    pub(crate) loss: Loss,
+    pub(crate) clipping: Clipping,
+}
+
+impl<T: Scalar> Clone for NeuralNetwork<T> {
+    fn clone(&self) -> NeuralNetwork<T> {
+        NeuralNetwork {
+            layers: self.layers.clone(),
+            optimizer: self.optimizer.clone(),
+            loss: self.loss,
+            clipping: self.clipping,
+        }
+    }
 }
 
-impl NeuralNetwork {
-    pub fn new(shape: Vec<usize>) -> NeuralNetwork {
-        let mut layers = Vec::new();
+impl<T: Scalar> NeuralNetwork<T> {
+    pub fn from_layers(layers: Vec<Box<dyn Layer<T>>>) -> NeuralNetwork<T> {
+        NeuralNetwork {
+            layers,
+            optimizer: Box::new(Sgd::new(0.3)),
+            loss: Loss::Mse,
+            clipping: Clipping::None,
+        }
+    }
+
+    // dense layers, each followed by a sigmoid
+    pub fn new(shape: Vec<usize>) -> NeuralNetwork<T> {
+        let mut layers: Vec<Box<dyn Layer<T>>> = Vec::new();
+        let len = shape.len();
+        for i in 1..len {
+            layers.push(Box::new(Dense::new_by_rand(shape[i - 1], shape[i])));
+            layers.push(Box::new(ActivationLayer::new(Activation::Sigmoid)));
+        }
+        NeuralNetwork::from_layers(layers)
+    }
+
+    pub fn new_without_bias(shape: Vec<usize>) -> NeuralNetwork<T> {
+        let mut layers: Vec<Box<dyn Layer<T>>> = Vec::new();
         let len = shape.len();
         for i in 1..len {
-            layers.push(Layer::new_by_rand(shape[i - 1], shape[i]))
+            layers.push(Box::new(Dense::new_by_rand_without_bias(
+                shape[i - 1],
+                shape[i],
+            )));
+            layers.push(Box::new(ActivationLayer::new(Activation::Sigmoid)));
         }
-        NeuralNetwork { lr: 0.3, layers }
+        NeuralNetwork::from_layers(layers)
     }
-    pub fn inference(&self, input: Matrix) -> Matrix {
+
+    pub fn new_with_activations(
+        shape: Vec<usize>,
+        activations: Vec<Activation>,
+    ) -> NeuralNetwork<T> {
+        NeuralNetwork::new_with_init(
+            shape,
+            activations,
+            Initializer::default(),
+            &mut rand::thread_rng(),
+        )
+    }
+
+    // sigmoid network whose weights only depend on the seed
+    pub fn new_seeded(shape: Vec<usize>, seed: u64) -> NeuralNetwork<T> {
+        let activations = vec![Activation::Sigmoid; shape.len().saturating_sub(1)];
+        NeuralNetwork::new_with_init(
+            shape,
+            activations,
+            Initializer::default(),
+            &mut seeded_rng(seed),
+        )
+    }
+
+    // every layer is drawn from `initializer` in order, so the same rng state
+    // gives the same weights, panics unless there is one activation per dense
+    // layer, NeuralNetwork::builder() reports bad shapes as a BuildError instead
+    pub fn new_with_init<R: Rng + ?Sized>(
+        shape: Vec<usize>,
+        activations: Vec<Activation>,
+        initializer: Initializer,
+        rng: &mut R,
+    ) -> NeuralNetwork<T> {
+        assert_eq!(shape.len(), activations.len() + 1);
+        let mut layers: Vec<Box<dyn Layer<T>>> = Vec::new();
+        let len = shape.len();
+        for i in 1..len {
+            layers.push(Box::new(Dense::new_by_init(
+                shape[i - 1],
+                shape[i],
+                initializer,
+                rng,
+            )));
+            layers.push(Box::new(ActivationLayer::new(activations[i - 1])));
+        }
+        NeuralNetwork::from_layers(layers)
+    }
+
+    // declares the layers one by one, see NetworkBuilder
+    pub fn builder() -> NetworkBuilder<T> {
+        NetworkBuilder::new()
+    }
+
+    pub fn layers(&self) -> &[Box<dyn Layer<T>>] {
+        &self.layers
+    }
+
+    // layer `index` as its concrete type, None if it is another kind of layer
+    pub fn layer<L: Layer<T> + 'static>(&self, index: usize) -> Option<&L> {
+        self.layers.get(index)?.as_any().downcast_ref::<L>()
+    }
+
+    pub fn layer_mut<L: Layer<T> + 'static>(&mut self, index: usize) -> Option<&mut L> {
+        self.layers.get_mut(index)?.as_any_mut().downcast_mut::<L>()
+    }
+
+    fn dense_layers_mut(&mut self) -> impl Iterator<Item = &mut Dense<T>> {
+        self.layers
+            .iter_mut()
+            .filter_map(|layer| layer.as_any_mut().downcast_mut::<Dense<T>>())
+    }
+
+    pub fn with_optimizer<O: Optimizer<T> + 'static>(mut self, optimizer: O) -> NeuralNetwork<T> {
+        self.set_optimizer(optimizer);
+        self
+    }
+
+    pub fn set_optimizer<O: Optimizer<T> + 'static>(&mut self, optimizer: O) {
+        self.optimizer = Box::new(optimizer);
+    }
+
+    pub fn with_loss(mut self, loss: Loss) -> NeuralNetwork<T> {
+        self.loss = loss;
+        self
+    }
+
+    pub fn set_loss(&mut self, loss: Loss) {
+        self.loss = loss;
+    }
+
+    pub fn loss(&self) -> Loss {
+        self.loss
+    }
+
+    pub fn optimizer(&self) -> &dyn Optimizer<T> {
+        self.optimizer.as_ref()
+    }
+
+    pub fn lr(&self) -> f64 {
+        self.optimizer.lr()
+    }
+
+    pub fn with_lr(mut self, lr: f64) -> NeuralNetwork<T> {
+        self.set_lr(lr);
+        self
+    }
+
+    pub fn set_lr(&mut self, lr: f64) {
+        self.optimizer.set_lr(lr);
+    }
+
+    pub fn with_clipping(mut self, clipping: Clipping) -> NeuralNetwork<T> {
+        self.set_clipping(clipping);
+        self
+    }
+
+    // panics unless the limit is positive
+    pub fn set_clipping(&mut self, clipping: Clipping) {
+        match clipping {
+            Clipping::None => {}
+            Clipping::Value(limit) | Clipping::GlobalNorm(limit) => assert!(limit > 0.0),
+        }
+        self.clipping = clipping;
+    }
+
+    pub fn clipping(&self) -> Clipping {
+        self.clipping
+    }
+
+    // same penalty on every dense layer, use Dense::with_regularizer for per-layer values
+    pub fn with_regularizer(mut self, regularizer: Regularizer) -> NeuralNetwork<T> {
+        self.set_regularizer(regularizer);
+        self
+    }
+
+    pub fn set_regularizer(&mut self, regularizer: Regularizer) {
+        for layer in self.dense_layers_mut() {
+            layer.regularizer = regularizer;
+        }
+    }
+
+    pub fn with_max_norm(mut self, max_norm: f64) -> NeuralNetwork<T> {
+        assert!(max_norm > 0.0);
+        for layer in self.dense_layers_mut() {
+            layer.max_norm = Some(max_norm);
+        }
+        self
+    }
+
+    // copy of the network in another precision including the optimizer state,
+    // custom optimizers unknown to from_state are replaced by SGD, panics on
+    // layer types defined outside this crate
+    pub fn convert<U: Scalar>(&self) -> NeuralNetwork<U> {
+        let optimizer = from_state(self.optimizer.state().convert())
+            .unwrap_or_else(|| Box::new(Sgd::new(self.lr())));
+        let layers = self
+            .layers
+            .iter()
+            .map(|layer| {
+                convert_layer(layer.as_ref())
+                    .unwrap_or_else(|| panic!("cannot convert {} layer", layer.name()))
+            })
+            .collect();
+        NeuralNetwork {
+            layers,
+            optimizer,
+            loss: self.loss,
+            clipping: self.clipping,
+        }
+    }
+
+    pub fn inference(&self, input: Matrix<T>) -> Result<Matrix<T>, MatrixError> {
         let mut res = input;
         for layer in self.layers.iter() {
             // layer.show();
-            res = layer.call(&res);
+            res = layer.forward(&res)?;
             // res.show();
         }
-        res
+        Ok(res)
     }
 
-    pub fn train(&mut self, input: &Matrix, label: &Matrix) -> Matrix {
-        // inference and save output
-        let mut layer_outputs = Vec::new();
+    pub fn train(
+        &mut self,
+        input: &Matrix<T>,
+        label: &Matrix<T>,
+    ) -> Result<(f64, Matrix<T>), MatrixError> {
+        let (loss, res) = self.train_batch(input, label)?;
+        Ok((loss, res.transpose()))
+    }
+
+    // categorical cross-entropy after a softmax output layer is computed from the logits
+    fn fused_softmax(&self) -> bool {
+        self.loss == Loss::CategoricalCrossEntropy
+            && self.layers.last().is_some_and(|layer| {
+                layer
+                    .as_any()
+                    .downcast_ref::<ActivationLayer<T>>()
+                    .is_some_and(|layer| layer.activation == Activation::Softmax)
+            })
+    }
+
+    // the output of the whole network, in train mode dropout is applied, batch norm
+    // uses the batch statistics and every layer keeps what a following backward pass
+    // needs
+    pub fn forward(&mut self, input: &Matrix<T>, mode: Mode) -> Result<Matrix<T>, MatrixError> {
         let mut res = input.clone();
-        layer_outputs.push(input.clone());
-        for layer in self.layers.iter() {
-            res = layer.call(&res);
-            // res.show();
-            layer_outputs.push(res.clone());
+        for layer in self.layers.iter_mut() {
+            res = match mode {
+                Mode::Train => layer.forward_train(&res)?,
+                Mode::Inference => layer.forward(&res)?,
+            };
+        }
+        Ok(res)
+    }
+
+    // batch normalization before the activation of every hidden dense layer
+    pub fn with_batch_norm(self) -> NeuralNetwork<T> {
+        self.with_hidden_norm(|size| Box::new(BatchNorm::new(size)))
+    }
+
+    // layer normalization before the activation of every hidden dense layer
+    pub fn with_layer_norm(self) -> NeuralNetwork<T> {
+        self.with_hidden_norm(|size| Box::new(LayerNorm::new(size)))
+    }
+
+    fn with_hidden_norm<F: Fn(usize) -> Box<dyn Layer<T>>>(mut self, norm: F) -> NeuralNetwork<T> {
+        let dense: Vec<usize> = (0..self.layers.len())
+            .filter(|index| self.layer::<Dense<T>>(*index).is_some())
+            .collect();
+        for index in dense.iter().rev().skip(1) {
+            let size = self.layer::<Dense<T>>(*index).unwrap().output_size;
+            self.layers.insert(index + 1, norm(size));
+        }
+        self
+    }
+
+    // dropout in front of every dense layer but the first, i.e. on the outputs of
+    // the hidden layers
+    pub fn with_dropout(mut self, rate: f64, seed: u64) -> NeuralNetwork<T> {
+        let dense: Vec<usize> = (0..self.layers.len())
+            .filter(|index| self.layer::<Dense<T>>(*index).is_some())
+            .collect();
+        for (hidden, index) in dense.iter().enumerate().skip(1).rev() {
+            let dropout = Dropout::new(rate).with_seed(seed.wrapping_add((hidden - 1) as u64));
+            self.layers.insert(*index, Box::new(dropout));
+        }
+        self
+    }
+
+    // each column of inputs and labels is one sample, the loss and gradients are averaged
+    // over the batch
+    pub fn train_batch(
+        &mut self,
+        inputs: &Matrix<T>,
+        labels: &Matrix<T>,
+    ) -> Result<(f64, Matrix<T>), MatrixError> {
+        if self.recordable() {
+            match self.train_batch_autograd(inputs, labels) {
+                Ok(res) => return Ok(res),
+                Err(AutogradError::Matrix(err)) => return Err(err),
+                // left to the layers' own backward passes
+                Err(_) => {}
+            }
         }
+        self.train_batch_backprop(inputs, labels)
+    }
+
+    // dense and activation layers can be recorded on a tape, the others only
+    // train through their backward passes
+    fn recordable(&self) -> bool {
+        self.layers.iter().all(|layer| {
+            let any = layer.as_any();
+            any.is::<Dense<T>>() || any.is::<ActivationLayer<T>>()
+        })
+    }
+
+    // train_batch with the gradients of every layer's backward pass
+    fn train_batch_backprop(
+        &mut self,
+        inputs: &Matrix<T>,
+        labels: &Matrix<T>,
+    ) -> Result<(f64, Matrix<T>), MatrixError> {
+        // a fused softmax only turns the logits into the output, its backward pass
+        // is part of the loss gradient
+        let fused = self.fused_softmax();
+        let trained = self.layers.len() - usize::from(fused);
+        let mut res = inputs.clone();
+        for layer in self.layers[..trained].iter_mut() {
+            res = layer.forward_train(&res)?;
+        }
+        let (loss, mut grad, res) = if fused {
+            let output = self.layers[trained].forward(&res)?;
+            check_labels(&output, labels)?;
+            (
+                softmax_cross_entropy(&res, labels),
+                softmax_cross_entropy_gradient(&output, labels),
+                output,
+            )
+        } else {
+            check_labels(&res, labels)?;
+            (
+                self.loss.loss(&res, labels),
+                self.loss.gradient(&res, labels),
+                res,
+            )
+        };
+
+        // the penalty is part of the loss at the weights the gradients are taken at
+        let loss = loss + self.penalty();
 
-        // calculate err -> update weights
-        let mut layer_errs = Vec::new();
-        let length = layer_outputs.len();
-        let mut err = Matrix::zeros(1, 1);
-        for i in 0..length {
-            let index = length - i - 1;
-            if i == 0 {
-                err = label.sub(&layer_outputs[index]);
+        // backward pass, all gradients are computed before any weight changes and
+        // the input gradient of the first layer is never needed
+        for index in (0..trained).rev() {
+            let layer = &mut self.layers[index];
+            if index == 0 {
+                layer.backward_parameters(&grad)?;
             } else {
-                err = self.layers[index].weights_matrix.transpose().product(&err);
+                grad = layer.backward(&grad)?;
+            }
+        }
+        self.apply_gradients();
+        Ok((loss, res))
+    }
+
+    // train_batch with the gradients taken by recording the forward pass and the
+    // loss on a tape, nothing changes unless every gradient could be taken
+    fn train_batch_autograd(
+        &mut self,
+        inputs: &Matrix<T>,
+        labels: &Matrix<T>,
+    ) -> Result<(f64, Matrix<T>), AutogradError> {
+        let mut tape = Tape::new();
+        let fused = self.fused_softmax();
+        let trained = self.layers.len() - usize::from(fused);
+        let mut res = tape.leaf(inputs.clone());
+        let mut params = Vec::new();
+        let mut penalties = Vec::new();
+        for (index, layer) in self.layers[..trained].iter().enumerate() {
+            let any = layer.as_any();
+            if let Some(dense) = any.downcast_ref::<Dense<T>>() {
+                let weights = tape.leaf(dense.weights_matrix.clone());
+                res = tape.product(weights, res)?;
+                params.push(weights);
+                if let Some(bias) = &dense.bias {
+                    let bias = tape.leaf(bias.clone());
+                    res = tape.add_column(res, bias)?;
+                    params.push(bias);
+                }
+                let Regularizer { l1, l2 } = dense.regularizer;
+                if l1 != 0.0 {
+                    let abs = tape.abs(weights);
+                    let sum = tape.sum(abs);
+                    penalties.push(tape.scale(sum, l1));
+                }
+                if l2 != 0.0 {
+                    let square = tape.mul(weights, weights)?;
+                    let sum = tape.sum(square);
+                    penalties.push(tape.scale(sum, l2));
+                }
+            } else if let Some(activation) = any.downcast_ref::<ActivationLayer<T>>() {
+                res = tape.activation(res, activation.activation);
+            } else {
+                return Err(AutogradError::Unsupported {
+                    layer: index,
+                    name: layer.name(),
+                });
+            }
+        }
+        let logits = res;
+        let mut loss = if fused {
+            let loss = tape.softmax_cross_entropy(res, labels)?;
+            res = tape.activation(res, Activation::Softmax);
+            loss
+        } else {
+            tape.loss(res, labels, self.loss)?
+        };
+        for penalty in penalties {
+            loss = tape.add(loss, penalty)?;
+        }
+
+        // the loss is reported in f64 like the other training path does, with the
+        // penalty at the weights the gradients are taken at
+        let output = tape.value(res).clone();
+        let reported = if fused {
+            softmax_cross_entropy(tape.value(logits), labels)
+        } else {
+            self.loss.loss(&output, labels)
+        } + self.penalty();
+
+        let mut grads = tape.backward(loss);
+        let mut gradients = Vec::new();
+        for (parameter, param) in params.into_iter().enumerate() {
+            gradients.push(
+                grads
+                    .take(param)
+                    .ok_or(AutogradError::MissingGradient { parameter })?,
+            );
+        }
+        let mut gradients = gradients.into_iter();
+        for layer in self.dense_layers_mut() {
+            let count = 1 + usize::from(layer.bias.is_some());
+            layer.gradients = gradients.by_ref().take(count).collect();
+        }
+        self.apply_gradients();
+        Ok((reported, output))
+    }
+
+    // clips the gradients of the last backward pass and lets the optimizer update
+    // every parameter with them
+    fn apply_gradients(&mut self) {
+        if self.clipping != Clipping::None {
+            let mut gradients: Vec<&mut Matrix<T>> = self
+                .layers
+                .iter_mut()
+                .flat_map(|layer| layer.gradients_mut())
+                .collect();
+            self.clipping.apply(&mut gradients);
+        }
+
+        // parameters are numbered in layer order, so a network of dense layers with
+        // bias uses 2i for the weights of its i-th dense layer and 2i+1 for the bias
+        self.optimizer.begin_step();
+        let optimizer = &mut self.optimizer;
+        let mut index = 0;
+        for layer in self.layers.iter_mut() {
+            let mut updated = Vec::new();
+            for (param, grad) in layer.parameters().into_iter().zip(layer.gradients()) {
+                updated.push(optimizer.update(index, param, grad));
+                index += 1;
+            }
+            for (param, value) in layer.parameters_mut().into_iter().zip(updated) {
+                *param = value;
+            }
+            layer.constrain();
+        }
+    }
+
+    // sum of the weight penalties of all layers
+    pub fn penalty(&self) -> f64 {
+        self.layers.iter().map(|layer| layer.penalty()).sum()
+    }
+
+    // mean loss over a batch plus the weight penalties, without updating any weights
+    pub fn compute_loss(&self, inputs: &Matrix<T>, labels: &Matrix<T>) -> Result<f64, MatrixError> {
+        Ok(self.loss_and_output(inputs, labels)?.0)
+    }
+
+    // compute_loss together with the inference output it was computed from
+    pub(crate) fn loss_and_output(
+        &self,
+        inputs: &Matrix<T>,
+        labels: &Matrix<T>,
+    ) -> Result<(f64, Matrix<T>), MatrixError> {
+        let fused = self.fused_softmax();
+        let last = self.layers.len() - usize::from(fused);
+        let mut res = inputs.clone();
+        for layer in self.layers[..last].iter() {
+            res = layer.forward(&res)?;
+        }
+        check_labels(&res, labels)?;
+        let (loss, output) = if fused {
+            let output = self.layers[last].forward(&res)?;
+            (softmax_cross_entropy(&res, labels), output)
+        } else {
+            (self.loss.loss(&res, labels), res)
+        };
+        Ok((loss + self.penalty(), output))
+    }
+
+    // samples are row vectors as returned by read_csv_by_path, returns the mean loss of each epoch
+    pub fn fit(
+        &mut self,
+        data: &[Matrix<T>],
+        labels: &[Matrix<T>],
+        batch_size: usize,
+        epochs: usize,
+    ) -> Result<Vec<f64>, MatrixError> {
+        check_counts(data, labels)?;
+        if data.is_empty() {
+            return Err(MatrixError::Empty);
+        }
+        assert!(batch_size > 0);
+        let mut history = Vec::new();
+        for _epoch in 0..epochs {
+            let mut total = 0.0;
+            let mut start = 0;
+            while start < data.len() {
+                let end = (start + batch_size).min(data.len());
+                let inputs = Matrix::try_vstack(&data[start..end])?.transpose();
+                let targets = Matrix::try_vstack(&labels[start..end])?.transpose();
+                let (loss, _) = self.train_batch(&inputs, &targets)?;
+                total += loss * (end - start) as f64;
+                start = end;
             }
-            layer_errs.push(err.clone());
+            history.push(total / data.len() as f64);
         }
+        Ok(history)
+    }
 
-        // update weight
-        let length = layer_errs.len();
-        for i in 0..length - 1 {
-            let index = length - i - 1;
-            let mut gradient = layer_errs[i].mul(&layer_outputs[index]);
-            let mut tmp = Matrix::ones(layer_outputs[index].rows, layer_outputs[index].cols);
-            tmp = tmp.sub(&layer_outputs[index]);
-            gradient = tmp.mul(&gradient);
-            gradient = gradient.product(&layer_outputs[index - 1].transpose());
-            gradient = gradient.mul_const(self.lr);
-            self.layers[index - 1].weights_matrix =
-                self.layers[index - 1].weights_matrix.add(&gradient);
+    // samples are row vectors as in fit, runs inference over the whole set in
+    // batches and collects accuracy, per-class metrics and the confusion matrix
+    pub fn evaluate(
+        &self,
+        data: &[Matrix<T>],
+        labels: &[Matrix<T>],
+    ) -> Result<Report, MatrixError> {
+        check_counts(data, labels)?;
+        if data.is_empty() {
+            return Err(MatrixError::Empty);
         }
-        res.transpose()
+        let mut outputs = Vec::new();
+        for chunk in data.chunks(EVAL_BATCH_SIZE) {
+            let inputs = Matrix::try_vstack(chunk)?.transpose();
+            outputs.push(self.inference(inputs)?.transpose());
+        }
+        let outputs = Matrix::try_vstack(&outputs)?.transpose();
+        let targets = Matrix::try_vstack(labels)?.transpose();
+        Report::new(&outputs, &targets)
     }
 
-    pub fn eval(&self, input: &Matrix, label: &Matrix) {
-        let pred = self.inference(input.clone());
+    pub fn eval(&self, input: &Matrix<T>, label: &Matrix<T>) -> Result<(), MatrixError> {
+        let pred = self.inference(input.clone())?;
         show_result(pred.transpose(), label.clone());
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ModelError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_model(self, &mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<NeuralNetwork<T>, ModelError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        read_model(&mut reader)
+    }
+
+    // like save, but also stores the optimizer state so training can resume
+    pub fn save_checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<(), ModelError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_checkpoint(self, &mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load_checkpoint<P: AsRef<Path>>(path: P) -> Result<NeuralNetwork<T>, ModelError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        read_checkpoint(&mut reader)
     }
 
     pub fn show(&self) {
-        println!("[Neural Network] learning rate: {}", self.lr);
+        println!("[Neural Network] optimizer: {}", self.optimizer.name());
+        println!("[Neural Network] learning rate: {}", self.lr());
         println!("[Neural Network] layers: ");
         for layer in self.layers.iter() {
             layer.show();
@@ -82,17 +635,96 @@ impl NeuralNetwork {
     }
 }
 
+// labels must have one column per sample and one row per output unit
+fn check_counts<T>(data: &[Matrix<T>], labels: &[Matrix<T>]) -> Result<(), MatrixError> {
+    if data.len() != labels.len() {
+        return Err(MatrixError::CountMismatch {
+            samples: data.len(),
+            labels: labels.len(),
+        });
+    }
+    Ok(())
+}
+
+fn check_labels<T: Scalar>(output: &Matrix<T>, labels: &Matrix<T>) -> Result<(), MatrixError> {
+    if output.rows != labels.rows || output.cols != labels.cols {
+        return Err(MatrixError::ShapeMismatch {
+            op: "loss",
+            left: output.shape(),
+            right: labels.shape(),
+        });
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod nn_tests {
-    use crate::matrix::{Matrix, MatrixOps};
+    use crate::activation::Activation;
+    use crate::autograd::AutogradError;
+    use crate::conv::Conv2d;
+    use crate::dataset::read_csv_by_path;
+    use crate::dropout::Dropout;
+    use crate::init::{seeded_rng, Initializer};
+    use crate::layer::{ActivationLayer, Dense, Layer, Mode, Reshape};
+    use crate::loss::Loss;
+    use crate::matrix::{Matrix, MatrixError, MatrixOps};
     use crate::nn::NeuralNetwork;
+    use crate::normalization::{BatchNorm, LayerNorm};
+    use crate::optimizer::Adam;
+    use crate::regularization::{Clipping, Regularizer};
+    use crate::scalar::{Fixed, Scalar};
+    use crate::test_util::{assert_gradient, numeric_gradient};
+
+    // the dense layers in order
+    fn dense<T: Scalar>(nn: &NeuralNetwork<T>) -> Vec<&Dense<T>> {
+        nn.layers
+            .iter()
+            .filter_map(|layer| layer.as_any().downcast_ref::<Dense<T>>())
+            .collect()
+    }
+
+    // compares one SGD step of train_batch with central differences of `loss` for
+    // every parameter of every layer
+    fn check_gradients<F: Fn(&NeuralNetwork) -> f64>(
+        nn: &NeuralNetwork,
+        inputs: &Matrix,
+        labels: &Matrix,
+        loss: F,
+    ) {
+        let lr = 1e-3;
+        let before = nn.clone().with_lr(lr);
+        let mut after = before.clone();
+        after.train_batch(inputs, labels).unwrap();
+        for index in 0..before.layers.len() {
+            let params = before.layers[index].parameters();
+            for (param, (old, new)) in params
+                .iter()
+                .zip(after.layers[index].parameters())
+                .enumerate()
+            {
+                let numeric = numeric_gradient(old, 1e-6, |value| {
+                    let mut nn = before.clone();
+                    *nn.layers[index].parameters_mut()[param] = value.clone();
+                    loss(&nn)
+                });
+                let steps = old.as_slice().iter().zip(new.as_slice());
+                let analytic = Matrix::from_vec(
+                    old.rows,
+                    old.cols,
+                    steps.map(|(old, new)| (old - new) / lr).collect(),
+                );
+                let what = format!("layer {} parameter {}", index, param);
+                assert_gradient(&what, &numeric, &analytic, 1e-6);
+            }
+        }
+    }
 
     #[test]
     fn test_inference() {
         let nn = NeuralNetwork::new(vec![3, 4, 1]);
         let inputs = Matrix::new(vec![vec![0.9, 0.1, 0.8]]);
         let inputs = inputs.transpose();
-        nn.inference(inputs);
+        nn.inference(inputs).unwrap();
     }
 
     #[test]
@@ -103,8 +735,619 @@ mod nn_tests {
         let inputs = inputs.transpose();
         for _i in 0..10 {
             nn.show();
-            nn.train(&inputs, &label);
+            nn.train(&inputs, &label).unwrap();
             nn.show();
         }
     }
+
+    #[test]
+    fn test_train_updates_bias() {
+        let mut nn = NeuralNetwork::new(vec![3, 4, 1]);
+        let inputs = Matrix::new(vec![vec![0.9, 0.1, 0.8]]).transpose();
+        let label = Matrix::new(vec![vec![1.0]]);
+        nn.train(&inputs, &label).unwrap();
+        for layer in dense(&nn) {
+            let bias = layer.bias().unwrap();
+            assert!(bias.data.iter().any(|b| *b != 0.0));
+        }
+
+        let mut nn = NeuralNetwork::new_without_bias(vec![3, 4, 1]);
+        nn.train(&inputs, &label).unwrap();
+        assert!(dense(&nn).iter().all(|layer| !layer.has_bias()));
+    }
+
+    fn squared_error(nn: &NeuralNetwork, inputs: &Matrix, label: &Matrix) -> f64 {
+        let err = nn.inference(inputs.clone()).unwrap() - label;
+        0.5 * err.dot(&err)
+    }
+
+    #[test]
+    fn test_train_gradient_check() {
+        let activations = vec![
+            vec![Activation::Sigmoid, Activation::Sigmoid],
+            vec![Activation::Tanh, Activation::Softmax],
+            vec![Activation::Gelu, Activation::Elu(1.0)],
+            vec![Activation::Softplus, Activation::Identity],
+        ];
+        let inputs = Matrix::new(vec![vec![0.9, 0.1, 0.8]]).transpose();
+        let label = Matrix::new(vec![vec![0.99, 0.01]]).transpose();
+        for acts in activations {
+            let nn = NeuralNetwork::new_with_activations(vec![3, 4, 2], acts);
+            check_gradients(&nn, &inputs, &label, |nn| {
+                squared_error(nn, &inputs, &label)
+            });
+        }
+    }
+
+    fn assert_same_weights(a: &NeuralNetwork, b: &NeuralNetwork) {
+        for (x, y) in a.layers.iter().zip(b.layers.iter()) {
+            assert_eq!(x.parameters(), y.parameters());
+        }
+    }
+
+    #[test]
+    fn test_fit_batch_size_one_matches_train() {
+        let (labels, data) = read_csv_by_path::<f64>("data/mnist_test_10.csv").unwrap();
+        let mut per_sample = NeuralNetwork::new(vec![784, 16, 10]);
+        let mut batched = per_sample.clone();
+        for _epoch in 0..2 {
+            for i in 0..data.len() {
+                per_sample
+                    .train(&data[i].transpose(), &labels[i].transpose())
+                    .unwrap();
+            }
+        }
+        batched.fit(&data, &labels, 1, 2).unwrap();
+        assert_same_weights(&per_sample, &batched);
+    }
+
+    #[test]
+    fn test_train_batch_averages_gradients() {
+        let mut single = NeuralNetwork::new(vec![3, 4, 2]);
+        let mut batched = single.clone();
+        let input = Matrix::new(vec![vec![0.9, 0.1, 0.8]]).transpose();
+        let label = Matrix::new(vec![vec![0.99, 0.01]]).transpose();
+        single.train(&input, &label).unwrap();
+
+        // a batch of two identical samples averages to the single-sample gradient
+        let inputs = Matrix::hstack(&[input.clone(), input]);
+        let labels = Matrix::hstack(&[label.clone(), label]);
+        let (_, outputs) = batched.train_batch(&inputs, &labels).unwrap();
+        assert_eq!(outputs.rows, 2);
+        assert_eq!(outputs.cols, 2);
+        for (x, y) in dense(&single).iter().zip(dense(&batched)) {
+            assert!(x.weights().approx_eq(y.weights(), 1e-12));
+        }
+    }
+
+    #[test]
+    fn test_fit_uneven_batches() {
+        let (labels, data) = read_csv_by_path::<f64>("data/mnist_test_10.csv").unwrap();
+        let mut nn = NeuralNetwork::new(vec![784, 8, 10]);
+        nn.fit(&data, &labels, 4, 1).unwrap();
+        let pred = nn.inference(data[0].transpose()).unwrap();
+        assert_eq!(pred.rows, 10);
+    }
+
+    #[test]
+    fn test_train_returns_loss() {
+        let mut nn = NeuralNetwork::new(vec![3, 4, 2]);
+        let inputs = Matrix::new(vec![vec![0.9, 0.1, 0.8]]).transpose();
+        let label = Matrix::new(vec![vec![0.99, 0.01]]).transpose();
+        let expected = nn.compute_loss(&inputs, &label).unwrap();
+        let (loss, output) = nn.train(&inputs, &label).unwrap();
+        assert_eq!(loss, expected);
+        assert_eq!(output.rows, 1);
+        assert_eq!(output.cols, 2);
+        let (after, _) = nn.train(&inputs, &label).unwrap();
+        assert!(after < loss);
+    }
+
+    #[test]
+    fn test_fit_softmax_cross_entropy() {
+        let (labels, data) = read_csv_by_path::<f64>("data/mnist_test_10.csv").unwrap();
+        let mut nn = NeuralNetwork::new_with_activations(
+            vec![784, 16, 10],
+            vec![Activation::Relu, Activation::Softmax],
+        )
+        .with_loss(Loss::CategoricalCrossEntropy);
+        nn.set_lr(0.05);
+        let history = nn.fit(&data, &labels, 3, 20).unwrap();
+        println!("{:?}", history);
+        assert_eq!(history.len(), 20);
+        assert!(history[19] < history[0]);
+    }
+
+    #[test]
+    fn test_shape_errors_are_returned() {
+        let mut nn = NeuralNetwork::new(vec![3, 4, 2]);
+        let inputs = Matrix::new(vec![vec![0.9, 0.1]]).transpose();
+        let label = Matrix::new(vec![vec![0.99, 0.01]]).transpose();
+        assert_eq!(
+            nn.inference(inputs.clone()).unwrap_err(),
+            MatrixError::ShapeMismatch {
+                op: "product",
+                left: (4, 3),
+                right: (2, 1)
+            }
+        );
+        assert!(nn.train(&inputs, &label).is_err());
+
+        // a label with the wrong number of classes is rejected before any update
+        let before = nn.clone();
+        let inputs = Matrix::new(vec![vec![0.9, 0.1, 0.8]]).transpose();
+        let label = Matrix::new(vec![vec![0.99, 0.01, 0.01]]).transpose();
+        let err = nn.train(&inputs, &label).unwrap_err();
+        println!("{}", err);
+        assert_eq!(
+            err,
+            MatrixError::ShapeMismatch {
+                op: "loss",
+                left: (2, 1),
+                right: (3, 1)
+            }
+        );
+        assert_same_weights(&before, &nn);
+
+        // samples without labels
+        let data = vec![inputs.transpose(); 3];
+        let labels = vec![label.transpose(); 2];
+        let mismatch = MatrixError::CountMismatch {
+            samples: 3,
+            labels: 2,
+        };
+        assert_eq!(nn.fit(&data, &labels, 2, 1).unwrap_err(), mismatch);
+        assert_eq!(nn.evaluate(&data, &labels).unwrap_err(), mismatch);
+        assert_same_weights(&before, &nn);
+
+        // no samples at all
+        assert_eq!(nn.fit(&[], &[], 1, 2).unwrap_err(), MatrixError::Empty);
+        assert_eq!(nn.evaluate(&[], &[]).unwrap_err(), MatrixError::Empty);
+    }
+
+    fn accuracy<T: Scalar>(nn: &NeuralNetwork<T>, data: &[Matrix<T>], labels: &[Matrix<T>]) -> f64 {
+        let mut correct = 0;
+        for (x, y) in data.iter().zip(labels.iter()) {
+            let pred = nn.inference(x.transpose()).unwrap();
+            let argmax = |m: &Matrix<T>| {
+                let values = m.as_slice();
+                (0..values.len())
+                    .max_by(|a, b| values[*a].partial_cmp(&values[*b]).unwrap())
+                    .unwrap()
+            };
+            if argmax(&pred) == argmax(y) {
+                correct += 1;
+            }
+        }
+        correct as f64 / data.len() as f64
+    }
+
+    #[test]
+    fn test_f32_mnist_matches_f64() {
+        let (labels, data) = read_csv_by_path::<f64>("data/mnist_train_100.csv").unwrap();
+        let (labels32, data32) = read_csv_by_path::<f32>("data/mnist_train_100.csv").unwrap();
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![784, 32, 10]);
+        let mut nn32 = nn.convert::<f32>();
+        let history = nn.fit(&data, &labels, 1, 5).unwrap();
+        let history32 = nn32.fit(&data32, &labels32, 1, 5).unwrap();
+        println!("f64 {:?}\nf32 {:?}", history, history32);
+        for (a, b) in history.iter().zip(history32.iter()) {
+            assert!((a - b).abs() < 1e-3 * a.abs().max(1.0));
+        }
+
+        let acc = accuracy(&nn, &data, &labels);
+        let acc32 = accuracy(&nn32, &data32, &labels32);
+        println!("train accuracy f64 {} f32 {}", acc, acc32);
+        assert!(acc > 0.5);
+        assert!((acc - acc32).abs() <= 0.03);
+    }
+
+    #[test]
+    fn test_fixed_point_trains() {
+        let mut nn: NeuralNetwork<Fixed> = NeuralNetwork::new(vec![3, 4, 2]);
+        let inputs = Matrix::new(vec![vec![0.9, 0.1, 0.8]]).transpose().convert();
+        let label = Matrix::new(vec![vec![0.99, 0.01]]).transpose().convert();
+        let (first, _) = nn.train(&inputs, &label).unwrap();
+        let mut last = first;
+        for _i in 0..20 {
+            last = nn.train(&inputs, &label).unwrap().0;
+        }
+        assert!(last < first);
+    }
+
+    #[test]
+    fn test_convert_keeps_optimizer_state() {
+        let mut nn: NeuralNetwork =
+            NeuralNetwork::new(vec![3, 4, 2]).with_optimizer(Adam::new(0.01, 0.9, 0.999));
+        let inputs = Matrix::new(vec![vec![0.9, 0.1, 0.8]]).transpose();
+        let label = Matrix::new(vec![vec![0.99, 0.01]]).transpose();
+        nn.train(&inputs, &label).unwrap();
+        let nn32 = nn.convert::<f32>();
+        let state = nn.optimizer().state();
+        let state32 = nn32.optimizer().state();
+        assert_eq!(state32.name, "adam");
+        assert_eq!(state32.step, state.step);
+        assert_eq!(state32.slots.len(), state.slots.len());
+        for (layer, layer32) in dense(&nn).iter().zip(dense(&nn32)) {
+            assert!(layer32.weights().convert().approx_eq(layer.weights(), 1e-7));
+        }
+    }
+
+    #[test]
+    fn test_same_seed_same_training() {
+        let (labels, data) = read_csv_by_path::<f64>("data/mnist_test_10.csv").unwrap();
+        let mut a: NeuralNetwork = NeuralNetwork::new_seeded(vec![784, 16, 10], 42);
+        let mut b: NeuralNetwork = NeuralNetwork::new_seeded(vec![784, 16, 10], 42);
+        assert_same_weights(&a, &b);
+        let curve_a = a.fit(&data, &labels, 2, 5).unwrap();
+        let curve_b = b.fit(&data, &labels, 2, 5).unwrap();
+        println!("loss curve: {:?}", curve_a);
+        assert_eq!(curve_a, curve_b);
+        assert_same_weights(&a, &b);
+
+        let c: NeuralNetwork = NeuralNetwork::new_seeded(vec![784, 16, 10], 43);
+        assert_ne!(
+            dense(&c)[0].weights(),
+            dense(&NeuralNetwork::<f64>::new_seeded(vec![784, 16, 10], 42))[0].weights()
+        );
+    }
+
+    #[test]
+    fn test_new_with_init() {
+        let activations = vec![Activation::Relu, Activation::Relu, Activation::Softmax];
+        let build = |seed| -> NeuralNetwork {
+            NeuralNetwork::new_with_init(
+                vec![784, 64, 32, 10],
+                activations.clone(),
+                Initializer::HeNormal,
+                &mut seeded_rng(seed),
+            )
+            .with_loss(Loss::CategoricalCrossEntropy)
+            .with_optimizer(Adam::new(0.001, 0.9, 0.999))
+        };
+        let (labels, data) = read_csv_by_path::<f64>("data/mnist_train_100.csv").unwrap();
+        let mut a = build(7);
+        let mut b = build(7);
+        assert_eq!(
+            a.layer::<ActivationLayer>(5).unwrap().activation(),
+            Activation::Softmax
+        );
+        // later layers draw from the same rng, so they differ from the first
+        assert_ne!(
+            dense(&a)[0].weights().get(0, 0),
+            dense(&a)[1].weights().get(0, 0)
+        );
+        let curve_a = a.fit(&data, &labels, 10, 3).unwrap();
+        let curve_b = b.fit(&data, &labels, 10, 3).unwrap();
+        println!("loss curve: {:?}", curve_a);
+        assert_eq!(curve_a, curve_b);
+        assert!(curve_a[2] < curve_a[0]);
+    }
+
+    #[test]
+    fn test_regularized_gradient_check() {
+        let inputs = Matrix::new(vec![vec![0.9, 0.1, 0.8], vec![0.2, 0.7, 0.4]]).transpose();
+        let labels = Matrix::new(vec![vec![0.99, 0.01], vec![0.01, 0.99]]).transpose();
+        let mut nn: NeuralNetwork = NeuralNetwork::new_seeded(vec![3, 4, 2], 1);
+        nn.layer_mut::<Dense>(0).unwrap().regularizer = Regularizer::l1(0.01);
+        nn.layer_mut::<Dense>(2).unwrap().regularizer = Regularizer::new(0.02, 0.05);
+        let before = nn.clone();
+        let penalty = before.penalty();
+        assert!(penalty > 0.0);
+        let (loss, _) = nn.train_batch(&inputs, &labels).unwrap();
+        // the reported loss includes the penalty
+        assert_eq!(loss, before.compute_loss(&inputs, &labels).unwrap());
+        let unregularized = before.clone().with_regularizer(Regularizer::default());
+        let data_loss = unregularized.compute_loss(&inputs, &labels).unwrap();
+        assert!((loss - data_loss - penalty).abs() < 1e-12);
+
+        check_gradients(&before, &inputs, &labels, |nn| {
+            nn.compute_loss(&inputs, &labels).unwrap()
+        });
+    }
+
+    #[test]
+    fn test_gradient_clipping() {
+        let inputs = Matrix::new(vec![vec![0.9, 0.1, 0.8]]).transpose();
+        let label = Matrix::new(vec![vec![0.99, 0.01]]).transpose();
+        let base: NeuralNetwork = NeuralNetwork::new_seeded(vec![3, 4, 2], 3).with_lr(1.0);
+
+        // with lr 1 every parameter moves by exactly its clipped gradient
+        let mut nn = base.clone().with_clipping(Clipping::Value(1e-3));
+        nn.train(&inputs, &label).unwrap();
+        for (before, after) in dense(&base).iter().zip(dense(&nn)) {
+            let diff = before.weights().max_abs_diff(after.weights());
+            assert!(diff.unwrap() <= 1e-3 + 1e-15);
+        }
+
+        let mut nn = base.clone().with_clipping(Clipping::GlobalNorm(1e-2));
+        nn.train(&inputs, &label).unwrap();
+        let mut squared = 0.0;
+        for (before, after) in dense(&base).iter().zip(dense(&nn)) {
+            let step = before.weights().sub(after.weights());
+            squared += step.dot(&step);
+            let bias_step = before.bias().unwrap().sub(after.bias().unwrap());
+            squared += bias_step.dot(&bias_step);
+        }
+        println!("global step norm: {}", squared.sqrt());
+        assert!((squared.sqrt() - 1e-2).abs() < 1e-12);
+
+        // unclipped training is unchanged by a limit it never reaches
+        let mut plain = base.clone();
+        let mut loose = base.clone().with_clipping(Clipping::GlobalNorm(1e9));
+        plain.train(&inputs, &label).unwrap();
+        loose.train(&inputs, &label).unwrap();
+        assert_same_weights(&plain, &loose);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_clipping_rejects_negative_limit() {
+        let _: NeuralNetwork = NeuralNetwork::new(vec![3, 2]).with_clipping(Clipping::Value(-1.0));
+    }
+
+    #[test]
+    fn test_max_norm_constraint() {
+        let (labels, data) = read_csv_by_path::<f64>("data/mnist_test_10.csv").unwrap();
+        let mut nn: NeuralNetwork =
+            NeuralNetwork::new_seeded(vec![784, 16, 10], 5).with_max_norm(0.5);
+        nn.fit(&data, &labels, 2, 3).unwrap();
+        for layer in dense(&nn) {
+            assert_eq!(layer.max_norm(), Some(0.5));
+            for row in 0..layer.weights().rows {
+                let norm = layer.weights().row(row).iter().map(|w| w * w).sum::<f64>();
+                assert!(norm.sqrt() <= 0.5 + 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_dropout_gradient_check() {
+        let inputs = Matrix::new(vec![vec![0.9, 0.1, 0.8], vec![0.2, 0.7, 0.4]]).transpose();
+        let labels = Matrix::new(vec![vec![0.99, 0.01], vec![0.01, 0.99]]).transpose();
+        let mut nn: NeuralNetwork = NeuralNetwork::new_with_activations(
+            vec![3, 6, 2],
+            vec![Activation::Tanh, Activation::Sigmoid],
+        )
+        .with_dropout(0.5, 11);
+        // dense, tanh, dropout, dense, sigmoid
+        assert_eq!(nn.layers().len(), 5);
+        assert_eq!(nn.layer::<Dropout>(2).unwrap().rate(), 0.5);
+        let before = nn.clone();
+        nn.train_batch(&inputs, &labels).unwrap();
+        let mask = nn.layer::<Dropout>(2).unwrap().mask().unwrap();
+        println!("mask: {}", mask);
+        assert_eq!(mask.shape(), (6, 2));
+
+        // a clone draws the same mask, so the numeric loss sees the same dropped units
+        let loss = |nn: &NeuralNetwork| {
+            let mut nn = nn.clone();
+            let output = nn.forward(&inputs, Mode::Train).unwrap();
+            Loss::Mse.loss(&output, &labels)
+        };
+        check_gradients(&before, &inputs, &labels, loss);
+    }
+
+    #[test]
+    fn test_dropout_inference_is_deterministic() {
+        let (labels, data) = read_csv_by_path::<f64>("data/mnist_test_10.csv").unwrap();
+        let plain: NeuralNetwork = NeuralNetwork::new_seeded(vec![784, 16, 10], 9);
+        let mut nn = plain.clone().with_dropout(0.3, 1);
+        let input = data[0].transpose();
+        let expected = plain.inference(input.clone()).unwrap();
+        assert_eq!(nn.inference(input.clone()).unwrap(), expected);
+        assert_eq!(nn.forward(&input, Mode::Inference).unwrap(), expected);
+        assert_ne!(nn.forward(&input, Mode::Train).unwrap(), expected);
+
+        let history = nn.fit(&data, &labels, 2, 10).unwrap();
+        println!("loss curve: {:?}", history);
+        assert!(history[9] < history[0]);
+        let output = nn.inference(input.clone()).unwrap();
+        assert_eq!(nn.inference(input).unwrap(), output);
+
+        // seeds near the top of the range wrap instead of overflowing
+        let deep: NeuralNetwork = NeuralNetwork::new_seeded(vec![784, 16, 8, 10], 9);
+        let deep = deep.with_dropout(0.3, u64::MAX);
+        assert_eq!(deep.layers().len(), 8);
+    }
+
+    #[test]
+    fn test_norm_gradient_check() {
+        let inputs = Matrix::new(vec![
+            vec![0.9, 0.1, 0.8],
+            vec![0.2, 0.7, 0.4],
+            vec![0.5, 0.3, 0.1],
+        ])
+        .transpose();
+        let labels =
+            Matrix::new(vec![vec![0.99, 0.01], vec![0.01, 0.99], vec![0.5, 0.5]]).transpose();
+        let mut nn: NeuralNetwork = NeuralNetwork::new_seeded(vec![3, 5, 4, 2], 4);
+        nn.layers.insert(3, Box::new(LayerNorm::new(4)));
+        nn.layers.insert(1, Box::new(BatchNorm::new(5)));
+
+        // gamma and beta are checked like every other parameter
+        check_gradients(&nn, &inputs, &labels, |nn| {
+            let mut nn = nn.clone();
+            let output = nn.forward(&inputs, Mode::Train).unwrap();
+            Loss::Mse.loss(&output, &labels)
+        });
+    }
+
+    #[test]
+    fn test_deep_sigmoid_with_batch_norm() {
+        let (labels, data) = read_csv_by_path::<f64>("data/mnist_train_100.csv").unwrap();
+        let mut nn: NeuralNetwork =
+            NeuralNetwork::new_seeded(vec![784, 64, 64, 64, 64, 10], 3).with_batch_norm();
+        // a norm between every hidden dense layer and its sigmoid
+        assert_eq!(nn.layers().len(), 14);
+        for index in [1, 4, 7, 10] {
+            assert!(nn.layer::<BatchNorm>(index).is_some());
+        }
+        assert!(nn.layer::<Dense>(12).is_some());
+        assert!(nn.layer::<ActivationLayer>(13).is_some());
+        let history = nn.fit(&data, &labels, 10, 10).unwrap();
+        println!("loss curve: {:?}", history);
+        assert!(history[9] < history[0]);
+
+        // inference runs on the running statistics, one sample at a time
+        let report = nn.evaluate(&data, &labels).unwrap();
+        println!("{}", report);
+        assert!(report.accuracy() > 0.5);
+    }
+
+    #[test]
+    fn test_conv_gradient_check() {
+        let mut rng = seeded_rng(8);
+        let init = Initializer::default();
+        let layers: Vec<Box<dyn Layer>> = vec![
+            Box::new(Reshape::new(vec![50], vec![2, 5, 5])),
+            Box::new(Conv2d::new_by_init(2, (5, 5), 3, (3, 3), init, &mut rng).with_padding(1)),
+            Box::new(ActivationLayer::new(Activation::Tanh)),
+            Box::new(Conv2d::new_by_init(3, (5, 5), 2, (2, 2), init, &mut rng).with_stride(2)),
+            Box::new(Dense::new_by_init(8, 3, init, &mut rng)),
+            Box::new(ActivationLayer::new(Activation::Softmax)),
+        ];
+        let nn = NeuralNetwork::from_layers(layers).with_loss(Loss::CategoricalCrossEntropy);
+        let inputs = init.init(50, 2, &mut rng);
+        let labels = Matrix::new(vec![vec![1.0, 0.0], vec![0.0, 0.0], vec![0.0, 1.0]]);
+        check_gradients(&nn, &inputs, &labels, |nn| {
+            nn.compute_loss(&inputs, &labels).unwrap()
+        });
+    }
+
+    #[test]
+    fn test_conv_net_trains() {
+        let (labels, data) = read_csv_by_path::<f64>("data/mnist_test_10.csv").unwrap();
+        let mut rng = seeded_rng(2);
+        let layers: Vec<Box<dyn Layer>> = vec![
+            Box::new(Reshape::new(vec![784], vec![1, 28, 28])),
+            Box::new(
+                Conv2d::new_by_init(1, (28, 28), 4, (4, 4), Initializer::HeNormal, &mut rng)
+                    .with_stride(4),
+            ),
+            Box::new(ActivationLayer::new(Activation::Relu)),
+            Box::new(Dense::new_by_init(
+                196,
+                10,
+                Initializer::default(),
+                &mut rng,
+            )),
+            Box::new(ActivationLayer::new(Activation::Softmax)),
+        ];
+        let mut nn = NeuralNetwork::from_layers(layers)
+            .with_loss(Loss::CategoricalCrossEntropy)
+            .with_optimizer(Adam::new(0.01, 0.9, 0.999));
+        nn.show();
+        let history = nn.fit(&data, &labels, 5, 10).unwrap();
+        println!("loss curve: {:?}", history);
+        assert!(history[9] < history[0]);
+        // weights and bias of the convolution and the dense layer
+        assert_eq!(nn.optimizer().state().slots.len(), 4);
+    }
+
+    // train_batch records these networks on a tape, which must reproduce the
+    // hand-written backward passes step by step
+    fn check_autograd(nn: NeuralNetwork, inputs: &Matrix, labels: &Matrix, steps: usize) {
+        assert!(nn.recordable());
+        let mut backprop = nn.clone();
+        let mut autograd = nn;
+        for step in 0..steps {
+            let (loss, output) = backprop.train_batch_backprop(inputs, labels).unwrap();
+            let (tape_loss, tape_output) = autograd.train_batch(inputs, labels).unwrap();
+            println!("step {}: loss {} vs {}", step, loss, tape_loss);
+            assert!((loss - tape_loss).abs() < 1e-12);
+            assert!(output.approx_eq(&tape_output, 1e-12));
+            for (a, b) in backprop.layers.iter().zip(autograd.layers.iter()) {
+                for (x, y) in a.parameters().iter().zip(b.parameters()) {
+                    assert!(x.approx_eq(y, 1e-12));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_autograd_matches_backprop() {
+        let (labels, data) = read_csv_by_path::<f64>("data/mnist_test_10.csv").unwrap();
+        let inputs = Matrix::vstack(&data).transpose();
+        let targets = Matrix::vstack(&labels).transpose();
+
+        // the original sigmoid + MSE network
+        let mut rng = seeded_rng(4);
+        let nn = NeuralNetwork::new_with_init(
+            vec![784, 30, 10],
+            vec![Activation::Sigmoid, Activation::Sigmoid],
+            Initializer::default(),
+            &mut rng,
+        );
+        check_autograd(nn, &inputs, &targets, 3);
+
+        // fused softmax, a layer without bias, penalties, clipping and Adam
+        let nn: NeuralNetwork = NeuralNetwork::builder()
+            .input(784)
+            .seed(5)
+            .initializer(Initializer::HeNormal)
+            .dense(20)
+            .relu()
+            .dense_without_bias(16)
+            .tanh()
+            .dense(10)
+            .softmax()
+            .optimizer(Adam::new(0.01, 0.9, 0.999))
+            .build()
+            .unwrap()
+            .with_regularizer(Regularizer::new(1e-4, 1e-3))
+            .with_clipping(Clipping::GlobalNorm(1.0));
+        check_autograd(nn, &inputs, &targets, 3);
+
+        let nn: NeuralNetwork = NeuralNetwork::builder()
+            .input(784)
+            .seed(6)
+            .dense(10)
+            .activation(Activation::Elu(1.0))
+            .loss(Loss::Huber(0.5))
+            .build()
+            .unwrap();
+        check_autograd(nn, &inputs, &targets, 3);
+    }
+
+    #[test]
+    fn test_autograd_unsupported_layer() {
+        let mut nn: NeuralNetwork = NeuralNetwork::builder()
+            .input(4)
+            .dense(3)
+            .dropout(0.5)
+            .dense(2)
+            .build()
+            .unwrap();
+        let before = nn.clone();
+        let err = nn
+            .train_batch_autograd(&Matrix::zeros(4, 1), &Matrix::zeros(2, 1))
+            .unwrap_err();
+        println!("{}", err);
+        assert!(matches!(
+            err,
+            AutogradError::Unsupported {
+                layer: 1,
+                name: "dropout"
+            }
+        ));
+        // nothing was updated
+        for (a, b) in before.layers.iter().zip(nn.layers.iter()) {
+            assert_eq!(a.parameters(), b.parameters());
+        }
+
+        // train_batch falls back to the backward passes
+        assert!(!nn.recordable());
+        let mut backprop = nn.clone();
+        let inputs = Matrix::ones(4, 2);
+        let labels = Matrix::zeros(2, 2);
+        let (loss, _) = nn.train_batch(&inputs, &labels).unwrap();
+        assert_eq!(
+            loss,
+            backprop.train_batch_backprop(&inputs, &labels).unwrap().0
+        );
+        for (a, b) in backprop.layers.iter().zip(nn.layers.iter()) {
+            assert_eq!(a.parameters(), b.parameters());
+        }
+    }
 }
@@ -0,0 +1,499 @@
+use crate::layer::{check_input, Layer};
+use crate::matrix::{Matrix, MatrixError, MatrixOps};
+use crate::scalar::Scalar;
+use std::any::Any;
+
+// Normalized values and 1 / sqrt(var + epsilon) of every normalized row, kept
+// from the last training forward pass for the backward pass.
+#[derive(Debug, Clone)]
+pub(crate) struct NormCache<T> {
+    xhat: Matrix<T>,
+    inv_std: Vec<T>,
+}
+
+impl<T: Scalar> NormCache<T> {
+    fn convert<U: Scalar>(&self) -> NormCache<U> {
+        NormCache {
+            xhat: self.xhat.convert(),
+            inv_std: self
+                .inv_std
+                .iter()
+                .map(|x| U::from_f64(x.to_f64()))
+                .collect(),
+        }
+    }
+}
+
+// Every unit is normalized over the samples of the batch while training, and
+// with running averages of the batch mean and variance at inference:
+//   running = momentum * running + (1 - momentum) * batch
+// followed by a learnable per-unit scale gamma and shift beta.
+#[derive(Debug, Clone)]
+pub struct BatchNorm<T = f64> {
+    pub(crate) gamma: Matrix<T>,
+    pub(crate) beta: Matrix<T>,
+    pub(crate) running_mean: Matrix<T>,
+    pub(crate) running_var: Matrix<T>,
+    pub(crate) momentum: f64,
+    pub(crate) epsilon: f64,
+    pub(crate) cache: Option<NormCache<T>>,
+    pub(crate) gradients: Vec<Matrix<T>>,
+}
+
+impl<T: Scalar> BatchNorm<T> {
+    pub fn new(size: usize) -> BatchNorm<T> {
+        BatchNorm {
+            gamma: Matrix::ones(size, 1),
+            beta: Matrix::zeros(size, 1),
+            running_mean: Matrix::zeros(size, 1),
+            running_var: Matrix::ones(size, 1),
+            momentum: 0.9,
+            epsilon: 1e-5,
+            cache: None,
+            gradients: Vec::new(),
+        }
+    }
+
+    pub fn with_momentum(mut self, momentum: f64) -> BatchNorm<T> {
+        assert!((0.0..1.0).contains(&momentum));
+        self.momentum = momentum;
+        self
+    }
+
+    pub fn with_epsilon(mut self, epsilon: f64) -> BatchNorm<T> {
+        assert!(epsilon > 0.0);
+        self.epsilon = epsilon;
+        self
+    }
+
+    pub fn size(&self) -> usize {
+        self.gamma.rows
+    }
+
+    pub fn gamma(&self) -> &Matrix<T> {
+        &self.gamma
+    }
+
+    pub fn beta(&self) -> &Matrix<T> {
+        &self.beta
+    }
+
+    pub fn running_mean(&self) -> &Matrix<T> {
+        &self.running_mean
+    }
+
+    pub fn running_var(&self) -> &Matrix<T> {
+        &self.running_var
+    }
+
+    pub fn convert<U: Scalar>(&self) -> BatchNorm<U> {
+        BatchNorm {
+            gamma: self.gamma.convert(),
+            beta: self.beta.convert(),
+            running_mean: self.running_mean.convert(),
+            running_var: self.running_var.convert(),
+            momentum: self.momentum,
+            epsilon: self.epsilon,
+            cache: self.cache.as_ref().map(|cache| cache.convert()),
+            gradients: self.gradients.iter().map(|grad| grad.convert()).collect(),
+        }
+    }
+}
+
+impl<T: Scalar> Layer<T> for BatchNorm<T> {
+    fn name(&self) -> &'static str {
+        "batch_norm"
+    }
+
+    fn input_size(&self) -> Option<usize> {
+        Some(self.size())
+    }
+
+    fn output_size(&self, input_size: usize) -> usize {
+        input_size
+    }
+
+    // inference, uses the running statistics
+    fn forward(&self, z: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        check_input(z, self.size(), "batch norm")?;
+        let epsilon = T::from_f64(self.epsilon);
+        let mut xhat = z.clone();
+        for (row, values) in xhat.data.chunks_mut(z.cols).enumerate() {
+            let mean = self.running_mean.data[row];
+            let inv_std = T::one() / (self.running_var.data[row] + epsilon).sqrt();
+            for value in values.iter_mut() {
+                *value = (*value - mean) * inv_std;
+            }
+        }
+        Ok(scale_shift_rows(&xhat, &self.gamma, &self.beta))
+    }
+
+    fn forward_train(&mut self, z: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        check_input(z, self.size(), "batch norm")?;
+        let (xhat, mean, var, inv_std) = normalize_rows(z, self.epsilon);
+        let momentum = T::from_f64(self.momentum);
+        let rest = T::one() - momentum;
+        for row in 0..z.rows {
+            self.running_mean.data[row] = momentum * self.running_mean.data[row] + rest * mean[row];
+            self.running_var.data[row] = momentum * self.running_var.data[row] + rest * var[row];
+        }
+        let output = scale_shift_rows(&xhat, &self.gamma, &self.beta);
+        self.cache = Some(NormCache { xhat, inv_std });
+        Ok(output)
+    }
+
+    fn backward(&mut self, grad: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        let cache = self
+            .cache
+            .as_ref()
+            .expect("backward before a training forward pass");
+        check_gradient(grad, cache.xhat.shape())?;
+        let (dxhat, dgamma, dbeta) = scale_shift_backward(grad, &cache.xhat, &self.gamma);
+        let dz = normalize_rows_backward(&dxhat, cache);
+        self.gradients = vec![dgamma, dbeta];
+        Ok(dz)
+    }
+
+    fn parameters(&self) -> Vec<&Matrix<T>> {
+        vec![&self.gamma, &self.beta]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix<T>> {
+        vec![&mut self.gamma, &mut self.beta]
+    }
+
+    fn gradients(&self) -> Vec<&Matrix<T>> {
+        self.gradients.iter().collect()
+    }
+
+    fn gradients_mut(&mut self) -> Vec<&mut Matrix<T>> {
+        self.gradients.iter_mut().collect()
+    }
+
+    fn show(&self) {
+        println!("[Layer] batch norm: {}", self.size());
+    }
+
+    fn box_clone(&self) -> Box<dyn Layer<T>> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// Every sample is normalized over the units of the layer, the same way while
+// training and at inference, followed by a learnable scale and shift.
+#[derive(Debug, Clone)]
+pub struct LayerNorm<T = f64> {
+    pub(crate) gamma: Matrix<T>,
+    pub(crate) beta: Matrix<T>,
+    pub(crate) epsilon: f64,
+    // kept in the transposed (samples x units) layout
+    pub(crate) cache: Option<NormCache<T>>,
+    pub(crate) gradients: Vec<Matrix<T>>,
+}
+
+impl<T: Scalar> LayerNorm<T> {
+    pub fn new(size: usize) -> LayerNorm<T> {
+        LayerNorm {
+            gamma: Matrix::ones(size, 1),
+            beta: Matrix::zeros(size, 1),
+            epsilon: 1e-5,
+            cache: None,
+            gradients: Vec::new(),
+        }
+    }
+
+    pub fn with_epsilon(mut self, epsilon: f64) -> LayerNorm<T> {
+        assert!(epsilon > 0.0);
+        self.epsilon = epsilon;
+        self
+    }
+
+    pub fn size(&self) -> usize {
+        self.gamma.rows
+    }
+
+    pub fn gamma(&self) -> &Matrix<T> {
+        &self.gamma
+    }
+
+    pub fn beta(&self) -> &Matrix<T> {
+        &self.beta
+    }
+
+    pub fn convert<U: Scalar>(&self) -> LayerNorm<U> {
+        LayerNorm {
+            gamma: self.gamma.convert(),
+            beta: self.beta.convert(),
+            epsilon: self.epsilon,
+            cache: self.cache.as_ref().map(|cache| cache.convert()),
+            gradients: self.gradients.iter().map(|grad| grad.convert()).collect(),
+        }
+    }
+}
+
+impl<T: Scalar> Layer<T> for LayerNorm<T> {
+    fn name(&self) -> &'static str {
+        "layer_norm"
+    }
+
+    fn input_size(&self) -> Option<usize> {
+        Some(self.size())
+    }
+
+    fn output_size(&self, input_size: usize) -> usize {
+        input_size
+    }
+
+    fn forward(&self, z: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        check_input(z, self.size(), "layer norm")?;
+        let (xhat, _, _, _) = normalize_rows(&z.transpose(), self.epsilon);
+        Ok(scale_shift_rows(&xhat.transpose(), &self.gamma, &self.beta))
+    }
+
+    fn forward_train(&mut self, z: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        check_input(z, self.size(), "layer norm")?;
+        let (xhat, _, _, inv_std) = normalize_rows(&z.transpose(), self.epsilon);
+        let output = scale_shift_rows(&xhat.transpose(), &self.gamma, &self.beta);
+        self.cache = Some(NormCache { xhat, inv_std });
+        Ok(output)
+    }
+
+    fn backward(&mut self, grad: &Matrix<T>) -> Result<Matrix<T>, MatrixError> {
+        let cache = self
+            .cache
+            .as_ref()
+            .expect("backward before a training forward pass");
+        let xhat = cache.xhat.transpose();
+        check_gradient(grad, xhat.shape())?;
+        let (dxhat, dgamma, dbeta) = scale_shift_backward(grad, &xhat, &self.gamma);
+        let dz = normalize_rows_backward(&dxhat.transpose(), cache).transpose();
+        self.gradients = vec![dgamma, dbeta];
+        Ok(dz)
+    }
+
+    fn parameters(&self) -> Vec<&Matrix<T>> {
+        vec![&self.gamma, &self.beta]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix<T>> {
+        vec![&mut self.gamma, &mut self.beta]
+    }
+
+    fn gradients(&self) -> Vec<&Matrix<T>> {
+        self.gradients.iter().collect()
+    }
+
+    fn gradients_mut(&mut self) -> Vec<&mut Matrix<T>> {
+        self.gradients.iter_mut().collect()
+    }
+
+    fn show(&self) {
+        println!("[Layer] layer norm: {}", self.size());
+    }
+
+    fn box_clone(&self) -> Box<dyn Layer<T>> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// normalizes every row to zero mean and unit variance, returns the normalized
+// matrix with the mean, variance and 1 / sqrt(var + epsilon) of each row
+fn normalize_rows<T: Scalar>(z: &Matrix<T>, epsilon: f64) -> (Matrix<T>, Vec<T>, Vec<T>, Vec<T>) {
+    let count = T::from_f64(z.cols as f64);
+    let epsilon = T::from_f64(epsilon);
+    let mut xhat = z.clone();
+    let (mut means, mut vars, mut inv_stds) = (Vec::new(), Vec::new(), Vec::new());
+    for values in xhat.data.chunks_mut(z.cols) {
+        let mean = values.iter().fold(T::zero(), |sum, x| sum + *x) / count;
+        let var = values
+            .iter()
+            .fold(T::zero(), |sum, x| sum + (*x - mean) * (*x - mean))
+            / count;
+        let inv_std = T::one() / (var + epsilon).sqrt();
+        for value in values.iter_mut() {
+            *value = (*value - mean) * inv_std;
+        }
+        means.push(mean);
+        vars.push(var);
+        inv_stds.push(inv_std);
+    }
+    (xhat, means, vars, inv_stds)
+}
+
+// dz = inv_std / m * (m * dxhat - sum(dxhat) - xhat * sum(dxhat * xhat)) per row
+fn normalize_rows_backward<T: Scalar>(dxhat: &Matrix<T>, cache: &NormCache<T>) -> Matrix<T> {
+    let cols = dxhat.cols;
+    let count = T::from_f64(cols as f64);
+    let mut dz = dxhat.clone();
+    for (row, values) in dz.data.chunks_mut(cols).enumerate() {
+        let xhat = cache.xhat.row(row);
+        let sum = values.iter().fold(T::zero(), |sum, x| sum + *x);
+        let dot = values
+            .iter()
+            .zip(xhat.iter())
+            .fold(T::zero(), |sum, (d, x)| sum + *d * *x);
+        let scale = cache.inv_std[row] / count;
+        for (value, x) in values.iter_mut().zip(xhat.iter()) {
+            *value = scale * (count * *value - sum - *x * dot);
+        }
+    }
+    dz
+}
+
+// gamma_i * xhat + beta_i for every row i
+fn scale_shift_rows<T: Scalar>(xhat: &Matrix<T>, gamma: &Matrix<T>, beta: &Matrix<T>) -> Matrix<T> {
+    let mut res = xhat.clone();
+    for (row, values) in res.data.chunks_mut(xhat.cols).enumerate() {
+        for value in values.iter_mut() {
+            *value = gamma.data[row] * *value + beta.data[row];
+        }
+    }
+    res
+}
+
+// gradients with respect to xhat, gamma and beta of scale_shift_rows
+fn scale_shift_backward<T: Scalar>(
+    grad: &Matrix<T>,
+    xhat: &Matrix<T>,
+    gamma: &Matrix<T>,
+) -> (Matrix<T>, Matrix<T>, Matrix<T>) {
+    let dgamma = grad.mul(xhat).sum_columns();
+    let dbeta = grad.sum_columns();
+    let mut dxhat = grad.clone();
+    for (row, values) in dxhat.data.chunks_mut(grad.cols).enumerate() {
+        for value in values.iter_mut() {
+            *value *= gamma.data[row];
+        }
+    }
+    (dxhat, dgamma, dbeta)
+}
+
+// the gradient must have the shape of the last training output
+fn check_gradient<T: Scalar>(grad: &Matrix<T>, shape: (usize, usize)) -> Result<(), MatrixError> {
+    if grad.shape() != shape {
+        return Err(MatrixError::ShapeMismatch {
+            op: "norm backward",
+            left: shape,
+            right: grad.shape(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod normalization_tests {
+    use super::{BatchNorm, LayerNorm};
+    use crate::layer::Layer;
+    use crate::matrix::{Matrix, MatrixOps};
+    use crate::test_util::{assert_gradient, loss_weights, numeric_gradient};
+
+    fn inputs() -> Matrix {
+        Matrix::new(vec![
+            vec![0.9, -0.3, 1.7, 0.2],
+            vec![2.0, 2.5, 1.0, 3.1],
+            vec![-1.2, 0.4, 0.0, -0.8],
+        ])
+    }
+
+    fn loss(norm: &dyn Layer, z: &Matrix) -> f64 {
+        let mut norm = norm.box_clone();
+        norm.forward_train(z)
+            .unwrap()
+            .dot(&loss_weights(z.rows, z.cols))
+    }
+
+    fn check_gradients(mut norm: Box<dyn Layer>) {
+        // a non-trivial scale and shift
+        let values = [vec![1.5, 0.5, -0.7], vec![0.1, -0.2, 0.3]];
+        for (param, values) in norm.parameters_mut().into_iter().zip(values.iter()) {
+            *param = Matrix::from_vec(3, 1, values.clone());
+        }
+        let z = inputs();
+        let before = norm.box_clone();
+        norm.forward_train(&z).unwrap();
+        let dz = norm.backward(&loss_weights(z.rows, z.cols)).unwrap();
+        let numeric = numeric_gradient(&z, 1e-6, |z| loss(before.as_ref(), z));
+        assert_gradient("input", &numeric, &dz, 1e-6);
+        let gradients = norm.gradients();
+        assert_eq!(gradients.len(), 2);
+        for (index, (param, gradient)) in before.parameters().into_iter().zip(gradients).enumerate()
+        {
+            let numeric = numeric_gradient(param, 1e-6, |param| {
+                let mut norm = before.box_clone();
+                *norm.parameters_mut()[index] = param.clone();
+                loss(norm.as_ref(), &z)
+            });
+            assert_gradient(&format!("parameter {}", index), &numeric, gradient, 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_batch_norm_gradients() {
+        check_gradients(Box::new(BatchNorm::new(3)));
+    }
+
+    #[test]
+    fn test_layer_norm_gradients() {
+        check_gradients(Box::new(LayerNorm::new(3).with_epsilon(1e-3)));
+    }
+
+    #[test]
+    fn test_batch_norm_statistics() {
+        let mut norm: BatchNorm = BatchNorm::new(3).with_momentum(0.5);
+        let z = inputs();
+        let output = norm.forward_train(&z).unwrap();
+        output.show();
+        for row in 0..3 {
+            let values = output.row(row);
+            let mean = values.iter().sum::<f64>() / 4.0;
+            let var = values.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / 4.0;
+            assert!(mean.abs() < 1e-12);
+            assert!((var - 1.0).abs() < 1e-4);
+        }
+        // half way from the initial 0 and 1 to the batch statistics
+        assert!((norm.running_mean()[(0, 0)] - 0.3125).abs() < 1e-12);
+        assert!((norm.running_var()[(1, 0)] - (1.0 + 0.5925) / 2.0).abs() < 1e-12);
+
+        // inference uses the running statistics and leaves them alone
+        let running = norm.running_mean().clone();
+        let inference = norm.forward(&z).unwrap();
+        assert_eq!(norm.running_mean(), &running);
+        let expected = (0.9 - 0.3125) / (norm.running_var()[(0, 0)] + 1e-5).sqrt();
+        assert!((inference[(0, 0)] - expected).abs() < 1e-12);
+        assert!(norm.forward(&Matrix::ones(2, 4)).is_err());
+    }
+
+    #[test]
+    fn test_layer_norm_per_sample() {
+        let mut norm: LayerNorm = LayerNorm::new(3);
+        let z = inputs();
+        let output = norm.forward_train(&z).unwrap();
+        for col in 0..4 {
+            let mean = (0..3).map(|row| output[(row, col)]).sum::<f64>() / 3.0;
+            assert!(mean.abs() < 1e-12);
+        }
+        // no batch statistics, a single sample gives the same result
+        let single = Matrix::new(vec![vec![0.9], vec![2.0], vec![-1.2]]);
+        let alone = norm.forward(&single).unwrap();
+        for row in 0..3 {
+            assert!((alone[(row, 0)] - output[(row, 0)]).abs() < 1e-12);
+        }
+        assert_eq!(norm.forward(&z).unwrap(), output);
+    }
+}
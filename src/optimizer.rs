@@ -0,0 +1,631 @@
+use crate::matrix::{Matrix, MatrixOps};
+use crate::scalar::Scalar;
+use std::fmt;
+
+// Snapshot of an optimizer used for checkpointing. `slots[i]` holds the
+// buffers (velocity, moments, ...) kept for parameter `i`.
+#[derive(Debug, Clone)]
+pub struct OptimizerState<T = f64> {
+    pub name: String,
+    pub hyperparameters: Vec<f64>,
+    pub step: u64,
+    pub slots: Vec<Vec<Matrix<T>>>,
+}
+
+impl<T: Scalar> OptimizerState<T> {
+    pub fn convert<U: Scalar>(&self) -> OptimizerState<U> {
+        OptimizerState {
+            name: self.name.clone(),
+            hyperparameters: self.hyperparameters.clone(),
+            step: self.step,
+            slots: self
+                .slots
+                .iter()
+                .map(|slots| slots.iter().map(|slot| slot.convert()).collect())
+                .collect(),
+        }
+    }
+}
+
+pub trait Optimizer<T: Scalar = f64>: fmt::Debug {
+    fn name(&self) -> &'static str;
+    fn lr(&self) -> f64;
+    fn set_lr(&mut self, lr: f64);
+    // called once per training step, before any parameter is updated
+    fn begin_step(&mut self) {}
+    // `index` identifies the parameter so per-parameter state can be kept
+    fn update(&mut self, index: usize, param: &Matrix<T>, grad: &Matrix<T>) -> Matrix<T>;
+    fn state(&self) -> OptimizerState<T>;
+    fn load_state(&mut self, state: OptimizerState<T>);
+    fn box_clone(&self) -> Box<dyn Optimizer<T>>;
+}
+
+impl<T: Scalar> Clone for Box<dyn Optimizer<T>> {
+    fn clone(&self) -> Box<dyn Optimizer<T>> {
+        self.box_clone()
+    }
+}
+
+// rebuilds an optimizer from a checkpointed state, None if the name or
+// hyperparameters are not recognised
+pub fn from_state<T: Scalar>(state: OptimizerState<T>) -> Option<Box<dyn Optimizer<T>>> {
+    let h = &state.hyperparameters;
+    let mut optimizer: Box<dyn Optimizer<T>> = match (state.name.as_str(), h.len()) {
+        ("sgd", 1) => Box::new(Sgd::new(h[0])),
+        ("momentum", 2) => Box::new(Momentum::new(h[0], h[1])),
+        ("nesterov", 2) => Box::new(Nesterov::new(h[0], h[1])),
+        ("adagrad", 2) => Box::new(AdaGrad::new(h[0]).with_epsilon(h[1])),
+        ("rmsprop", 3) => Box::new(RMSProp::new(h[0], h[1]).with_epsilon(h[2])),
+        ("adam", 4) => Box::new(Adam::new(h[0], h[1], h[2]).with_epsilon(h[3])),
+        ("adamw", 5) => Box::new(AdamW::new(h[0], h[1], h[2], h[4]).with_epsilon(h[3])),
+        _ => return None,
+    };
+    optimizer.load_state(state);
+    Some(optimizer)
+}
+
+// number of buffers the optimizer of that name keeps per parameter
+pub(crate) fn slot_count(name: &str) -> Option<usize> {
+    match name {
+        "sgd" => Some(0),
+        "momentum" | "nesterov" | "adagrad" | "rmsprop" => Some(1),
+        "adam" | "adamw" => Some(2),
+        _ => None,
+    }
+}
+
+// returns the buffers for parameter `index`, creating `count` zero matrices shaped like `param`
+fn slots_for<'a, T: Scalar>(
+    slots: &'a mut Vec<Vec<Matrix<T>>>,
+    index: usize,
+    count: usize,
+    param: &Matrix<T>,
+) -> &'a mut Vec<Matrix<T>> {
+    if slots.len() <= index {
+        slots.resize(index + 1, Vec::new());
+    }
+    if slots[index].is_empty() {
+        for _i in 0..count {
+            slots[index].push(Matrix::zeros(param.rows, param.cols));
+        }
+    }
+    &mut slots[index]
+}
+
+// g / (sqrt(s) + eps), element-wise
+fn scale_by_root<T: Scalar>(grad: &Matrix<T>, s: &Matrix<T>, eps: f64) -> Matrix<T> {
+    let eps = T::from_f64(eps);
+    let denom = s.map(|x| x.sqrt() + eps);
+    let mut res = grad.clone();
+    for row in 0..res.rows {
+        for col in 0..res.cols {
+            res[(row, col)] /= denom[(row, col)];
+        }
+    }
+    res
+}
+
+#[derive(Debug, Clone)]
+pub struct Sgd {
+    lr: f64,
+}
+
+impl Sgd {
+    pub fn new(lr: f64) -> Sgd {
+        Sgd { lr }
+    }
+}
+
+impl<T: Scalar> Optimizer<T> for Sgd {
+    fn name(&self) -> &'static str {
+        "sgd"
+    }
+
+    fn lr(&self) -> f64 {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    fn update(&mut self, _index: usize, param: &Matrix<T>, grad: &Matrix<T>) -> Matrix<T> {
+        param - grad * T::from_f64(self.lr)
+    }
+
+    fn state(&self) -> OptimizerState<T> {
+        OptimizerState {
+            name: "sgd".to_string(),
+            hyperparameters: vec![self.lr],
+            step: 0,
+            slots: Vec::new(),
+        }
+    }
+
+    fn load_state(&mut self, _state: OptimizerState<T>) {}
+
+    fn box_clone(&self) -> Box<dyn Optimizer<T>> {
+        Box::new(self.clone())
+    }
+}
+
+// v = momentum * v + g, p -= lr * v
+#[derive(Debug, Clone)]
+pub struct Momentum<T = f64> {
+    lr: f64,
+    momentum: f64,
+    slots: Vec<Vec<Matrix<T>>>,
+}
+
+impl<T: Scalar> Momentum<T> {
+    pub fn new(lr: f64, momentum: f64) -> Momentum<T> {
+        Momentum {
+            lr,
+            momentum,
+            slots: Vec::new(),
+        }
+    }
+}
+
+impl<T: Scalar> Optimizer<T> for Momentum<T> {
+    fn name(&self) -> &'static str {
+        "momentum"
+    }
+
+    fn lr(&self) -> f64 {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    fn update(&mut self, index: usize, param: &Matrix<T>, grad: &Matrix<T>) -> Matrix<T> {
+        let slots = slots_for(&mut self.slots, index, 1, param);
+        slots[0] *= T::from_f64(self.momentum);
+        slots[0] += grad;
+        param - &slots[0] * T::from_f64(self.lr)
+    }
+
+    fn state(&self) -> OptimizerState<T> {
+        OptimizerState {
+            name: self.name().to_string(),
+            hyperparameters: vec![self.lr, self.momentum],
+            step: 0,
+            slots: self.slots.clone(),
+        }
+    }
+
+    fn load_state(&mut self, state: OptimizerState<T>) {
+        self.slots = state.slots;
+    }
+
+    fn box_clone(&self) -> Box<dyn Optimizer<T>> {
+        Box::new(self.clone())
+    }
+}
+
+// v = momentum * v + g, p -= lr * (g + momentum * v)
+#[derive(Debug, Clone)]
+pub struct Nesterov<T = f64> {
+    lr: f64,
+    momentum: f64,
+    slots: Vec<Vec<Matrix<T>>>,
+}
+
+impl<T: Scalar> Nesterov<T> {
+    pub fn new(lr: f64, momentum: f64) -> Nesterov<T> {
+        Nesterov {
+            lr,
+            momentum,
+            slots: Vec::new(),
+        }
+    }
+}
+
+impl<T: Scalar> Optimizer<T> for Nesterov<T> {
+    fn name(&self) -> &'static str {
+        "nesterov"
+    }
+
+    fn lr(&self) -> f64 {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    fn update(&mut self, index: usize, param: &Matrix<T>, grad: &Matrix<T>) -> Matrix<T> {
+        let slots = slots_for(&mut self.slots, index, 1, param);
+        let momentum = T::from_f64(self.momentum);
+        slots[0] *= momentum;
+        slots[0] += grad;
+        let step = grad + &slots[0] * momentum;
+        param - step * T::from_f64(self.lr)
+    }
+
+    fn state(&self) -> OptimizerState<T> {
+        OptimizerState {
+            name: self.name().to_string(),
+            hyperparameters: vec![self.lr, self.momentum],
+            step: 0,
+            slots: self.slots.clone(),
+        }
+    }
+
+    fn load_state(&mut self, state: OptimizerState<T>) {
+        self.slots = state.slots;
+    }
+
+    fn box_clone(&self) -> Box<dyn Optimizer<T>> {
+        Box::new(self.clone())
+    }
+}
+
+// G += g^2, p -= lr * g / (sqrt(G) + eps)
+#[derive(Debug, Clone)]
+pub struct AdaGrad<T = f64> {
+    lr: f64,
+    epsilon: f64,
+    slots: Vec<Vec<Matrix<T>>>,
+}
+
+impl<T: Scalar> AdaGrad<T> {
+    pub fn new(lr: f64) -> AdaGrad<T> {
+        AdaGrad {
+            lr,
+            epsilon: 1e-8,
+            slots: Vec::new(),
+        }
+    }
+
+    pub fn with_epsilon(mut self, epsilon: f64) -> AdaGrad<T> {
+        self.epsilon = epsilon;
+        self
+    }
+}
+
+impl<T: Scalar> Optimizer<T> for AdaGrad<T> {
+    fn name(&self) -> &'static str {
+        "adagrad"
+    }
+
+    fn lr(&self) -> f64 {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    fn update(&mut self, index: usize, param: &Matrix<T>, grad: &Matrix<T>) -> Matrix<T> {
+        let slots = slots_for(&mut self.slots, index, 1, param);
+        slots[0] += grad * grad;
+        let step = scale_by_root(grad, &slots[0], self.epsilon);
+        param - step * T::from_f64(self.lr)
+    }
+
+    fn state(&self) -> OptimizerState<T> {
+        OptimizerState {
+            name: self.name().to_string(),
+            hyperparameters: vec![self.lr, self.epsilon],
+            step: 0,
+            slots: self.slots.clone(),
+        }
+    }
+
+    fn load_state(&mut self, state: OptimizerState<T>) {
+        self.slots = state.slots;
+    }
+
+    fn box_clone(&self) -> Box<dyn Optimizer<T>> {
+        Box::new(self.clone())
+    }
+}
+
+// s = rho * s + (1 - rho) * g^2, p -= lr * g / (sqrt(s) + eps)
+#[derive(Debug, Clone)]
+pub struct RMSProp<T = f64> {
+    lr: f64,
+    rho: f64,
+    epsilon: f64,
+    slots: Vec<Vec<Matrix<T>>>,
+}
+
+impl<T: Scalar> RMSProp<T> {
+    pub fn new(lr: f64, rho: f64) -> RMSProp<T> {
+        RMSProp {
+            lr,
+            rho,
+            epsilon: 1e-8,
+            slots: Vec::new(),
+        }
+    }
+
+    pub fn with_epsilon(mut self, epsilon: f64) -> RMSProp<T> {
+        self.epsilon = epsilon;
+        self
+    }
+}
+
+impl<T: Scalar> Optimizer<T> for RMSProp<T> {
+    fn name(&self) -> &'static str {
+        "rmsprop"
+    }
+
+    fn lr(&self) -> f64 {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    fn update(&mut self, index: usize, param: &Matrix<T>, grad: &Matrix<T>) -> Matrix<T> {
+        let slots = slots_for(&mut self.slots, index, 1, param);
+        slots[0] *= T::from_f64(self.rho);
+        slots[0] += grad * grad * T::from_f64(1.0 - self.rho);
+        let step = scale_by_root(grad, &slots[0], self.epsilon);
+        param - step * T::from_f64(self.lr)
+    }
+
+    fn state(&self) -> OptimizerState<T> {
+        OptimizerState {
+            name: self.name().to_string(),
+            hyperparameters: vec![self.lr, self.rho, self.epsilon],
+            step: 0,
+            slots: self.slots.clone(),
+        }
+    }
+
+    fn load_state(&mut self, state: OptimizerState<T>) {
+        self.slots = state.slots;
+    }
+
+    fn box_clone(&self) -> Box<dyn Optimizer<T>> {
+        Box::new(self.clone())
+    }
+}
+
+// bias-corrected first and second moments:
+// m = b1 * m + (1 - b1) * g, v = b2 * v + (1 - b2) * g^2
+// p -= lr * m_hat / (sqrt(v_hat) + eps)
+#[derive(Debug, Clone)]
+pub struct Adam<T = f64> {
+    lr: f64,
+    beta1: f64,
+    beta2: f64,
+    epsilon: f64,
+    step: u64,
+    slots: Vec<Vec<Matrix<T>>>,
+}
+
+impl<T: Scalar> Adam<T> {
+    pub fn new(lr: f64, beta1: f64, beta2: f64) -> Adam<T> {
+        Adam {
+            lr,
+            beta1,
+            beta2,
+            epsilon: 1e-8,
+            step: 0,
+            slots: Vec::new(),
+        }
+    }
+
+    pub fn with_epsilon(mut self, epsilon: f64) -> Adam<T> {
+        self.epsilon = epsilon;
+        self
+    }
+
+    fn adam_step(&mut self, index: usize, param: &Matrix<T>, grad: &Matrix<T>) -> Matrix<T> {
+        let step = self.step.max(1) as i32;
+        let (beta1, beta2) = (self.beta1, self.beta2);
+        let slots = slots_for(&mut self.slots, index, 2, param);
+        slots[0] *= T::from_f64(beta1);
+        slots[0] += grad * T::from_f64(1.0 - beta1);
+        slots[1] *= T::from_f64(beta2);
+        slots[1] += grad * grad * T::from_f64(1.0 - beta2);
+        let m_hat = &slots[0] / T::from_f64(1.0 - beta1.powi(step));
+        let v_hat = &slots[1] / T::from_f64(1.0 - beta2.powi(step));
+        scale_by_root(&m_hat, &v_hat, self.epsilon) * T::from_f64(self.lr)
+    }
+}
+
+impl<T: Scalar> Optimizer<T> for Adam<T> {
+    fn name(&self) -> &'static str {
+        "adam"
+    }
+
+    fn lr(&self) -> f64 {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    fn begin_step(&mut self) {
+        self.step += 1;
+    }
+
+    fn update(&mut self, index: usize, param: &Matrix<T>, grad: &Matrix<T>) -> Matrix<T> {
+        let step = self.adam_step(index, param, grad);
+        param - step
+    }
+
+    fn state(&self) -> OptimizerState<T> {
+        OptimizerState {
+            name: self.name().to_string(),
+            hyperparameters: vec![self.lr, self.beta1, self.beta2, self.epsilon],
+            step: self.step,
+            slots: self.slots.clone(),
+        }
+    }
+
+    fn load_state(&mut self, state: OptimizerState<T>) {
+        self.step = state.step;
+        self.slots = state.slots;
+    }
+
+    fn box_clone(&self) -> Box<dyn Optimizer<T>> {
+        Box::new(self.clone())
+    }
+}
+
+// Adam with decoupled weight decay: p -= lr * weight_decay * p before the Adam step
+#[derive(Debug, Clone)]
+pub struct AdamW<T = f64> {
+    adam: Adam<T>,
+    weight_decay: f64,
+}
+
+impl<T: Scalar> AdamW<T> {
+    pub fn new(lr: f64, beta1: f64, beta2: f64, weight_decay: f64) -> AdamW<T> {
+        AdamW {
+            adam: Adam::new(lr, beta1, beta2),
+            weight_decay,
+        }
+    }
+
+    pub fn with_epsilon(mut self, epsilon: f64) -> AdamW<T> {
+        self.adam.epsilon = epsilon;
+        self
+    }
+}
+
+impl<T: Scalar> Optimizer<T> for AdamW<T> {
+    fn name(&self) -> &'static str {
+        "adamw"
+    }
+
+    fn lr(&self) -> f64 {
+        self.adam.lr
+    }
+
+    fn set_lr(&mut self, lr: f64) {
+        self.adam.lr = lr;
+    }
+
+    fn begin_step(&mut self) {
+        self.adam.begin_step();
+    }
+
+    fn update(&mut self, index: usize, param: &Matrix<T>, grad: &Matrix<T>) -> Matrix<T> {
+        let decayed = param - param * T::from_f64(self.adam.lr * self.weight_decay);
+        let step = self.adam.adam_step(index, param, grad);
+        decayed - step
+    }
+
+    fn state(&self) -> OptimizerState<T> {
+        let mut state = self.adam.state();
+        state.name = self.name().to_string();
+        state.hyperparameters.push(self.weight_decay);
+        state
+    }
+
+    fn load_state(&mut self, state: OptimizerState<T>) {
+        self.adam.load_state(state);
+    }
+
+    fn box_clone(&self) -> Box<dyn Optimizer<T>> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod optimizer_tests {
+    use super::*;
+
+    fn all() -> Vec<Box<dyn Optimizer>> {
+        vec![
+            Box::new(Sgd::new(0.1)),
+            Box::new(Momentum::new(0.05, 0.9)),
+            Box::new(Nesterov::new(0.05, 0.9)),
+            Box::new(AdaGrad::new(0.5)),
+            Box::new(RMSProp::new(0.05, 0.9)),
+            Box::new(Adam::new(0.05, 0.9, 0.999)),
+            Box::new(AdamW::new(0.05, 0.9, 0.999, 0.0)),
+        ]
+    }
+
+    // minimise 0.5 * |p - target|^2, whose gradient is p - target
+    #[test]
+    fn test_converges_on_quadratic() {
+        let target = Matrix::new(vec![vec![1.0, -2.0], vec![0.5, 3.0]]);
+        for mut optimizer in all() {
+            let mut param = Matrix::zeros(2, 2);
+            for _i in 0..500 {
+                optimizer.begin_step();
+                let grad = param.sub(&target);
+                param = optimizer.update(0, &param, &grad);
+            }
+            let err = param.sub(&target);
+            println!("{}: error {}", optimizer.name(), err.dot(&err));
+            assert!(
+                err.dot(&err) < 1e-3,
+                "{} did not converge",
+                optimizer.name()
+            );
+        }
+    }
+
+    #[test]
+    fn test_momentum_accumulates_velocity() {
+        let mut optimizer: Momentum = Momentum::new(0.1, 0.5);
+        let grad = Matrix::ones(1, 1);
+        let p = optimizer.update(0, &Matrix::zeros(1, 1), &grad);
+        assert!((p.get(0, 0) + 0.1).abs() < 1e-12);
+        let p = optimizer.update(0, &p, &grad);
+        // v = 0.5 * 1 + 1 = 1.5
+        assert!((p.get(0, 0) + 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_adam_first_step_is_lr_sized() {
+        let mut optimizer = Adam::new(0.01, 0.9, 0.999);
+        optimizer.begin_step();
+        let grad = Matrix::new(vec![vec![250.0, -0.003]]);
+        let p = optimizer.update(0, &Matrix::zeros(1, 2), &grad);
+        assert!((p.get(0, 0) + 0.01).abs() < 1e-6);
+        assert!((p.get(0, 1) - 0.01).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_adamw_decays_weights() {
+        let mut optimizer: AdamW = AdamW::new(0.1, 0.9, 0.999, 0.5);
+        optimizer.begin_step();
+        let p = optimizer.update(0, &Matrix::ones(1, 1), &Matrix::zeros(1, 1));
+        assert!((p.get(0, 0) - 0.95).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        for mut optimizer in all() {
+            let grad = Matrix::new(vec![vec![0.3, -0.7]]);
+            let mut param = Matrix::ones(1, 2);
+            for index in 0..3 {
+                optimizer.begin_step();
+                param = optimizer.update(index, &param, &grad);
+            }
+            let mut restored = from_state(optimizer.state()).unwrap();
+            assert_eq!(restored.name(), optimizer.name());
+            optimizer.begin_step();
+            restored.begin_step();
+            let a = optimizer.update(1, &param, &grad);
+            let b = restored.update(1, &param, &grad);
+            assert_eq!(a.data, b.data);
+        }
+    }
+
+    #[test]
+    fn test_from_state_unknown() {
+        let state: OptimizerState = OptimizerState {
+            name: "lbfgs".to_string(),
+            hyperparameters: vec![0.1],
+            step: 0,
+            slots: Vec::new(),
+        };
+        assert!(from_state(state).is_none());
+    }
+}
@@ -0,0 +1,179 @@
+use crate::matrix::{Matrix, MatrixOps};
+use crate::scalar::Scalar;
+
+// Weight penalty l1 * sum(|w|) + l2 * sum(w^2) added to the loss of every
+// batch, so its gradient l1 * sign(w) + 2 * l2 * w is added once per update.
+// Biases are not penalized.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Regularizer {
+    pub l1: f64,
+    pub l2: f64,
+}
+
+impl Regularizer {
+    pub fn new(l1: f64, l2: f64) -> Regularizer {
+        assert!(l1 >= 0.0 && l2 >= 0.0);
+        Regularizer { l1, l2 }
+    }
+
+    pub fn l1(l1: f64) -> Regularizer {
+        Regularizer::new(l1, 0.0)
+    }
+
+    pub fn l2(l2: f64) -> Regularizer {
+        Regularizer::new(0.0, l2)
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.l1 == 0.0 && self.l2 == 0.0
+    }
+
+    pub fn penalty<T: Scalar>(&self, weights: &Matrix<T>) -> f64 {
+        if self.is_none() {
+            return 0.0;
+        }
+        weights
+            .as_slice()
+            .iter()
+            .map(|w| {
+                let w = w.to_f64();
+                self.l1 * w.abs() + self.l2 * w * w
+            })
+            .sum()
+    }
+
+    // subgradient, sign(0) is taken as 0
+    pub fn gradient<T: Scalar>(&self, weights: &Matrix<T>) -> Matrix<T> {
+        let (l1, l2) = (T::from_f64(self.l1), T::from_f64(self.l2 * 2.0));
+        weights.map(|w| {
+            let sign = if w > T::zero() {
+                T::one()
+            } else if w < T::zero() {
+                -T::one()
+            } else {
+                T::zero()
+            };
+            l1 * sign + l2 * w
+        })
+    }
+}
+
+// Gradient clipping applied to all weight and bias gradients of a batch
+// before the optimizer sees them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Clipping {
+    #[default]
+    None,
+    // clamp every element into [-limit, limit]
+    Value(f64),
+    // rescale all gradients together so their joint L2 norm is at most max
+    GlobalNorm(f64),
+}
+
+impl Clipping {
+    pub fn apply<T: Scalar>(&self, gradients: &mut [&mut Matrix<T>]) {
+        match *self {
+            Clipping::None => {}
+            Clipping::Value(limit) => {
+                let (low, high) = (T::from_f64(-limit), T::from_f64(limit));
+                for gradient in gradients.iter_mut() {
+                    gradient.map_in_place(|g| g.clamp(low, high));
+                }
+            }
+            Clipping::GlobalNorm(max) => {
+                let norm = global_norm(gradients);
+                if norm > max {
+                    let scale = T::from_f64(max / norm);
+                    for gradient in gradients.iter_mut() {
+                        gradient.map_in_place(|g| g * scale);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// L2 norm of all gradients taken as one vector
+pub fn global_norm<T: Scalar>(gradients: &[&mut Matrix<T>]) -> f64 {
+    gradients
+        .iter()
+        .flat_map(|gradient| gradient.as_slice().iter())
+        .map(|g| g.to_f64() * g.to_f64())
+        .sum::<f64>()
+        .sqrt()
+}
+
+// Rescales every row (the incoming weights of one output unit) whose L2
+// norm exceeds max down to exactly max.
+pub fn apply_max_norm<T: Scalar>(weights: &mut Matrix<T>, max: f64) {
+    let cols = weights.cols;
+    for row in weights.data.chunks_mut(cols) {
+        let norm = row
+            .iter()
+            .map(|w| w.to_f64() * w.to_f64())
+            .sum::<f64>()
+            .sqrt();
+        if norm > max {
+            let scale = T::from_f64(max / norm);
+            for w in row.iter_mut() {
+                *w *= scale;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod regularization_tests {
+    use super::{apply_max_norm, global_norm, Clipping, Regularizer};
+    use crate::matrix::{Matrix, MatrixOps};
+    use crate::test_util::{assert_gradient, numeric_gradient};
+
+    #[test]
+    fn test_penalty_gradient_matches_numeric() {
+        let weights = Matrix::new(vec![vec![0.5, -1.5, 0.25], vec![-0.1, 2.0, 0.75]]);
+        let reg = Regularizer::new(0.01, 0.05);
+        assert!((reg.penalty(&weights) - (0.01 * 5.1 + 0.05 * 7.135)).abs() < 1e-12);
+        let gradient = reg.gradient(&weights);
+        let numeric = numeric_gradient(&weights, 1e-6, |weights| reg.penalty(weights));
+        assert_gradient("penalty", &numeric, &gradient, 1e-8);
+        // the analytic form
+        let w = weights[(0, 1)];
+        assert_eq!(gradient[(0, 1)], -0.01 + 2.0 * 0.05 * w);
+        let zero = Regularizer::l1(0.1).gradient(&Matrix::new(vec![vec![0.0]]));
+        assert_eq!(zero[(0, 0)], 0.0);
+        assert!(Regularizer::default().is_none());
+        assert_eq!(Regularizer::default().penalty(&weights), 0.0);
+    }
+
+    #[test]
+    fn test_clipping() {
+        let mut a = Matrix::new(vec![vec![3.0, -4.0]]);
+        let mut b = Matrix::new(vec![vec![0.5], vec![-12.0]]);
+        Clipping::Value(1.0).apply(&mut [&mut a, &mut b]);
+        assert_eq!(a.as_slice(), &[1.0, -1.0][..]);
+        assert_eq!(b.as_slice(), &[0.5, -1.0][..]);
+
+        // joint norm 13 scaled to 6.5
+        let mut a = Matrix::new(vec![vec![3.0, -4.0]]);
+        let mut b = Matrix::new(vec![vec![0.0], vec![-12.0]]);
+        Clipping::GlobalNorm(6.5).apply(&mut [&mut a, &mut b]);
+        assert_eq!(a.as_slice(), &[1.5, -2.0][..]);
+        assert_eq!(b.as_slice(), &[0.0, -6.0][..]);
+        assert!((global_norm(&[&mut a, &mut b]) - 6.5).abs() < 1e-12);
+        // below the limit nothing changes
+        Clipping::GlobalNorm(10.0).apply(&mut [&mut a, &mut b]);
+        assert_eq!(a.as_slice(), &[1.5, -2.0][..]);
+        Clipping::None.apply(&mut [&mut a]);
+        assert_eq!(a.as_slice(), &[1.5, -2.0][..]);
+    }
+
+    #[test]
+    fn test_max_norm() {
+        let mut weights: Matrix = Matrix::new(vec![vec![3.0, 4.0], vec![0.3, 0.4]]);
+        apply_max_norm(&mut weights, 1.0);
+        assert!((weights[(0, 0)] - 0.6).abs() < 1e-12);
+        assert!((weights[(0, 1)] - 0.8).abs() < 1e-12);
+        assert_eq!(weights[(1, 0)], 0.3);
+        assert_eq!(weights[(1, 1)], 0.4);
+    }
+}
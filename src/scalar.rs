@@ -0,0 +1,348 @@
+use std::fmt;
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+
+// Element type of a Matrix. Hyperparameters (learning rates, activation
+// parameters, ...) and reported losses stay f64 and are converted with
+// from_f64 / to_f64.
+pub trait Scalar:
+    Copy
+    + Default
+    + PartialEq
+    + PartialOrd
+    + fmt::Debug
+    + fmt::Display
+    + Send
+    + Sync
+    + 'static
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+    + AddAssign
+    + SubAssign
+    + MulAssign
+    + DivAssign
+{
+    const NAME: &'static str;
+
+    fn zero() -> Self;
+    fn one() -> Self;
+    // smallest positive value with 1 + epsilon != 1
+    fn epsilon() -> Self;
+    fn from_f64(x: f64) -> Self;
+    fn to_f64(self) -> f64;
+
+    fn abs(self) -> Self;
+    fn max(self, other: Self) -> Self;
+    fn min(self, other: Self) -> Self;
+    fn clamp(self, min: Self, max: Self) -> Self;
+    fn sqrt(self) -> Self;
+    fn exp(self) -> Self;
+    fn exp_m1(self) -> Self;
+    fn ln(self) -> Self;
+    fn ln_1p(self) -> Self;
+    fn tanh(self) -> Self;
+    fn powi(self, n: i32) -> Self;
+    fn powf(self, n: Self) -> Self;
+    fn is_finite(self) -> bool;
+}
+
+macro_rules! float_scalar {
+    ($t:ident) => {
+        impl Scalar for $t {
+            const NAME: &'static str = stringify!($t);
+
+            fn zero() -> $t {
+                0.0
+            }
+
+            fn one() -> $t {
+                1.0
+            }
+
+            fn epsilon() -> $t {
+                $t::EPSILON
+            }
+
+            fn from_f64(x: f64) -> $t {
+                x as $t
+            }
+
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+
+            fn abs(self) -> $t {
+                $t::abs(self)
+            }
+
+            fn max(self, other: $t) -> $t {
+                $t::max(self, other)
+            }
+
+            fn min(self, other: $t) -> $t {
+                $t::min(self, other)
+            }
+
+            fn clamp(self, min: $t, max: $t) -> $t {
+                $t::clamp(self, min, max)
+            }
+
+            fn sqrt(self) -> $t {
+                $t::sqrt(self)
+            }
+
+            fn exp(self) -> $t {
+                $t::exp(self)
+            }
+
+            fn exp_m1(self) -> $t {
+                $t::exp_m1(self)
+            }
+
+            fn ln(self) -> $t {
+                $t::ln(self)
+            }
+
+            fn ln_1p(self) -> $t {
+                $t::ln_1p(self)
+            }
+
+            fn tanh(self) -> $t {
+                $t::tanh(self)
+            }
+
+            fn powi(self, n: i32) -> $t {
+                $t::powi(self, n)
+            }
+
+            fn powf(self, n: $t) -> $t {
+                $t::powf(self, n)
+            }
+
+            fn is_finite(self) -> bool {
+                $t::is_finite(self)
+            }
+        }
+    };
+}
+
+float_scalar!(f32);
+float_scalar!(f64);
+
+// Signed Q32.32 fixed-point number. Arithmetic saturates instead of
+// overflowing, transcendental functions go through f64.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fixed(i64);
+
+const FRAC_BITS: u32 = 32;
+const FRAC_SCALE: f64 = (1u64 << FRAC_BITS) as f64;
+
+impl Fixed {
+    pub fn from_bits(bits: i64) -> Fixed {
+        Fixed(bits)
+    }
+
+    pub fn to_bits(self) -> i64 {
+        self.0
+    }
+
+    fn saturate(x: i128) -> Fixed {
+        Fixed(x.clamp(i64::MIN as i128, i64::MAX as i128) as i64)
+    }
+
+    fn via_f64<F: Fn(f64) -> f64>(self, f: F) -> Fixed {
+        Fixed::from_f64(f(self.to_f64()))
+    }
+}
+
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_f64())
+    }
+}
+
+impl Add for Fixed {
+    type Output = Fixed;
+
+    fn add(self, b: Fixed) -> Fixed {
+        Fixed(self.0.saturating_add(b.0))
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Fixed;
+
+    fn sub(self, b: Fixed) -> Fixed {
+        Fixed(self.0.saturating_sub(b.0))
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Fixed;
+
+    fn mul(self, b: Fixed) -> Fixed {
+        Fixed::saturate((self.0 as i128 * b.0 as i128) >> FRAC_BITS)
+    }
+}
+
+impl Div for Fixed {
+    type Output = Fixed;
+
+    fn div(self, b: Fixed) -> Fixed {
+        if b.0 == 0 {
+            return if self.0 < 0 {
+                Fixed(i64::MIN)
+            } else {
+                Fixed(i64::MAX)
+            };
+        }
+        Fixed::saturate(((self.0 as i128) << FRAC_BITS) / b.0 as i128)
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Fixed;
+
+    fn neg(self) -> Fixed {
+        Fixed(self.0.saturating_neg())
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, b: Fixed) {
+        *self = *self + b;
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, b: Fixed) {
+        *self = *self - b;
+    }
+}
+
+impl MulAssign for Fixed {
+    fn mul_assign(&mut self, b: Fixed) {
+        *self = *self * b;
+    }
+}
+
+impl DivAssign for Fixed {
+    fn div_assign(&mut self, b: Fixed) {
+        *self = *self / b;
+    }
+}
+
+impl Scalar for Fixed {
+    const NAME: &'static str = "fixed";
+
+    fn zero() -> Fixed {
+        Fixed(0)
+    }
+
+    fn one() -> Fixed {
+        Fixed(1 << FRAC_BITS)
+    }
+
+    fn epsilon() -> Fixed {
+        Fixed(1)
+    }
+
+    // rounds to the nearest representable value, saturating out of range
+    fn from_f64(x: f64) -> Fixed {
+        Fixed((x * FRAC_SCALE).round() as i64)
+    }
+
+    fn to_f64(self) -> f64 {
+        self.0 as f64 / FRAC_SCALE
+    }
+
+    fn abs(self) -> Fixed {
+        Fixed(self.0.saturating_abs())
+    }
+
+    fn max(self, other: Fixed) -> Fixed {
+        Ord::max(self, other)
+    }
+
+    fn min(self, other: Fixed) -> Fixed {
+        Ord::min(self, other)
+    }
+
+    fn clamp(self, min: Fixed, max: Fixed) -> Fixed {
+        Ord::clamp(self, min, max)
+    }
+
+    fn sqrt(self) -> Fixed {
+        self.via_f64(f64::sqrt)
+    }
+
+    fn exp(self) -> Fixed {
+        self.via_f64(f64::exp)
+    }
+
+    fn exp_m1(self) -> Fixed {
+        self.via_f64(f64::exp_m1)
+    }
+
+    fn ln(self) -> Fixed {
+        self.via_f64(f64::ln)
+    }
+
+    fn ln_1p(self) -> Fixed {
+        self.via_f64(f64::ln_1p)
+    }
+
+    fn tanh(self) -> Fixed {
+        self.via_f64(f64::tanh)
+    }
+
+    fn powi(self, n: i32) -> Fixed {
+        self.via_f64(|x| x.powi(n))
+    }
+
+    fn powf(self, n: Fixed) -> Fixed {
+        self.via_f64(|x| x.powf(n.to_f64()))
+    }
+
+    fn is_finite(self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod scalar_tests {
+    use super::{Fixed, Scalar};
+
+    #[test]
+    fn test_fixed_arithmetic() {
+        let a = Fixed::from_f64(1.5);
+        let b = Fixed::from_f64(-0.25);
+        assert_eq!((a + b).to_f64(), 1.25);
+        assert_eq!((a - b).to_f64(), 1.75);
+        assert_eq!((a * b).to_f64(), -0.375);
+        assert_eq!((a / b).to_f64(), -6.0);
+        assert_eq!((-a).to_f64(), -1.5);
+        assert!(b < a);
+        assert_eq!(Fixed::one().to_f64(), 1.0);
+        assert!((Fixed::from_f64(2.0).sqrt().to_f64() - 2f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_fixed_saturates() {
+        let big = Fixed::from_f64(2e9);
+        assert_eq!((big * big).to_bits(), i64::MAX);
+        assert_eq!((-big * big).to_bits(), i64::MIN);
+        assert_eq!((Fixed::one() / Fixed::zero()).to_bits(), i64::MAX);
+        assert_eq!(Fixed::from_f64(1e30).to_bits(), i64::MAX);
+    }
+
+    #[test]
+    fn test_float_round_trip() {
+        assert_eq!(f64::from_f64(0.1), 0.1);
+        assert_eq!(f32::from_f64(0.1), 0.1f32);
+        assert_eq!(<f32 as Scalar>::NAME, "f32");
+        assert_eq!(<f64 as Scalar>::NAME, "f64");
+    }
+}
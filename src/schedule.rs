@@ -0,0 +1,365 @@
+use std::f64::consts::PI;
+
+// Learning rate as a function of a step counter. The trainer counts epochs,
+// a hand-written loop may count batches instead. `lr` only reads the state so
+// it can be queried for logging at any time, `observe` feeds back the
+// monitored metric for schedules that react to it.
+pub trait Schedule {
+    fn lr(&self, step: usize) -> f64;
+
+    fn observe(&mut self, _value: f64) {}
+}
+
+// any closure from step to learning rate
+impl<F: Fn(usize) -> f64> Schedule for F {
+    fn lr(&self, step: usize) -> f64 {
+        self(step)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Constant {
+    lr: f64,
+}
+
+impl Constant {
+    pub fn new(lr: f64) -> Constant {
+        Constant { lr }
+    }
+}
+
+impl Schedule for Constant {
+    fn lr(&self, _step: usize) -> f64 {
+        self.lr
+    }
+}
+
+// lr * gamma^(step / step_size)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepDecay {
+    lr: f64,
+    step_size: usize,
+    gamma: f64,
+}
+
+impl StepDecay {
+    pub fn new(lr: f64, step_size: usize, gamma: f64) -> StepDecay {
+        assert!(step_size > 0);
+        StepDecay {
+            lr,
+            step_size,
+            gamma,
+        }
+    }
+}
+
+impl Schedule for StepDecay {
+    fn lr(&self, step: usize) -> f64 {
+        self.lr * self.gamma.powi((step / self.step_size) as i32)
+    }
+}
+
+// lr * gamma^step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExponentialDecay {
+    lr: f64,
+    gamma: f64,
+}
+
+impl ExponentialDecay {
+    pub fn new(lr: f64, gamma: f64) -> ExponentialDecay {
+        ExponentialDecay { lr, gamma }
+    }
+}
+
+impl Schedule for ExponentialDecay {
+    fn lr(&self, step: usize) -> f64 {
+        self.lr * self.gamma.powi(step as i32)
+    }
+}
+
+// Cosine annealing from max_lr to min_lr, restarting after `period` steps.
+// Every period is `mult` times longer than the one before (SGDR).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CosineWarmRestarts {
+    max_lr: f64,
+    min_lr: f64,
+    period: usize,
+    mult: usize,
+}
+
+impl CosineWarmRestarts {
+    pub fn new(max_lr: f64, min_lr: f64, period: usize) -> CosineWarmRestarts {
+        assert!(period > 0);
+        CosineWarmRestarts {
+            max_lr,
+            min_lr,
+            period,
+            mult: 1,
+        }
+    }
+
+    pub fn with_mult(mut self, mult: usize) -> CosineWarmRestarts {
+        assert!(mult > 0);
+        self.mult = mult;
+        self
+    }
+}
+
+impl Schedule for CosineWarmRestarts {
+    fn lr(&self, step: usize) -> f64 {
+        let (mut current, mut period) = (step, self.period);
+        while current >= period {
+            current -= period;
+            period *= self.mult;
+        }
+        let progress = current as f64 / period as f64;
+        self.min_lr + (self.max_lr - self.min_lr) * (1.0 + (PI * progress).cos()) / 2.0
+    }
+}
+
+// Ramps linearly up to the wrapped schedule over the first `steps` steps,
+// then follows it with its step counter starting at zero.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinearWarmup<S> {
+    steps: usize,
+    after: S,
+}
+
+impl<S: Schedule> LinearWarmup<S> {
+    pub fn new(steps: usize, after: S) -> LinearWarmup<S> {
+        LinearWarmup { steps, after }
+    }
+}
+
+impl<S: Schedule> Schedule for LinearWarmup<S> {
+    fn lr(&self, step: usize) -> f64 {
+        if step < self.steps {
+            self.after.lr(0) * (step + 1) as f64 / (self.steps + 1) as f64
+        } else {
+            self.after.lr(step - self.steps)
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        self.after.observe(value);
+    }
+}
+
+// One-cycle policy: cosine ramp from max_lr / div_factor up to max_lr over
+// the first pct_start of total_steps, then cosine annealing down to
+// max_lr / (div_factor * final_div_factor) at the last step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OneCycle {
+    max_lr: f64,
+    total_steps: usize,
+    pct_start: f64,
+    div_factor: f64,
+    final_div_factor: f64,
+}
+
+impl OneCycle {
+    pub fn new(max_lr: f64, total_steps: usize) -> OneCycle {
+        assert!(total_steps >= 2);
+        OneCycle {
+            max_lr,
+            total_steps,
+            pct_start: 0.3,
+            div_factor: 25.0,
+            final_div_factor: 1e4,
+        }
+    }
+
+    pub fn with_pct_start(mut self, pct_start: f64) -> OneCycle {
+        assert!(pct_start > 0.0 && pct_start < 1.0);
+        self.pct_start = pct_start;
+        self
+    }
+
+    pub fn with_div_factors(mut self, div_factor: f64, final_div_factor: f64) -> OneCycle {
+        self.div_factor = div_factor;
+        self.final_div_factor = final_div_factor;
+        self
+    }
+}
+
+fn cosine_between(start: f64, end: f64, progress: f64) -> f64 {
+    end + (start - end) * (1.0 + (PI * progress).cos()) / 2.0
+}
+
+impl Schedule for OneCycle {
+    fn lr(&self, step: usize) -> f64 {
+        let initial = self.max_lr / self.div_factor;
+        let last = initial / self.final_div_factor;
+        let peak =
+            ((self.pct_start * self.total_steps as f64) as usize).clamp(1, self.total_steps - 1);
+        let end = self.total_steps - 1;
+        if step <= peak {
+            cosine_between(initial, self.max_lr, step as f64 / peak as f64)
+        } else if step < end {
+            cosine_between(
+                self.max_lr,
+                last,
+                (step - peak) as f64 / (end - peak) as f64,
+            )
+        } else {
+            last
+        }
+    }
+}
+
+// Multiplies the rate by `factor` once the observed value (usually the
+// validation loss) has not dropped by more than `threshold` for `patience`
+// observations in a row, never going below min_lr.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReduceOnPlateau {
+    lr: f64,
+    factor: f64,
+    patience: usize,
+    threshold: f64,
+    min_lr: f64,
+    best: Option<f64>,
+    wait: usize,
+}
+
+impl ReduceOnPlateau {
+    pub fn new(lr: f64, factor: f64, patience: usize) -> ReduceOnPlateau {
+        assert!(factor > 0.0 && factor < 1.0);
+        ReduceOnPlateau {
+            lr,
+            factor,
+            patience,
+            threshold: 0.0,
+            min_lr: 0.0,
+            best: None,
+            wait: 0,
+        }
+    }
+
+    pub fn with_threshold(mut self, threshold: f64) -> ReduceOnPlateau {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_min_lr(mut self, min_lr: f64) -> ReduceOnPlateau {
+        self.min_lr = min_lr;
+        self
+    }
+}
+
+impl Schedule for ReduceOnPlateau {
+    fn lr(&self, _step: usize) -> f64 {
+        self.lr
+    }
+
+    fn observe(&mut self, value: f64) {
+        match self.best {
+            Some(best) if value >= best - self.threshold => {
+                self.wait += 1;
+                if self.wait > self.patience {
+                    self.lr = (self.lr * self.factor).max(self.min_lr);
+                    self.wait = 0;
+                }
+            }
+            _ => {
+                self.best = Some(value);
+                self.wait = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod schedule_tests {
+    use super::{
+        Constant, CosineWarmRestarts, ExponentialDecay, LinearWarmup, OneCycle, ReduceOnPlateau,
+        Schedule, StepDecay,
+    };
+
+    fn lrs<S: Schedule>(schedule: &S, steps: usize) -> Vec<f64> {
+        (0..steps).map(|step| schedule.lr(step)).collect()
+    }
+
+    fn assert_close(a: &[f64], b: &[f64]) {
+        println!("{:?}", a);
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(b.iter()) {
+            assert!((x - y).abs() < 1e-12, "{} != {}", x, y);
+        }
+    }
+
+    #[test]
+    fn test_decay() {
+        assert_close(&lrs(&Constant::new(0.3), 2), &[0.3, 0.3]);
+        assert_close(
+            &lrs(&StepDecay::new(0.4, 2, 0.5), 6),
+            &[0.4, 0.4, 0.2, 0.2, 0.1, 0.1],
+        );
+        assert_close(&lrs(&ExponentialDecay::new(1.0, 0.9), 3), &[1.0, 0.9, 0.81]);
+        let closure = |step: usize| 1.0 / (step + 1) as f64;
+        assert_close(&lrs(&closure, 3), &[1.0, 0.5, 1.0 / 3.0]);
+    }
+
+    #[test]
+    fn test_cosine_warm_restarts() {
+        let schedule = CosineWarmRestarts::new(1.0, 0.0, 4);
+        assert_close(
+            &lrs(&schedule, 6),
+            &[
+                1.0,
+                0.8535533905932737,
+                0.5,
+                0.14644660940672627,
+                1.0,
+                0.8535533905932737,
+            ],
+        );
+        // periods 2, 4, 8, ...
+        let schedule = CosineWarmRestarts::new(1.0, 0.1, 2).with_mult(2);
+        let values = lrs(&schedule, 7);
+        assert_eq!(values[0], 1.0);
+        assert_eq!(values[2], 1.0);
+        assert_eq!(values[6], 1.0);
+        assert!((values[4] - 0.55).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_linear_warmup() {
+        let schedule = LinearWarmup::new(3, StepDecay::new(0.8, 2, 0.5));
+        assert_close(&lrs(&schedule, 7), &[0.2, 0.4, 0.6, 0.8, 0.8, 0.4, 0.4]);
+    }
+
+    #[test]
+    fn test_one_cycle() {
+        let schedule = OneCycle::new(1.0, 11).with_pct_start(0.2);
+        let values = lrs(&schedule, 12);
+        assert!((values[0] - 0.04).abs() < 1e-12);
+        assert_eq!(values[2], 1.0);
+        assert!((values[1] - 0.52).abs() < 1e-12);
+        assert!(values[3..11].windows(2).all(|w| w[1] < w[0]));
+        assert!((values[10] - 0.04 / 1e4).abs() < 1e-15);
+        assert_eq!(values[11], values[10]);
+    }
+
+    #[test]
+    fn test_reduce_on_plateau() {
+        let mut schedule = ReduceOnPlateau::new(1.0, 0.5, 1)
+            .with_threshold(0.01)
+            .with_min_lr(0.2);
+        let losses = [1.0, 0.9, 0.895, 0.9, 0.5, 0.6, 0.7, 0.8, 0.9, 1.0, 1.1];
+        let mut values = Vec::new();
+        for (step, loss) in losses.iter().enumerate() {
+            schedule.observe(*loss);
+            values.push(schedule.lr(step));
+        }
+        assert_close(
+            &values,
+            &[1.0, 1.0, 1.0, 0.5, 0.5, 0.5, 0.25, 0.25, 0.2, 0.2, 0.2],
+        );
+        // a warmup forwards the observations
+        let mut warmup = LinearWarmup::new(1, ReduceOnPlateau::new(1.0, 0.5, 0));
+        warmup.observe(1.0);
+        warmup.observe(2.0);
+        assert_eq!(warmup.lr(1), 0.5);
+    }
+}
@@ -0,0 +1,1442 @@
+use crate::activation::Activation;
+use crate::conv::Conv2d;
+use crate::dropout::Dropout;
+use crate::layer::{ActivationLayer, Dense, Layer, Reshape};
+use crate::matrix::Matrix;
+use crate::nn::NeuralNetwork;
+use crate::normalization::{BatchNorm, LayerNorm};
+use crate::optimizer::{from_state, slot_count, OptimizerState, Sgd};
+use crate::scalar::Scalar;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+// On-disk layout (all integers and floats little-endian):
+//   magic "SNNM" | version u32 | lr f64 | layer count u32 | per layer: kind tag u8 | layer
+//   0 dense: input size u64 | output size u64 | weights matrix | has bias u8 | bias matrix
+//   1 activation: activation tag u8 | activation parameter f64
+//   2 dropout: rate f64
+//   3 batch norm: momentum f64 | epsilon f64 | gamma | beta | running mean | running var
+//   4 layer norm: epsilon f64 | gamma | beta
+//   5 conv2d: in channels u64 | height u64 | width u64 | out channels u64 | kernel height u64
+//             | kernel width u64 | stride u64 | padding u64 | weights matrix | bias matrix
+//   6 reshape: input rank u32 | input dims u64 | output rank u32 | output dims u64
+//   matrix: rows u64 | cols u64 | rows * cols f64
+// Layer types defined outside this crate cannot be saved.
+//
+// Versions 1 to 4 store one record per dense layer, each loads as a dense layer,
+// an optional norm and an activation layer:
+//   input size u64 | output size u64 | weights matrix | has bias u8 | bias matrix
+//   | activation tag u8 | activation parameter f64 | norm
+//   norm: tag u8, 0 for none or 1 (batch) or 2 (layer) followed by the fields above
+// Version 1 files have no bias fields and load as layers without bias.
+// Version 1 and 2 files have no activation fields and load as sigmoid layers.
+// Version 1 to 3 files have no norm fields and load as layers without normalization.
+// Matrix elements are always stored as f64, so a file saved from an f32 network
+// can be loaded as f64 and the other way around.
+//
+// A checkpoint is a model followed by the optimizer state:
+//   magic "SNNO" | version u32 | name length u32 | name utf-8 | hyperparameter count u32
+//   | count * f64 | step u64 | parameter count u32 | per parameter: slot count u32 | slot matrices
+pub const MAGIC: [u8; 4] = *b"SNNM";
+pub const VERSION: u32 = 5;
+pub const OPTIMIZER_MAGIC: [u8; 4] = *b"SNNO";
+pub const OPTIMIZER_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum ModelError {
+    Io(io::Error),
+    InvalidMagic([u8; 4]),
+    UnsupportedVersion(u32),
+    Truncated(String),
+    ShapeMismatch {
+        layer: usize,
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+    LayerMismatch {
+        layer: usize,
+        output_size: usize,
+        next_input_size: usize,
+    },
+    UnknownActivation {
+        layer: usize,
+        tag: u8,
+    },
+    UnknownNorm {
+        layer: usize,
+        tag: u8,
+    },
+    UnknownLayer {
+        layer: usize,
+        tag: u8,
+    },
+    UnsupportedLayer {
+        layer: usize,
+        name: &'static str,
+    },
+    InvalidParameter {
+        layer: usize,
+        name: &'static str,
+        value: f64,
+    },
+    UnknownOptimizer(String),
+    KernelTooLarge {
+        layer: usize,
+        kernel: (usize, usize),
+        input: (usize, usize),
+        padding: usize,
+    },
+    // a stored matrix whose dimensions are zero or too large
+    InvalidMatrix {
+        what: String,
+        rows: usize,
+        cols: usize,
+    },
+    // optimizer state that does not fit the parameter it belongs to
+    SlotMismatch {
+        parameter: usize,
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+    SlotCount {
+        parameter: usize,
+        expected: usize,
+        found: usize,
+    },
+    EmptyLayer(usize),
+    Empty,
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModelError::Io(err) => write!(f, "io error: {}", err),
+            ModelError::InvalidMagic(magic) => {
+                write!(f, "not a model file (magic bytes {:?})", magic)
+            }
+            ModelError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported model format version {} (this build reads up to {})",
+                version, VERSION
+            ),
+            ModelError::Truncated(what) => write!(f, "file truncated while reading {}", what),
+            ModelError::ShapeMismatch {
+                layer,
+                expected,
+                found,
+            } => write!(
+                f,
+                "layer {} weights should be {}x{} but are {}x{}",
+                layer, expected.0, expected.1, found.0, found.1
+            ),
+            ModelError::LayerMismatch {
+                layer,
+                output_size,
+                next_input_size,
+            } => write!(
+                f,
+                "layer {} outputs {} values but layer {} expects {}",
+                layer,
+                output_size,
+                layer + 1,
+                next_input_size
+            ),
+            ModelError::UnknownActivation { layer, tag } => {
+                write!(f, "layer {} has unknown activation tag {}", layer, tag)
+            }
+            ModelError::UnknownNorm { layer, tag } => {
+                write!(f, "layer {} has unknown normalization tag {}", layer, tag)
+            }
+            ModelError::UnknownLayer { layer, tag } => {
+                write!(f, "layer {} has unknown kind tag {}", layer, tag)
+            }
+            ModelError::UnsupportedLayer { layer, name } => {
+                write!(f, "layer {} ({}) cannot be saved", layer, name)
+            }
+            ModelError::InvalidParameter { layer, name, value } => {
+                write!(f, "layer {} has invalid {} {}", layer, name, value)
+            }
+            ModelError::UnknownOptimizer(name) => {
+                write!(f, "unknown optimizer {:?} or bad hyperparameters", name)
+            }
+            ModelError::KernelTooLarge {
+                layer,
+                kernel,
+                input,
+                padding,
+            } => write!(
+                f,
+                "layer {} kernel {}x{} does not fit the {}x{} input with padding {}",
+                layer, kernel.0, kernel.1, input.0, input.1, padding
+            ),
+            ModelError::InvalidMatrix { what, rows, cols } => {
+                write!(f, "{} has invalid shape {}x{}", what, rows, cols)
+            }
+            ModelError::SlotMismatch {
+                parameter,
+                expected,
+                found,
+            } => write!(
+                f,
+                "optimizer state of parameter {} should be {}x{} but is {}x{}",
+                parameter, expected.0, expected.1, found.0, found.1
+            ),
+            ModelError::SlotCount {
+                parameter,
+                expected,
+                found,
+            } => write!(
+                f,
+                "parameter {} should have {} optimizer buffers but has {}",
+                parameter, expected, found
+            ),
+            ModelError::EmptyLayer(layer) => {
+                write!(f, "layer {} has a zero-sized dimension", layer)
+            }
+            ModelError::Empty => write!(f, "model has no layers"),
+        }
+    }
+}
+
+impl Error for ModelError {}
+
+impl From<io::Error> for ModelError {
+    fn from(err: io::Error) -> ModelError {
+        ModelError::Io(err)
+    }
+}
+
+pub fn write_model<T: Scalar, W: Write>(
+    nn: &NeuralNetwork<T>,
+    w: &mut W,
+) -> Result<(), ModelError> {
+    w.write_all(&MAGIC)?;
+    w.write_all(&VERSION.to_le_bytes())?;
+    w.write_all(&nn.lr().to_le_bytes())?;
+    w.write_all(&(nn.layers.len() as u32).to_le_bytes())?;
+    for (i, layer) in nn.layers.iter().enumerate() {
+        write_layer(w, i, layer.as_ref())?;
+    }
+    Ok(())
+}
+
+fn write_layer<T: Scalar, W: Write>(
+    w: &mut W,
+    i: usize,
+    layer: &dyn Layer<T>,
+) -> Result<(), ModelError> {
+    let any = layer.as_any();
+    if let Some(layer) = any.downcast_ref::<Dense<T>>() {
+        w.write_all(&[0])?;
+        write_u64(w, layer.input_size)?;
+        write_u64(w, layer.output_size)?;
+        write_matrix(w, &layer.weights_matrix)?;
+        match &layer.bias {
+            Some(bias) => {
+                w.write_all(&[1])?;
+                write_matrix(w, bias)?;
+            }
+            None => w.write_all(&[0])?,
+        }
+    } else if let Some(layer) = any.downcast_ref::<ActivationLayer<T>>() {
+        w.write_all(&[1])?;
+        let (tag, param) = activation_to_tag(&layer.activation);
+        w.write_all(&[tag])?;
+        w.write_all(&param.to_le_bytes())?;
+    } else if let Some(layer) = any.downcast_ref::<Dropout<T>>() {
+        w.write_all(&[2])?;
+        w.write_all(&layer.rate.to_le_bytes())?;
+    } else if let Some(norm) = any.downcast_ref::<BatchNorm<T>>() {
+        w.write_all(&[3])?;
+        w.write_all(&norm.momentum.to_le_bytes())?;
+        w.write_all(&norm.epsilon.to_le_bytes())?;
+        write_matrix(w, &norm.gamma)?;
+        write_matrix(w, &norm.beta)?;
+        write_matrix(w, &norm.running_mean)?;
+        write_matrix(w, &norm.running_var)?;
+    } else if let Some(norm) = any.downcast_ref::<LayerNorm<T>>() {
+        w.write_all(&[4])?;
+        w.write_all(&norm.epsilon.to_le_bytes())?;
+        write_matrix(w, &norm.gamma)?;
+        write_matrix(w, &norm.beta)?;
+    } else if let Some(conv) = any.downcast_ref::<Conv2d<T>>() {
+        w.write_all(&[5])?;
+        for value in [
+            conv.in_channels,
+            conv.input.0,
+            conv.input.1,
+            conv.out_channels,
+            conv.kernel.0,
+            conv.kernel.1,
+            conv.stride,
+            conv.padding,
+        ] {
+            write_u64(w, value)?;
+        }
+        write_matrix(w, &conv.weights_matrix)?;
+        write_matrix(w, &conv.bias)?;
+    } else if let Some(reshape) = any.downcast_ref::<Reshape>() {
+        w.write_all(&[6])?;
+        for shape in [&reshape.input_shape, &reshape.output_shape] {
+            w.write_all(&(shape.len() as u32).to_le_bytes())?;
+            for dim in shape.iter() {
+                write_u64(w, *dim)?;
+            }
+        }
+    } else {
+        return Err(ModelError::UnsupportedLayer {
+            layer: i,
+            name: layer.name(),
+        });
+    }
+    Ok(())
+}
+
+pub fn read_model<T: Scalar, R: Read>(r: &mut R) -> Result<NeuralNetwork<T>, ModelError> {
+    read_versioned_model(r).map(|(nn, _)| nn)
+}
+
+// the model and the format version it was stored with
+fn read_versioned_model<T: Scalar, R: Read>(
+    r: &mut R,
+) -> Result<(NeuralNetwork<T>, u32), ModelError> {
+    let mut magic = [0u8; 4];
+    read_exact(r, &mut magic, "magic bytes")?;
+    if magic != MAGIC {
+        return Err(ModelError::InvalidMagic(magic));
+    }
+    let version = read_u32(r, "format version")?;
+    if version == 0 || version > VERSION {
+        return Err(ModelError::UnsupportedVersion(version));
+    }
+    let lr = read_f64(r, "learning rate")?;
+    let count = read_u32(r, "layer count")? as usize;
+    if count == 0 {
+        return Err(ModelError::Empty);
+    }
+
+    let mut layers: Vec<Box<dyn Layer<T>>> = Vec::new();
+    for i in 0..count {
+        if version >= 5 {
+            layers.push(read_layer(r, i)?);
+        } else {
+            layers.extend(read_legacy_layer(r, i, version)?);
+        }
+    }
+    check_sizes(&layers)?;
+    let nn = NeuralNetwork::from_layers(layers).with_optimizer(Sgd::new(lr));
+    Ok((nn, version))
+}
+
+// every layer with a fixed input size must match the output of the layers
+// before it
+fn check_sizes<T: Scalar>(layers: &[Box<dyn Layer<T>>]) -> Result<(), ModelError> {
+    let mut size: Option<usize> = None;
+    for (i, layer) in layers.iter().enumerate() {
+        if let (Some(output_size), Some(input_size)) = (size, layer.input_size()) {
+            if output_size != input_size {
+                return Err(ModelError::LayerMismatch {
+                    layer: i - 1,
+                    output_size,
+                    next_input_size: input_size,
+                });
+            }
+        }
+        size = size
+            .or(layer.input_size())
+            .map(|size| layer.output_size(size));
+    }
+    Ok(())
+}
+
+fn read_layer<T: Scalar, R: Read>(r: &mut R, i: usize) -> Result<Box<dyn Layer<T>>, ModelError> {
+    let what = format!("layer {} kind", i);
+    let mut tag = [0u8; 1];
+    read_exact(r, &mut tag, &what)?;
+    let layer: Box<dyn Layer<T>> = match tag[0] {
+        0 => Box::new(read_dense(r, i, true)?),
+        1 => Box::new(ActivationLayer::new(read_activation(r, i)?)),
+        2 => {
+            let rate = read_f64(r, &format!("layer {} dropout rate", i))?;
+            if !(0.0..1.0).contains(&rate) {
+                return Err(ModelError::InvalidParameter {
+                    layer: i,
+                    name: "dropout rate",
+                    value: rate,
+                });
+            }
+            Box::new(Dropout::new(rate))
+        }
+        3 => Box::new(read_batch_norm(r, i, None)?),
+        4 => Box::new(read_layer_norm(r, i, None)?),
+        5 => Box::new(read_conv(r, i)?),
+        6 => Box::new(read_reshape(r, i)?),
+        tag => return Err(ModelError::UnknownLayer { layer: i, tag }),
+    };
+    Ok(layer)
+}
+
+// dense layer, norm and activation of a version 1 to 4 record
+fn read_legacy_layer<T: Scalar, R: Read>(
+    r: &mut R,
+    i: usize,
+    version: u32,
+) -> Result<Vec<Box<dyn Layer<T>>>, ModelError> {
+    let dense = read_dense(r, i, version >= 2)?;
+    let output_size = dense.output_size;
+    let activation = if version >= 3 {
+        read_activation(r, i)?
+    } else {
+        Activation::Sigmoid
+    };
+    let mut layers: Vec<Box<dyn Layer<T>>> = vec![Box::new(dense)];
+    if version >= 4 {
+        let what = format!("layer {} normalization", i);
+        let mut tag = [0u8; 1];
+        read_exact(r, &mut tag, &what)?;
+        match tag[0] {
+            0 => {}
+            1 => layers.push(Box::new(read_batch_norm(r, i, Some(output_size))?)),
+            2 => layers.push(Box::new(read_layer_norm(r, i, Some(output_size))?)),
+            tag => return Err(ModelError::UnknownNorm { layer: i, tag }),
+        }
+    }
+    layers.push(Box::new(ActivationLayer::new(activation)));
+    Ok(layers)
+}
+
+fn read_dense<T: Scalar, R: Read>(
+    r: &mut R,
+    i: usize,
+    has_bias_field: bool,
+) -> Result<Dense<T>, ModelError> {
+    let input_size = read_u64(r, &format!("layer {} input size", i))?;
+    let output_size = read_u64(r, &format!("layer {} output size", i))?;
+    if input_size == 0 || output_size == 0 {
+        return Err(ModelError::EmptyLayer(i));
+    }
+    let weights_matrix = read_shaped_matrix(
+        r,
+        i,
+        (output_size, input_size),
+        &format!("layer {} weights", i),
+    )?;
+    let bias = if has_bias_field {
+        read_bias(r, i, output_size)?
+    } else {
+        None
+    };
+    Ok(match bias {
+        Some(bias) => Dense::new_with_bias(weights_matrix, bias),
+        None => Dense::new(weights_matrix),
+    })
+}
+
+// a matrix whose stored shape must be `expected`
+fn read_shaped_matrix<T: Scalar, R: Read>(
+    r: &mut R,
+    layer: usize,
+    expected: (usize, usize),
+    what: &str,
+) -> Result<Matrix<T>, ModelError> {
+    let rows = read_u64(r, what)?;
+    let cols = read_u64(r, what)?;
+    if (rows, cols) != expected {
+        return Err(ModelError::ShapeMismatch {
+            layer,
+            expected,
+            found: (rows, cols),
+        });
+    }
+    read_matrix(r, rows, cols, what)
+}
+
+pub fn write_checkpoint<T: Scalar, W: Write>(
+    nn: &NeuralNetwork<T>,
+    w: &mut W,
+) -> Result<(), ModelError> {
+    write_model(nn, w)?;
+    let state = nn.optimizer.state();
+    w.write_all(&OPTIMIZER_MAGIC)?;
+    w.write_all(&OPTIMIZER_VERSION.to_le_bytes())?;
+    w.write_all(&(state.name.len() as u32).to_le_bytes())?;
+    w.write_all(state.name.as_bytes())?;
+    w.write_all(&(state.hyperparameters.len() as u32).to_le_bytes())?;
+    for value in state.hyperparameters.iter() {
+        w.write_all(&value.to_le_bytes())?;
+    }
+    w.write_all(&state.step.to_le_bytes())?;
+    w.write_all(&(state.slots.len() as u32).to_le_bytes())?;
+    for slots in state.slots.iter() {
+        w.write_all(&(slots.len() as u32).to_le_bytes())?;
+        for slot in slots.iter() {
+            write_matrix(w, slot)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn read_checkpoint<T: Scalar, R: Read>(r: &mut R) -> Result<NeuralNetwork<T>, ModelError> {
+    let (mut nn, model_version) = read_versioned_model(r)?;
+    let mut magic = [0u8; 4];
+    read_exact(r, &mut magic, "optimizer magic bytes")?;
+    if magic != OPTIMIZER_MAGIC {
+        return Err(ModelError::InvalidMagic(magic));
+    }
+    let version = read_u32(r, "optimizer format version")?;
+    if version == 0 || version > OPTIMIZER_VERSION {
+        return Err(ModelError::UnsupportedVersion(version));
+    }
+    let len = read_u32(r, "optimizer name length")? as usize;
+    if len > 64 {
+        return Err(ModelError::UnknownOptimizer(format!("<{} byte name>", len)));
+    }
+    let mut name = vec![0u8; len];
+    read_exact(r, &mut name, "optimizer name")?;
+    let name = String::from_utf8_lossy(&name).to_string();
+    let count = read_u32(r, "optimizer hyperparameter count")?;
+    let mut hyperparameters = Vec::new();
+    for _i in 0..count {
+        hyperparameters.push(read_f64(r, "optimizer hyperparameters")?);
+    }
+    let mut buf = [0u8; 8];
+    read_exact(r, &mut buf, "optimizer step")?;
+    let step = u64::from_le_bytes(buf);
+    let params = read_u32(r, "optimizer parameter count")?;
+    let mut slots = Vec::new();
+    for i in 0..params {
+        let what = format!("optimizer state for parameter {}", i);
+        let count = read_u32(r, &what)?;
+        let mut param_slots = Vec::new();
+        for _j in 0..count {
+            let rows = read_u64(r, &what)?;
+            let cols = read_u64(r, &what)?;
+            param_slots.push(read_matrix(r, rows, cols, &what)?);
+        }
+        slots.push(param_slots);
+    }
+    if model_version < 5 {
+        slots = legacy_slot_order(&nn, slots);
+    }
+    check_slots(&nn, &name, &slots)?;
+    let state = OptimizerState {
+        name: name.clone(),
+        hyperparameters,
+        step,
+        slots,
+    };
+    nn.optimizer = from_state(state).ok_or(ModelError::UnknownOptimizer(name))?;
+    Ok(nn)
+}
+
+// Every parameter either has no state yet or the full set of buffers of the
+// optimizer, each shaped like the parameter.
+fn check_slots<T: Scalar>(
+    nn: &NeuralNetwork<T>,
+    name: &str,
+    slots: &[Vec<Matrix<T>>],
+) -> Result<(), ModelError> {
+    let count = slot_count(name).ok_or_else(|| ModelError::UnknownOptimizer(name.to_string()))?;
+    let params: Vec<&Matrix<T>> = nn
+        .layers
+        .iter()
+        .flat_map(|layer| layer.parameters())
+        .collect();
+    for (parameter, param_slots) in slots.iter().enumerate() {
+        if param_slots.is_empty() {
+            continue;
+        }
+        let param = match params.get(parameter) {
+            Some(param) if param_slots.len() == count => param,
+            Some(_) => {
+                return Err(ModelError::SlotCount {
+                    parameter,
+                    expected: count,
+                    found: param_slots.len(),
+                })
+            }
+            None => {
+                return Err(ModelError::SlotCount {
+                    parameter,
+                    expected: 0,
+                    found: param_slots.len(),
+                })
+            }
+        };
+        for slot in param_slots {
+            if slot.shape() != param.shape() {
+                return Err(ModelError::SlotMismatch {
+                    parameter,
+                    expected: param.shape(),
+                    found: slot.shape(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+// Before version 5 parameter 2i was the weights and 2i+1 the bias of dense
+// layer i, and the gamma and beta of its norm followed all of them at 2n+2i and
+// 2n+2i+1. Now the parameters of all layers are numbered in order.
+fn legacy_slot_order<T: Scalar>(
+    nn: &NeuralNetwork<T>,
+    mut slots: Vec<Vec<Matrix<T>>>,
+) -> Vec<Vec<Matrix<T>>> {
+    let dense_count = nn
+        .layers
+        .iter()
+        .filter(|layer| layer.name() == "dense")
+        .count();
+    let mut dense = 0;
+    let mut order = Vec::new();
+    for layer in nn.layers.iter() {
+        let count = layer.parameters().len();
+        if layer.name() == "dense" {
+            order.extend((0..count).map(|k| 2 * dense + k));
+            dense += 1;
+        } else {
+            // a norm follows the dense layer it belongs to
+            order.extend((0..count).map(|k| 2 * dense_count + 2 * (dense - 1) + k));
+        }
+    }
+    order
+        .into_iter()
+        .map(|old| slots.get_mut(old).map(std::mem::take).unwrap_or_default())
+        .collect()
+}
+
+fn read_bias<T: Scalar, R: Read>(
+    r: &mut R,
+    layer: usize,
+    output_size: usize,
+) -> Result<Option<Matrix<T>>, ModelError> {
+    let what = format!("layer {} bias", layer);
+    let mut flag = [0u8; 1];
+    read_exact(r, &mut flag, &what)?;
+    if flag[0] == 0 {
+        return Ok(None);
+    }
+    let rows = read_u64(r, &what)?;
+    let cols = read_u64(r, &what)?;
+    if rows != output_size || cols != 1 {
+        return Err(ModelError::ShapeMismatch {
+            layer,
+            expected: (output_size, 1),
+            found: (rows, cols),
+        });
+    }
+    Ok(Some(read_matrix(r, rows, cols, &what)?))
+}
+
+fn activation_to_tag(activation: &Activation) -> (u8, f64) {
+    match *activation {
+        Activation::Sigmoid => (0, 0.0),
+        Activation::Tanh => (1, 0.0),
+        Activation::Relu => (2, 0.0),
+        Activation::LeakyRelu(alpha) => (3, alpha),
+        Activation::Elu(alpha) => (4, alpha),
+        Activation::Gelu => (5, 0.0),
+        Activation::Softplus => (6, 0.0),
+        Activation::Identity => (7, 0.0),
+        Activation::Softmax => (8, 0.0),
+    }
+}
+
+fn read_activation<R: Read>(r: &mut R, layer: usize) -> Result<Activation, ModelError> {
+    let what = format!("layer {} activation", layer);
+    let mut tag = [0u8; 1];
+    read_exact(r, &mut tag, &what)?;
+    let param = read_f64(r, &what)?;
+    match tag[0] {
+        0 => Ok(Activation::Sigmoid),
+        1 => Ok(Activation::Tanh),
+        2 => Ok(Activation::Relu),
+        3 => Ok(Activation::LeakyRelu(param)),
+        4 => Ok(Activation::Elu(param)),
+        5 => Ok(Activation::Gelu),
+        6 => Ok(Activation::Softplus),
+        7 => Ok(Activation::Identity),
+        8 => Ok(Activation::Softmax),
+        tag => Err(ModelError::UnknownActivation { layer, tag }),
+    }
+}
+
+// one value per unit, the first column read of a layer defines the number of
+// units when `size` is None
+fn read_column<T: Scalar, R: Read>(
+    r: &mut R,
+    layer: usize,
+    size: Option<usize>,
+    what: &str,
+) -> Result<Matrix<T>, ModelError> {
+    let rows = read_u64(r, what)?;
+    let cols = read_u64(r, what)?;
+    if rows == 0 {
+        return Err(ModelError::EmptyLayer(layer));
+    }
+    let expected = (size.unwrap_or(rows), 1);
+    if (rows, cols) != expected {
+        return Err(ModelError::ShapeMismatch {
+            layer,
+            expected,
+            found: (rows, cols),
+        });
+    }
+    read_matrix(r, rows, cols, what)
+}
+
+fn read_batch_norm<T: Scalar, R: Read>(
+    r: &mut R,
+    layer: usize,
+    size: Option<usize>,
+) -> Result<BatchNorm<T>, ModelError> {
+    let what = format!("layer {} normalization", layer);
+    let momentum = read_f64(r, &what)?;
+    let epsilon = read_f64(r, &what)?;
+    let gamma = read_column(r, layer, size, &what)?;
+    let size = Some(gamma.rows);
+    let mut norm = BatchNorm::new(gamma.rows);
+    norm.momentum = momentum;
+    norm.epsilon = epsilon;
+    norm.gamma = gamma;
+    norm.beta = read_column(r, layer, size, &what)?;
+    norm.running_mean = read_column(r, layer, size, &what)?;
+    norm.running_var = read_column(r, layer, size, &what)?;
+    Ok(norm)
+}
+
+fn read_layer_norm<T: Scalar, R: Read>(
+    r: &mut R,
+    layer: usize,
+    size: Option<usize>,
+) -> Result<LayerNorm<T>, ModelError> {
+    let what = format!("layer {} normalization", layer);
+    let epsilon = read_f64(r, &what)?;
+    let gamma = read_column(r, layer, size, &what)?;
+    let mut norm = LayerNorm::new(gamma.rows);
+    norm.epsilon = epsilon;
+    norm.beta = read_column(r, layer, Some(gamma.rows), &what)?;
+    norm.gamma = gamma;
+    Ok(norm)
+}
+
+fn read_conv<T: Scalar, R: Read>(r: &mut R, layer: usize) -> Result<Conv2d<T>, ModelError> {
+    let what = format!("layer {} convolution", layer);
+    let mut values = [0usize; 8];
+    for value in values.iter_mut() {
+        *value = read_u64(r, &what)?;
+    }
+    let [in_channels, height, width, out_channels, kernel_height, kernel_width, stride, padding] =
+        values;
+    // the same rules the builder applies, in arithmetic that cannot overflow
+    if [
+        in_channels,
+        height,
+        width,
+        out_channels,
+        kernel_height,
+        kernel_width,
+        stride,
+    ]
+    .contains(&0)
+    {
+        return Err(ModelError::EmptyLayer(layer));
+    }
+    let invalid = |name, value: usize| ModelError::InvalidParameter {
+        layer,
+        name,
+        value: value as f64,
+    };
+    let padded = |size: usize| {
+        padding
+            .checked_mul(2)
+            .and_then(|padding| size.checked_add(padding))
+            .ok_or_else(|| invalid("padding", padding))
+    };
+    let (padded_height, padded_width) = (padded(height)?, padded(width)?);
+    if kernel_height > padded_height || kernel_width > padded_width {
+        return Err(ModelError::KernelTooLarge {
+            layer,
+            kernel: (kernel_height, kernel_width),
+            input: (height, width),
+            padding,
+        });
+    }
+    let columns = in_channels
+        .checked_mul(kernel_height)
+        .and_then(|n| n.checked_mul(kernel_width))
+        .ok_or_else(|| invalid("kernel size", kernel_height))?;
+    let out_height = (padded_height - kernel_height) / stride + 1;
+    let out_width = (padded_width - kernel_width) / stride + 1;
+    out_channels
+        .checked_mul(out_height)
+        .and_then(|n| n.checked_mul(out_width))
+        .ok_or_else(|| invalid("output channels", out_channels))?;
+    let weights = read_shaped_matrix(r, layer, (out_channels, columns), &what)?;
+    let bias = read_shaped_matrix(r, layer, (out_channels, 1), &what)?;
+    let mut conv = Conv2d::new(
+        in_channels,
+        (height, width),
+        out_channels,
+        (kernel_height, kernel_width),
+    )
+    .with_stride(stride)
+    .with_padding(padding);
+    conv.weights_matrix = weights;
+    conv.bias = bias;
+    Ok(conv)
+}
+
+fn read_reshape<R: Read>(r: &mut R, layer: usize) -> Result<Reshape, ModelError> {
+    let what = format!("layer {} reshape", layer);
+    let mut shapes = Vec::new();
+    for _i in 0..2 {
+        let rank = read_u32(r, &what)?;
+        if rank == 0 || rank > 8 {
+            return Err(ModelError::InvalidParameter {
+                layer,
+                name: "reshape rank",
+                value: rank as f64,
+            });
+        }
+        let mut shape = Vec::new();
+        for _j in 0..rank {
+            shape.push(read_u64(r, &what)?);
+        }
+        shapes.push(shape);
+    }
+    let output_shape = shapes.pop().unwrap();
+    let input_shape = shapes.pop().unwrap();
+    if input_shape.contains(&0) || output_shape.contains(&0) {
+        return Err(ModelError::EmptyLayer(layer));
+    }
+    let size = |shape: &[usize]| {
+        shape
+            .iter()
+            .try_fold(1usize, |size, dim| size.checked_mul(*dim))
+            .ok_or(ModelError::InvalidParameter {
+                layer,
+                name: "reshape size",
+                value: shape.iter().map(|dim| *dim as f64).product(),
+            })
+    };
+    let (input, output) = (size(&input_shape)?, size(&output_shape)?);
+    if input != output {
+        return Err(ModelError::InvalidParameter {
+            layer,
+            name: "reshape output size",
+            value: output as f64,
+        });
+    }
+    Ok(Reshape::new(input_shape, output_shape))
+}
+
+fn write_u64<W: Write>(w: &mut W, value: usize) -> Result<(), ModelError> {
+    w.write_all(&(value as u64).to_le_bytes())?;
+    Ok(())
+}
+
+fn write_matrix<T: Scalar, W: Write>(w: &mut W, matrix: &Matrix<T>) -> Result<(), ModelError> {
+    write_u64(w, matrix.rows)?;
+    write_u64(w, matrix.cols)?;
+    for value in matrix.data.iter() {
+        w.write_all(&value.to_f64().to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_exact<R: Read>(r: &mut R, buf: &mut [u8], what: &str) -> Result<(), ModelError> {
+    r.read_exact(buf).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => ModelError::Truncated(what.to_string()),
+        _ => ModelError::Io(err),
+    })
+}
+
+fn read_u32<R: Read>(r: &mut R, what: &str) -> Result<u32, ModelError> {
+    let mut buf = [0u8; 4];
+    read_exact(r, &mut buf, what)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R, what: &str) -> Result<usize, ModelError> {
+    let mut buf = [0u8; 8];
+    read_exact(r, &mut buf, what)?;
+    Ok(u64::from_le_bytes(buf) as usize)
+}
+
+fn read_f64<R: Read>(r: &mut R, what: &str) -> Result<f64, ModelError> {
+    let mut buf = [0u8; 8];
+    read_exact(r, &mut buf, what)?;
+    Ok(f64::from_le_bytes(buf))
+}
+
+fn read_matrix<T: Scalar, R: Read>(
+    r: &mut R,
+    rows: usize,
+    cols: usize,
+    what: &str,
+) -> Result<Matrix<T>, ModelError> {
+    let invalid = || ModelError::InvalidMatrix {
+        what: what.to_string(),
+        rows,
+        cols,
+    };
+    let len = rows.checked_mul(cols).ok_or_else(invalid)?;
+    let mut data = Vec::new();
+    for _i in 0..len {
+        data.push(T::from_f64(read_f64(r, what)?));
+    }
+    Matrix::try_from_vec(rows, cols, data).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod serialization_tests {
+    use super::{
+        legacy_slot_order, read_checkpoint, read_model, write_checkpoint, write_model, ModelError,
+        VERSION,
+    };
+    use crate::activation::Activation;
+    use crate::conv::Conv2d;
+    use crate::dropout::Dropout;
+    use crate::layer::{ActivationLayer, Dense, Layer};
+    use crate::layer::{Mode, Reshape};
+    use crate::matrix::{Matrix, MatrixError, MatrixOps};
+    use crate::nn::NeuralNetwork;
+    use crate::normalization::{BatchNorm, LayerNorm};
+    use crate::optimizer::{from_state, Adam, Momentum};
+    use crate::scalar::Scalar;
+    use crate::test_util::temp_path;
+    use std::any::Any;
+    use std::io::Cursor;
+
+    fn encode<T: Scalar>(nn: &NeuralNetwork<T>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_model(nn, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_save_load_round_trip() {
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![3, 4, 2]);
+        let inputs = Matrix::new(vec![vec![0.9, 0.1, 0.8]]).transpose();
+        let label = Matrix::new(vec![vec![0.99, 0.01]]).transpose();
+        for _i in 0..5 {
+            nn.train(&inputs, &label).unwrap();
+        }
+
+        let path = temp_path("round_trip.snnm");
+        nn.save(&path).unwrap();
+        let loaded: NeuralNetwork = NeuralNetwork::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(nn.lr().to_bits(), loaded.lr().to_bits());
+        assert_eq!(nn.layers.len(), loaded.layers.len());
+        let before = nn.inference(inputs.clone()).unwrap();
+        let after = loaded.inference(inputs).unwrap();
+        for (a, b) in before.data.iter().zip(after.data.iter()) {
+            assert_eq!(a.to_bits(), b.to_bits());
+        }
+    }
+
+    #[test]
+    fn test_load_truncated() {
+        let nn: NeuralNetwork = NeuralNetwork::new(vec![3, 4, 2]);
+        let bytes = encode(&nn);
+        for len in [0, 3, 7, 15, 20, bytes.len() - 1].iter() {
+            match read_model::<f64, _>(&mut Cursor::new(&bytes[..*len])) {
+                Err(ModelError::Truncated(what)) => println!("truncated at {}: {}", len, what),
+                other => panic!("expected truncated error, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_unknown_version() {
+        let nn: NeuralNetwork = NeuralNetwork::new(vec![2, 2]);
+        let mut bytes = encode(&nn);
+        bytes[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        match read_model::<f64, _>(&mut Cursor::new(&bytes)) {
+            Err(ModelError::UnsupportedVersion(v)) => assert_eq!(v, VERSION + 1),
+            other => panic!("expected version error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_load_invalid_magic() {
+        let bytes = b"NOPE\x01\x00\x00\x00".to_vec();
+        match read_model::<f64, _>(&mut Cursor::new(&bytes)) {
+            Err(ModelError::InvalidMagic(magic)) => assert_eq!(&magic, b"NOPE"),
+            other => panic!("expected magic error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_load_shape_mismatch() {
+        let nn: NeuralNetwork = NeuralNetwork::new(vec![3, 4]);
+        let mut bytes = encode(&nn);
+        // header is 4 + 4 + 8 + 4 bytes, then the layer's kind tag and input size
+        bytes[21..29].copy_from_slice(&5u64.to_le_bytes());
+        match read_model::<f64, _>(&mut Cursor::new(&bytes)) {
+            Err(ModelError::ShapeMismatch {
+                layer,
+                expected,
+                found,
+            }) => {
+                assert_eq!(layer, 0);
+                assert_eq!(expected, (4, 5));
+                assert_eq!(found, (4, 3));
+            }
+            other => panic!("expected shape error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_round_trip_without_bias() {
+        let nn: NeuralNetwork = NeuralNetwork::new_without_bias(vec![3, 2]);
+        let loaded = read_model::<f64, _>(&mut Cursor::new(encode(&nn))).unwrap();
+        assert!(!loaded.layer::<Dense>(0).unwrap().has_bias());
+    }
+
+    #[test]
+    fn test_round_trip_activations() {
+        let activations = vec![
+            Activation::LeakyRelu(0.05),
+            Activation::Elu(0.7),
+            Activation::Softmax,
+        ];
+        let nn: NeuralNetwork =
+            NeuralNetwork::new_with_activations(vec![4, 3, 3, 2], activations.clone());
+        let loaded = read_model::<f64, _>(&mut Cursor::new(encode(&nn))).unwrap();
+        for (index, activation) in activations.iter().enumerate() {
+            let layer = loaded.layer::<ActivationLayer>(2 * index + 1).unwrap();
+            assert_eq!(layer.activation(), *activation);
+        }
+    }
+
+    #[test]
+    fn test_load_unknown_activation() {
+        let nn: NeuralNetwork = NeuralNetwork::new(vec![2, 2]);
+        let mut bytes = encode(&nn);
+        // the last layer is the activation, a tag and a parameter
+        let tag = bytes.len() - 9;
+        bytes[tag] = 42;
+        match read_model::<f64, _>(&mut Cursor::new(&bytes)) {
+            Err(ModelError::UnknownActivation { layer, tag }) => {
+                assert_eq!(layer, 1);
+                assert_eq!(tag, 42);
+            }
+            other => panic!("expected activation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_resumes_training() {
+        let inputs = Matrix::new(vec![vec![0.9, 0.1, 0.8]]).transpose();
+        let label = Matrix::new(vec![vec![0.99, 0.01]]).transpose();
+        let mut nn: NeuralNetwork =
+            NeuralNetwork::new(vec![3, 4, 2]).with_optimizer(Adam::new(0.01, 0.9, 0.999));
+        for _i in 0..3 {
+            nn.train(&inputs, &label).unwrap();
+        }
+
+        let path = temp_path("checkpoint.snnm");
+        nn.save_checkpoint(&path).unwrap();
+        let mut resumed = NeuralNetwork::load_checkpoint(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(resumed.optimizer().name(), "adam");
+
+        for _i in 0..3 {
+            nn.train(&inputs, &label).unwrap();
+            resumed.train(&inputs, &label).unwrap();
+        }
+        for (a, b) in nn.layers().iter().zip(resumed.layers()) {
+            assert_eq!(a.parameters(), b.parameters());
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_truncated() {
+        let mut nn: NeuralNetwork =
+            NeuralNetwork::new(vec![2, 2]).with_optimizer(Momentum::new(0.1, 0.9));
+        let inputs = Matrix::new(vec![vec![0.9, 0.1]]).transpose();
+        nn.train(&inputs, &inputs).unwrap();
+        let mut bytes = Vec::new();
+        write_checkpoint(&nn, &mut bytes).unwrap();
+        bytes.truncate(bytes.len() - 4);
+        match read_checkpoint::<f64, _>(&mut Cursor::new(&bytes)) {
+            Err(ModelError::Truncated(what)) => assert!(what.contains("optimizer")),
+            other => panic!("expected truncated error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_corrupt_slots() {
+        let mut nn: NeuralNetwork =
+            NeuralNetwork::new(vec![2, 2]).with_optimizer(Momentum::new(0.1, 0.9));
+        let inputs = Matrix::new(vec![vec![0.9, 0.1]]).transpose();
+        nn.train(&inputs, &inputs).unwrap();
+        let mut bytes = Vec::new();
+        write_checkpoint(&nn, &mut bytes).unwrap();
+
+        // the file ends with the 2x1 velocity of the bias: rows, cols and 2 values
+        let shape_at = bytes.len() - 32;
+        let with_shape = |rows: u64, cols: u64| {
+            let mut bytes = bytes.clone();
+            bytes[shape_at..shape_at + 8].copy_from_slice(&rows.to_le_bytes());
+            bytes[shape_at + 8..shape_at + 16].copy_from_slice(&cols.to_le_bytes());
+            read_checkpoint::<f64, _>(&mut Cursor::new(&bytes))
+        };
+        assert!(with_shape(2, 1).is_ok());
+        for (rows, cols) in [(0, 1), (2, 0), (u64::MAX, 2)].iter() {
+            match with_shape(*rows, *cols) {
+                Err(ModelError::InvalidMatrix { what, .. }) => {
+                    assert!(what.contains("parameter 1"))
+                }
+                other => panic!("expected invalid matrix, got {:?}", other),
+            }
+        }
+        match with_shape(1, 2) {
+            Err(err @ ModelError::SlotMismatch { .. }) => {
+                assert_eq!(
+                    err.to_string(),
+                    "optimizer state of parameter 1 should be 2x1 but is 1x2"
+                );
+            }
+            other => panic!("expected slot mismatch, got {:?}", other),
+        }
+
+        // momentum keeps one buffer per parameter
+        let mut state = nn.optimizer().state();
+        let velocity = state.slots[0][0].clone();
+        state.slots[0].push(velocity);
+        nn.optimizer = from_state(state).unwrap();
+        let mut bytes = Vec::new();
+        write_checkpoint(&nn, &mut bytes).unwrap();
+        assert!(matches!(
+            read_checkpoint::<f64, _>(&mut Cursor::new(&bytes)),
+            Err(ModelError::SlotCount {
+                parameter: 0,
+                expected: 1,
+                found: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_load_version_1() {
+        let mut bytes = b"SNNM".to_vec();
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&0.3f64.to_le_bytes());
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        for size in [2u64, 1, 1, 2].iter() {
+            bytes.extend_from_slice(&size.to_le_bytes());
+        }
+        bytes.extend_from_slice(&0.5f64.to_le_bytes());
+        bytes.extend_from_slice(&(-0.5f64).to_le_bytes());
+
+        let nn = read_model::<f64, _>(&mut Cursor::new(&bytes)).unwrap();
+        // a dense layer and its sigmoid
+        assert_eq!(nn.layers().len(), 2);
+        let dense = nn.layer::<Dense>(0).unwrap();
+        assert!(!dense.has_bias());
+        assert_eq!(dense.weights().data, vec![0.5, -0.5]);
+        let activation = nn.layer::<ActivationLayer>(1).unwrap().activation();
+        assert_eq!(activation, Activation::Sigmoid);
+    }
+
+    #[test]
+    fn test_f32_model_loads_as_f64() {
+        let nn: NeuralNetwork<f32> = NeuralNetwork::new(vec![3, 4, 2]);
+        let bytes = encode(&nn);
+        let loaded = read_model::<f64, _>(&mut Cursor::new(&bytes)).unwrap();
+        for (a, b) in nn.layers().iter().zip(loaded.layers()) {
+            let a: Vec<Matrix> = a.parameters().iter().map(|p| p.convert()).collect();
+            let b: Vec<Matrix> = b.parameters().into_iter().cloned().collect();
+            assert_eq!(a, b);
+        }
+        // f32 values survive the round trip through f64 exactly
+        let back = read_model::<f32, _>(&mut Cursor::new(encode(&loaded))).unwrap();
+        for (a, b) in nn.layers().iter().zip(back.layers()) {
+            assert_eq!(a.parameters(), b.parameters());
+        }
+    }
+
+    #[test]
+    fn test_round_trip_norms() {
+        let inputs = Matrix::new(vec![vec![0.9, 0.1, 0.8], vec![0.3, 0.6, 0.2]]).transpose();
+        let labels = Matrix::new(vec![vec![0.99, 0.01], vec![0.01, 0.99]]).transpose();
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![3, 4, 3, 2]);
+        nn.layers.insert(3, Box::new(LayerNorm::new(3)));
+        nn.layers.insert(1, Box::new(BatchNorm::new(4)));
+        for _i in 0..5 {
+            nn.train_batch(&inputs, &labels).unwrap();
+        }
+        let loaded = read_model::<f64, _>(&mut Cursor::new(encode(&nn))).unwrap();
+        let (a, b) = (
+            nn.layer::<BatchNorm>(1).unwrap(),
+            loaded.layer::<BatchNorm>(1).unwrap(),
+        );
+        assert_eq!(a.running_mean(), b.running_mean());
+        assert_eq!(a.running_var(), b.running_var());
+        assert_eq!(a.gamma(), b.gamma());
+        assert_eq!(
+            nn.layer::<LayerNorm>(4).unwrap().beta(),
+            loaded.layer::<LayerNorm>(4).unwrap().beta()
+        );
+        assert_eq!(
+            nn.inference(inputs.clone()).unwrap(),
+            loaded.inference(inputs).unwrap()
+        );
+
+        let mut bytes = encode(&nn);
+        // the kind tag of the last layer, a sigmoid
+        let tag = bytes.len() - 10;
+        bytes[tag] = 9;
+        match read_model::<f64, _>(&mut Cursor::new(&bytes)) {
+            Err(ModelError::UnknownLayer { layer, tag }) => assert_eq!((layer, tag), (7, 9)),
+            other => panic!("expected layer error, got {:?}", other),
+        }
+    }
+
+    // a version 4 file of one dense layer with batch norm and a tanh
+    fn version_4_bytes() -> Vec<u8> {
+        let mut bytes = b"SNNM".to_vec();
+        bytes.extend_from_slice(&4u32.to_le_bytes());
+        bytes.extend_from_slice(&0.3f64.to_le_bytes());
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        let push_matrix = |bytes: &mut Vec<u8>, values: &[f64]| {
+            bytes.extend_from_slice(&(values.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(&1u64.to_le_bytes());
+            for value in values {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        };
+        for size in [1u64, 2, 2, 1].iter() {
+            bytes.extend_from_slice(&size.to_le_bytes());
+        }
+        bytes.extend_from_slice(&0.5f64.to_le_bytes());
+        bytes.extend_from_slice(&(-0.5f64).to_le_bytes());
+        bytes.push(1);
+        push_matrix(&mut bytes, &[0.1, 0.2]);
+        bytes.push(1);
+        bytes.extend_from_slice(&0.0f64.to_le_bytes());
+        bytes.push(1);
+        bytes.extend_from_slice(&0.9f64.to_le_bytes());
+        bytes.extend_from_slice(&1e-5f64.to_le_bytes());
+        for values in [[1.5, 0.5], [0.0, 0.1], [0.2, 0.3], [1.0, 2.0]].iter() {
+            push_matrix(&mut bytes, values);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_load_version_4() {
+        let nn = read_model::<f64, _>(&mut Cursor::new(version_4_bytes())).unwrap();
+        let names: Vec<&str> = nn.layers().iter().map(|layer| layer.name()).collect();
+        assert_eq!(names, vec!["dense", "batch_norm", "tanh"]);
+        let norm = nn.layer::<BatchNorm>(1).unwrap();
+        assert_eq!(norm.gamma().data, vec![1.5, 0.5]);
+        assert_eq!(norm.running_var().data, vec![1.0, 2.0]);
+
+        let mut bytes = version_4_bytes();
+        // the norm tag after the activation
+        let tag = bytes.len() - 8 * 16 - 16 - 1;
+        bytes[tag] = 3;
+        match read_model::<f64, _>(&mut Cursor::new(&bytes)) {
+            Err(ModelError::UnknownNorm { layer, tag }) => assert_eq!((layer, tag), (0, 3)),
+            other => panic!("expected norm error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_legacy_slot_order() {
+        // old slots: weights and bias of both dense layers, then the norm's gamma
+        // and beta at 2n + 2i
+        let mut nn: NeuralNetwork = NeuralNetwork::new(vec![2, 3, 1]);
+        nn.layers.insert(1, Box::new(BatchNorm::new(3)));
+        let slots: Vec<Vec<Matrix>> = (0..6)
+            .map(|old| vec![Matrix::from_vec(1, 1, vec![old as f64])])
+            .collect();
+        let order: Vec<f64> = legacy_slot_order(&nn, slots)
+            .iter()
+            .map(|slots| slots[0][(0, 0)])
+            .collect();
+        assert_eq!(order, vec![0.0, 1.0, 4.0, 5.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_round_trip_conv() {
+        let layers: Vec<Box<dyn Layer>> = vec![
+            Box::new(Reshape::new(vec![36], vec![1, 6, 6])),
+            Box::new(
+                Conv2d::new(1, (6, 6), 2, (3, 2))
+                    .with_stride(2)
+                    .with_padding(1),
+            ),
+            Box::new(ActivationLayer::new(Activation::Relu)),
+            Box::new(Dropout::new(0.25)),
+            Box::new(Dense::new_by_rand(24, 3)),
+        ];
+        let nn = NeuralNetwork::from_layers(layers);
+        let loaded = read_model::<f64, _>(&mut Cursor::new(encode(&nn))).unwrap();
+        let conv = loaded.layer::<Conv2d>(1).unwrap();
+        assert_eq!((conv.stride(), conv.padding()), (2, 1));
+        assert_eq!(conv.weights(), nn.layer::<Conv2d>(1).unwrap().weights());
+        assert_eq!(
+            loaded.layer::<Reshape>(0).unwrap().output_shape(),
+            &[1, 6, 6]
+        );
+        assert_eq!(loaded.layer::<Dropout>(3).unwrap().rate(), 0.25);
+        let input = Matrix::from_vec(36, 1, (0..36).map(|i| i as f64 / 36.0).collect());
+        assert_eq!(
+            nn.inference(input.clone()).unwrap(),
+            loaded.inference(input).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_load_corrupt_geometry() {
+        // the u64 fields of the first layer start after magic, version, lr,
+        // layer count and kind tag
+        let patch = |layer: Box<dyn Layer>, field: usize, value: u64| {
+            let mut bytes = encode(&NeuralNetwork::from_layers(vec![layer]));
+            let at = 21 + 8 * field;
+            bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+            read_model::<f64, _>(&mut Cursor::new(bytes))
+        };
+        let conv = || -> Box<dyn Layer> { Box::new(Conv2d::new(1, (6, 6), 2, (3, 3))) };
+        assert!(patch(conv(), 7, 1).is_ok());
+        assert!(matches!(
+            patch(conv(), 7, u64::MAX),
+            Err(ModelError::InvalidParameter {
+                name: "padding",
+                ..
+            })
+        ));
+        assert!(matches!(
+            patch(conv(), 4, 7),
+            Err(ModelError::KernelTooLarge {
+                kernel: (7, 3),
+                input: (6, 6),
+                padding: 0,
+                ..
+            })
+        ));
+        assert!(matches!(
+            patch(conv(), 6, 0),
+            Err(ModelError::EmptyLayer(0))
+        ));
+        assert!(matches!(
+            patch(conv(), 0, u64::MAX),
+            Err(ModelError::InvalidParameter {
+                name: "kernel size",
+                ..
+            })
+        ));
+
+        // a reshape of [4] into [2, 2] stores rank 1, 4, rank 2, 2, 2
+        let reshape: Vec<Box<dyn Layer>> = vec![Box::new(Reshape::new(vec![4], vec![2, 2]))];
+        let mut bytes = encode(&NeuralNetwork::from_layers(reshape));
+        for at in [37, 45].iter() {
+            bytes[*at..*at + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        }
+        assert!(matches!(
+            read_model::<f64, _>(&mut Cursor::new(bytes)),
+            Err(ModelError::InvalidParameter {
+                name: "reshape size",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_load_layer_mismatch() {
+        let layers: Vec<Box<dyn Layer>> = vec![
+            Box::new(Dense::new_by_rand(3, 4)),
+            Box::new(ActivationLayer::new(Activation::Relu)),
+            Box::new(Dense::new_by_rand(5, 2)),
+        ];
+        let bytes = encode(&NeuralNetwork::from_layers(layers));
+        match read_model::<f64, _>(&mut Cursor::new(&bytes)) {
+            Err(ModelError::LayerMismatch {
+                layer,
+                output_size,
+                next_input_size,
+            }) => assert_eq!((layer, output_size, next_input_size), (1, 4, 5)),
+            other => panic!("expected layer mismatch, got {:?}", other),
+        }
+    }
+
+    // a layer type the file format does not know
+    #[derive(Debug, Clone)]
+    struct Negate;
+
+    impl Layer for Negate {
+        fn name(&self) -> &'static str {
+            "negate"
+        }
+
+        fn input_size(&self) -> Option<usize> {
+            None
+        }
+
+        fn output_size(&self, input_size: usize) -> usize {
+            input_size
+        }
+
+        fn forward(&self, input: &Matrix) -> Result<Matrix, MatrixError> {
+            Ok(input.mul_const(-1.0))
+        }
+
+        fn forward_train(&mut self, input: &Matrix) -> Result<Matrix, MatrixError> {
+            self.forward(input)
+        }
+
+        fn backward(&mut self, grad: &Matrix) -> Result<Matrix, MatrixError> {
+            Ok(grad.mul_const(-1.0))
+        }
+
+        fn show(&self) {
+            println!("[Layer] negate");
+        }
+
+        fn box_clone(&self) -> Box<dyn Layer> {
+            Box::new(self.clone())
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn test_save_custom_layer() {
+        let layers: Vec<Box<dyn Layer>> =
+            vec![Box::new(Dense::new_by_rand(3, 2)), Box::new(Negate)];
+        let mut nn = NeuralNetwork::from_layers(layers);
+        // custom layers train like the built-in ones
+        let inputs = Matrix::new(vec![vec![0.9, 0.1, 0.8]]).transpose();
+        let label = Matrix::new(vec![vec![0.5, -0.5]]).transpose();
+        let (first, _) = nn.train(&inputs, &label).unwrap();
+        let (second, _) = nn.train(&inputs, &label).unwrap();
+        assert!(second < first);
+        assert_eq!(
+            nn.forward(&inputs, Mode::Train).unwrap(),
+            nn.inference(inputs.clone()).unwrap()
+        );
+
+        let mut bytes = Vec::new();
+        match write_model(&nn, &mut bytes) {
+            Err(ModelError::UnsupportedLayer { layer, name }) => {
+                assert_eq!((layer, name), (1, "negate"))
+            }
+            other => panic!("expected unsupported layer, got {:?}", other),
+        }
+    }
+}
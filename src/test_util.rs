@@ -0,0 +1,58 @@
+use crate::matrix::Matrix;
+use std::path::PathBuf;
+
+// Helpers shared by the unit tests of the other modules.
+
+// central differences of `f` with respect to every element of `x`
+pub(crate) fn numeric_gradient<F: FnMut(&Matrix) -> f64>(x: &Matrix, eps: f64, mut f: F) -> Matrix {
+    let data = (0..x.rows * x.cols)
+        .map(|i| {
+            let (row, col) = (i / x.cols, i % x.cols);
+            let mut plus = x.clone();
+            plus[(row, col)] += eps;
+            let mut minus = x.clone();
+            minus[(row, col)] -= eps;
+            (f(&plus) - f(&minus)) / (2.0 * eps)
+        })
+        .collect();
+    Matrix::from_vec(x.rows, x.cols, data)
+}
+
+// panics at the first element where the gradients differ by `tolerance` or more
+pub(crate) fn assert_gradient(what: &str, numeric: &Matrix, analytic: &Matrix, tolerance: f64) {
+    assert_eq!(numeric.shape(), analytic.shape(), "{} gradient shape", what);
+    for row in 0..numeric.rows {
+        for col in 0..numeric.cols {
+            assert!(
+                (numeric[(row, col)] - analytic[(row, col)]).abs() < tolerance,
+                "{} gradient mismatch at ({}, {}): numeric {} analytic {}",
+                what,
+                row,
+                col,
+                numeric[(row, col)],
+                analytic[(row, col)]
+            );
+        }
+    }
+}
+
+// weights of the layer test loss sum(output * weights), varied so that no two
+// neighbouring outputs count the same
+pub(crate) fn loss_weights(rows: usize, cols: usize) -> Matrix {
+    let data = (0..rows * cols)
+        .map(|i| (i * 7 % 11) as f64 / 11.0 - 0.4)
+        .collect();
+    Matrix::from_vec(rows, cols, data)
+}
+
+// path in the temp directory, unique to this test process
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("snn_{}_{}", std::process::id(), name))
+}
+
+// temp_path holding `contents`
+pub(crate) fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+    let path = temp_path(name);
+    std::fs::write(&path, contents).unwrap();
+    path
+}
@@ -0,0 +1,789 @@
+use crate::dataset::Dataset;
+use crate::init::seeded_rng;
+use crate::matrix::{Matrix, MatrixError, MatrixOps};
+use crate::metrics::Report;
+use crate::nn::{NeuralNetwork, EVAL_BATCH_SIZE};
+use crate::scalar::Scalar;
+use crate::schedule::Schedule;
+use crate::serialization::ModelError;
+use rand::rngs::StdRng;
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum TrainError {
+    Matrix(MatrixError),
+    Model(ModelError),
+}
+
+impl fmt::Display for TrainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrainError::Matrix(err) => write!(f, "{}", err),
+            TrainError::Model(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for TrainError {}
+
+impl From<MatrixError> for TrainError {
+    fn from(err: MatrixError) -> TrainError {
+        TrainError::Matrix(err)
+    }
+}
+
+impl From<ModelError> for TrainError {
+    fn from(err: ModelError) -> TrainError {
+        TrainError::Model(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EpochStats {
+    pub epoch: usize,
+    // learning rate used during the epoch
+    pub lr: f64,
+    // mean loss of the training batches, measured while the weights change
+    pub train_loss: f64,
+    // only present when the trainer got a validation set
+    pub val_loss: Option<f64>,
+    pub val_accuracy: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct History {
+    pub(crate) epochs: Vec<EpochStats>,
+    // set when a callback ended training before the last epoch
+    pub(crate) stopped_epoch: Option<usize>,
+}
+
+impl History {
+    pub fn epochs(&self) -> &[EpochStats] {
+        &self.epochs
+    }
+
+    pub fn len(&self) -> usize {
+        self.epochs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.epochs.is_empty()
+    }
+
+    pub fn last(&self) -> Option<&EpochStats> {
+        self.epochs.last()
+    }
+
+    pub fn stopped_epoch(&self) -> Option<usize> {
+        self.stopped_epoch
+    }
+
+    pub fn train_loss(&self) -> Vec<f64> {
+        self.epochs.iter().map(|e| e.train_loss).collect()
+    }
+
+    pub fn val_loss(&self) -> Vec<f64> {
+        self.epochs.iter().filter_map(|e| e.val_loss).collect()
+    }
+
+    pub fn val_accuracy(&self) -> Vec<f64> {
+        self.epochs.iter().filter_map(|e| e.val_accuracy).collect()
+    }
+
+    pub fn lr(&self) -> Vec<f64> {
+        self.epochs.iter().map(|e| e.lr).collect()
+    }
+
+    // epoch with the best value of the monitored quantity
+    pub fn best(&self, monitor: Monitor) -> Option<&EpochStats> {
+        let mut best: Option<&EpochStats> = None;
+        for stats in self.epochs.iter() {
+            if let Some(value) = monitor.value(stats) {
+                let better = match best.and_then(|b| monitor.value(b)) {
+                    Some(current) => monitor.improved(value, current, 0.0),
+                    None => true,
+                };
+                if better {
+                    best = Some(stats);
+                }
+            }
+        }
+        best
+    }
+}
+
+// Quantity watched by early stopping and checkpointing. Losses improve when
+// they decrease, accuracy when it increases.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Monitor {
+    TrainLoss,
+    ValLoss,
+    ValAccuracy,
+}
+
+impl Monitor {
+    // None for validation quantities when training without a validation set
+    pub fn value(&self, stats: &EpochStats) -> Option<f64> {
+        match self {
+            Monitor::TrainLoss => Some(stats.train_loss),
+            Monitor::ValLoss => stats.val_loss,
+            Monitor::ValAccuracy => stats.val_accuracy,
+        }
+    }
+
+    // new beats best by more than min_delta
+    pub fn improved(&self, new: f64, best: f64, min_delta: f64) -> bool {
+        match self {
+            Monitor::TrainLoss | Monitor::ValLoss => new < best - min_delta,
+            Monitor::ValAccuracy => new > best + min_delta,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Continue,
+    Stop,
+}
+
+// Hooks called by Trainer::fit, all of them default to doing nothing.
+pub trait Callback<T: Scalar = f64> {
+    fn on_train_begin(&mut self, _nn: &mut NeuralNetwork<T>) -> Result<(), TrainError> {
+        Ok(())
+    }
+
+    fn on_epoch_begin(
+        &mut self,
+        _epoch: usize,
+        _nn: &mut NeuralNetwork<T>,
+    ) -> Result<(), TrainError> {
+        Ok(())
+    }
+
+    // history already contains stats as its last entry
+    fn on_epoch_end(
+        &mut self,
+        _nn: &mut NeuralNetwork<T>,
+        _stats: &EpochStats,
+        _history: &History,
+    ) -> Result<Action, TrainError> {
+        Ok(Action::Continue)
+    }
+
+    fn on_train_end(
+        &mut self,
+        _nn: &mut NeuralNetwork<T>,
+        _history: &History,
+    ) -> Result<(), TrainError> {
+        Ok(())
+    }
+}
+
+// Stops when the monitored value has not improved by more than min_delta
+// for `patience` epochs in a row, optionally restoring the best weights.
+pub struct EarlyStopping<T = f64> {
+    monitor: Monitor,
+    patience: usize,
+    min_delta: f64,
+    restore_best: bool,
+    best_value: Option<f64>,
+    best: Option<NeuralNetwork<T>>,
+    wait: usize,
+}
+
+impl<T: Scalar> EarlyStopping<T> {
+    pub fn new(monitor: Monitor, patience: usize) -> EarlyStopping<T> {
+        EarlyStopping {
+            monitor,
+            patience,
+            min_delta: 0.0,
+            restore_best: false,
+            best_value: None,
+            best: None,
+            wait: 0,
+        }
+    }
+
+    pub fn with_min_delta(mut self, min_delta: f64) -> EarlyStopping<T> {
+        self.min_delta = min_delta;
+        self
+    }
+
+    pub fn with_restore_best(mut self, restore_best: bool) -> EarlyStopping<T> {
+        self.restore_best = restore_best;
+        self
+    }
+}
+
+impl<T: Scalar> Callback<T> for EarlyStopping<T> {
+    fn on_train_begin(&mut self, _nn: &mut NeuralNetwork<T>) -> Result<(), TrainError> {
+        self.best_value = None;
+        self.best = None;
+        self.wait = 0;
+        Ok(())
+    }
+
+    fn on_epoch_end(
+        &mut self,
+        nn: &mut NeuralNetwork<T>,
+        stats: &EpochStats,
+        _history: &History,
+    ) -> Result<Action, TrainError> {
+        let value = match self.monitor.value(stats) {
+            Some(value) => value,
+            None => return Ok(Action::Continue),
+        };
+        let improved = match self.best_value {
+            Some(best) => self.monitor.improved(value, best, self.min_delta),
+            None => true,
+        };
+        if improved {
+            self.best_value = Some(value);
+            self.wait = 0;
+            if self.restore_best {
+                self.best = Some(nn.clone());
+            }
+            return Ok(Action::Continue);
+        }
+        self.wait += 1;
+        if self.wait >= self.patience {
+            Ok(Action::Stop)
+        } else {
+            Ok(Action::Continue)
+        }
+    }
+
+    fn on_train_end(
+        &mut self,
+        nn: &mut NeuralNetwork<T>,
+        _history: &History,
+    ) -> Result<(), TrainError> {
+        if let Some(best) = self.best.take() {
+            *nn = best;
+        }
+        Ok(())
+    }
+}
+
+// Saves a checkpoint (model and optimizer state) whenever the monitored
+// value reaches a new best.
+pub struct ModelCheckpoint {
+    path: PathBuf,
+    monitor: Monitor,
+    best_value: Option<f64>,
+}
+
+impl ModelCheckpoint {
+    pub fn new<P: Into<PathBuf>>(path: P, monitor: Monitor) -> ModelCheckpoint {
+        ModelCheckpoint {
+            path: path.into(),
+            monitor,
+            best_value: None,
+        }
+    }
+}
+
+impl<T: Scalar> Callback<T> for ModelCheckpoint {
+    fn on_train_begin(&mut self, _nn: &mut NeuralNetwork<T>) -> Result<(), TrainError> {
+        self.best_value = None;
+        Ok(())
+    }
+
+    fn on_epoch_end(
+        &mut self,
+        nn: &mut NeuralNetwork<T>,
+        stats: &EpochStats,
+        _history: &History,
+    ) -> Result<Action, TrainError> {
+        if let Some(value) = self.monitor.value(stats) {
+            let improved = match self.best_value {
+                Some(best) => self.monitor.improved(value, best, 0.0),
+                None => true,
+            };
+            if improved {
+                self.best_value = Some(value);
+                nn.save_checkpoint(&self.path)?;
+            }
+        }
+        Ok(Action::Continue)
+    }
+}
+
+// Sets the learning rate from a schedule at the start of every epoch and
+// reports the monitored value (validation loss by default) back to it after
+// the epoch, which drives ReduceOnPlateau.
+pub struct LearningRateScheduler {
+    schedule: Box<dyn Schedule>,
+    monitor: Monitor,
+}
+
+impl LearningRateScheduler {
+    pub fn new<S: Schedule + 'static>(schedule: S) -> LearningRateScheduler {
+        LearningRateScheduler {
+            schedule: Box::new(schedule),
+            monitor: Monitor::ValLoss,
+        }
+    }
+
+    pub fn with_monitor(mut self, monitor: Monitor) -> LearningRateScheduler {
+        self.monitor = monitor;
+        self
+    }
+
+    // rate the schedule gives for an epoch, without changing any state
+    pub fn lr(&self, epoch: usize) -> f64 {
+        self.schedule.lr(epoch)
+    }
+}
+
+impl<T: Scalar> Callback<T> for LearningRateScheduler {
+    fn on_epoch_begin(
+        &mut self,
+        epoch: usize,
+        nn: &mut NeuralNetwork<T>,
+    ) -> Result<(), TrainError> {
+        nn.set_lr(self.schedule.lr(epoch));
+        Ok(())
+    }
+
+    fn on_epoch_end(
+        &mut self,
+        _nn: &mut NeuralNetwork<T>,
+        stats: &EpochStats,
+        _history: &History,
+    ) -> Result<Action, TrainError> {
+        if let Some(value) = self.monitor.value(stats) {
+            self.schedule.observe(value);
+        }
+        Ok(Action::Continue)
+    }
+}
+
+// Prints the stats of every `every`-th epoch.
+pub struct ProgressLogger {
+    every: usize,
+    epochs: usize,
+}
+
+impl ProgressLogger {
+    pub fn new(every: usize) -> ProgressLogger {
+        assert!(every > 0);
+        ProgressLogger { every, epochs: 0 }
+    }
+}
+
+impl<T: Scalar> Callback<T> for ProgressLogger {
+    fn on_train_begin(&mut self, _nn: &mut NeuralNetwork<T>) -> Result<(), TrainError> {
+        self.epochs = 0;
+        Ok(())
+    }
+
+    fn on_epoch_end(
+        &mut self,
+        _nn: &mut NeuralNetwork<T>,
+        stats: &EpochStats,
+        _history: &History,
+    ) -> Result<Action, TrainError> {
+        self.epochs += 1;
+        if stats.epoch.is_multiple_of(self.every) {
+            let mut line = format!(
+                "[Epoch {}] lr {:.6} loss {:.6}",
+                stats.epoch, stats.lr, stats.train_loss
+            );
+            if let Some(val_loss) = stats.val_loss {
+                line.push_str(&format!(" val_loss {:.6}", val_loss));
+            }
+            if let Some(val_accuracy) = stats.val_accuracy {
+                line.push_str(&format!(" val_accuracy {:.4}", val_accuracy));
+            }
+            println!("{}", line);
+        }
+        Ok(Action::Continue)
+    }
+
+    fn on_train_end(
+        &mut self,
+        _nn: &mut NeuralNetwork<T>,
+        history: &History,
+    ) -> Result<(), TrainError> {
+        match history.stopped_epoch() {
+            Some(epoch) => println!("[Trainer] stopped early after epoch {}", epoch),
+            None => println!("[Trainer] finished {} epochs", self.epochs),
+        }
+        Ok(())
+    }
+}
+
+// Runs a network over a dataset for a number of epochs. Without a shuffle
+// seed the samples are visited in dataset order every epoch.
+pub struct Trainer<T = f64> {
+    epochs: usize,
+    batch_size: usize,
+    shuffle: Option<u64>,
+    callbacks: Vec<Box<dyn Callback<T>>>,
+}
+
+impl<T: Scalar> Trainer<T> {
+    pub fn new(epochs: usize, batch_size: usize) -> Trainer<T> {
+        assert!(batch_size > 0);
+        Trainer {
+            epochs,
+            batch_size,
+            shuffle: None,
+            callbacks: Vec::new(),
+        }
+    }
+
+    // reshuffle the training set before every epoch, the same seed gives the
+    // same order of batches
+    pub fn with_shuffle(mut self, seed: u64) -> Trainer<T> {
+        self.shuffle = Some(seed);
+        self
+    }
+
+    pub fn with_callback<C: Callback<T> + 'static>(mut self, callback: C) -> Trainer<T> {
+        self.callbacks.push(Box::new(callback));
+        self
+    }
+
+    pub fn fit(
+        &mut self,
+        nn: &mut NeuralNetwork<T>,
+        train: &Dataset<T>,
+        validation: Option<&Dataset<T>>,
+    ) -> Result<History, TrainError> {
+        if train.is_empty() {
+            return Err(TrainError::Matrix(MatrixError::Empty));
+        }
+        let mut history = History::default();
+        let mut train = train.clone();
+        let mut rng: Option<StdRng> = self.shuffle.map(seeded_rng);
+        for callback in self.callbacks.iter_mut() {
+            callback.on_train_begin(nn)?;
+        }
+        for epoch in 0..self.epochs {
+            for callback in self.callbacks.iter_mut() {
+                callback.on_epoch_begin(epoch, nn)?;
+            }
+            if let Some(rng) = rng.as_mut() {
+                train.shuffle(rng);
+            }
+            let lr = nn.lr();
+            let mut total = 0.0;
+            for (inputs, labels) in train.batches(self.batch_size) {
+                let (loss, _) = nn.train_batch(&inputs, &labels)?;
+                total += loss * inputs.cols as f64;
+            }
+            let (val_loss, val_accuracy) = match validation {
+                Some(dataset) if !dataset.is_empty() => {
+                    let (loss, accuracy) = validate(nn, dataset)?;
+                    (Some(loss), Some(accuracy))
+                }
+                _ => (None, None),
+            };
+            let stats = EpochStats {
+                epoch,
+                lr,
+                train_loss: total / train.len() as f64,
+                val_loss,
+                val_accuracy,
+            };
+            history.epochs.push(stats);
+            let mut stop = false;
+            for callback in self.callbacks.iter_mut() {
+                if callback.on_epoch_end(nn, &stats, &history)? == Action::Stop {
+                    stop = true;
+                }
+            }
+            if stop {
+                if epoch + 1 < self.epochs {
+                    history.stopped_epoch = Some(epoch);
+                }
+                break;
+            }
+        }
+        for callback in self.callbacks.iter_mut() {
+            callback.on_train_end(nn, &history)?;
+        }
+        Ok(history)
+    }
+}
+
+// mean loss and accuracy over a whole dataset
+fn validate<T: Scalar>(
+    nn: &NeuralNetwork<T>,
+    dataset: &Dataset<T>,
+) -> Result<(f64, f64), MatrixError> {
+    let mut total = 0.0;
+    let mut outputs = Vec::new();
+    let mut targets = Vec::new();
+    for (inputs, labels) in dataset.batches(EVAL_BATCH_SIZE) {
+        let (loss, output) = nn.loss_and_output(&inputs, &labels)?;
+        total += loss * inputs.cols as f64;
+        outputs.push(output);
+        targets.push(labels);
+    }
+    let report = Report::new(
+        &Matrix::try_hstack(&outputs)?,
+        &Matrix::try_hstack(&targets)?,
+    )?;
+    Ok((total / dataset.len() as f64, report.accuracy()))
+}
+
+#[cfg(test)]
+mod trainer_tests {
+    use super::{
+        Action, Callback, EarlyStopping, EpochStats, History, LearningRateScheduler,
+        ModelCheckpoint, Monitor, ProgressLogger, TrainError, Trainer,
+    };
+    use crate::dataset::{read_csv_by_path, Dataset};
+    use crate::loss::Loss;
+    use crate::matrix::MatrixError;
+    use crate::nn::NeuralNetwork;
+    use crate::schedule::{ReduceOnPlateau, StepDecay};
+    use crate::test_util::temp_path;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn mnist() -> (Dataset, Dataset) {
+        let train = read_csv_by_path("data/mnist_train_100.csv").unwrap().into();
+        let test = read_csv_by_path("data/mnist_test_10.csv").unwrap().into();
+        (train, test)
+    }
+
+    fn network() -> NeuralNetwork {
+        NeuralNetwork::new_seeded(vec![784, 16, 10], 3)
+    }
+
+    #[test]
+    fn test_fit_records_history() {
+        let (train, test) = mnist();
+        let mut nn = network();
+        let mut trainer = Trainer::new(5, 10).with_callback(ProgressLogger::new(2));
+        let history = trainer.fit(&mut nn, &train, Some(&test)).unwrap();
+        assert_eq!(history.len(), 5);
+        assert_eq!(history.val_loss().len(), 5);
+        assert_eq!(history.val_accuracy().len(), 5);
+        assert_eq!(history.stopped_epoch(), None);
+        let loss = history.train_loss();
+        println!("{:?}", loss);
+        assert!(loss[4] < loss[0]);
+        // the last validation entry matches an evaluation of the final weights
+        let report = nn.evaluate(test.inputs(), test.labels()).unwrap();
+        assert_eq!(
+            history.last().unwrap().val_accuracy,
+            Some(report.accuracy())
+        );
+
+        let history = Trainer::new(1, 10).fit(&mut nn, &train, None).unwrap();
+        assert_eq!(history.last().unwrap().val_loss, None);
+        assert!(history.val_accuracy().is_empty());
+
+        let empty = Dataset::new(Vec::new(), Vec::new()).unwrap();
+        assert!(matches!(
+            Trainer::new(1, 10).fit(&mut nn, &empty, None),
+            Err(TrainError::Matrix(MatrixError::Empty))
+        ));
+    }
+
+    #[test]
+    fn test_fit_matches_network_fit() {
+        let (train, _) = mnist();
+        let mut a = network();
+        let mut b = network();
+        let history = Trainer::new(3, 4).fit(&mut a, &train, None).unwrap();
+        let losses = b.fit(train.inputs(), train.labels(), 4, 3).unwrap();
+        assert_eq!(history.train_loss(), losses);
+    }
+
+    #[test]
+    fn test_shuffle_is_reproducible() {
+        let (train, test) = mnist();
+        let run = |seed| {
+            let mut nn = network();
+            Trainer::new(3, 10)
+                .with_shuffle(seed)
+                .fit(&mut nn, &train, Some(&test))
+                .unwrap()
+        };
+        let a = run(7);
+        assert_eq!(a, run(7));
+        assert_ne!(a.train_loss(), run(8).train_loss());
+    }
+
+    #[test]
+    fn test_progress_logger_restarts() {
+        let (train, _) = mnist();
+        let mut nn = network();
+        let mut logger = ProgressLogger::new(1);
+        for _run in 0..2 {
+            Callback::on_train_begin(&mut logger, &mut nn).unwrap();
+            let history = Trainer::new(2, 50).fit(&mut nn, &train, None).unwrap();
+            for stats in history.epochs() {
+                Callback::on_epoch_end(&mut logger, &mut nn, stats, &history).unwrap();
+            }
+            assert_eq!(logger.epochs, 2);
+        }
+    }
+
+    #[test]
+    fn test_early_stopping() {
+        let (train, test) = mnist();
+        // nothing counts as an improvement after the first epoch
+        let mut nn = network();
+        let mut trainer = Trainer::new(10, 10).with_callback(
+            EarlyStopping::new(Monitor::TrainLoss, 2)
+                .with_min_delta(1e9)
+                .with_restore_best(true),
+        );
+        let history = trainer.fit(&mut nn, &train, Some(&test)).unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.stopped_epoch(), Some(2));
+
+        // the restored weights are the ones after the first epoch
+        let mut first = network();
+        Trainer::new(1, 10).fit(&mut first, &train, None).unwrap();
+        for (a, b) in nn.layers().iter().zip(first.layers()) {
+            assert_eq!(a.parameters(), b.parameters());
+        }
+
+        // without a validation set a validation monitor never stops training
+        let mut nn = network();
+        let history = Trainer::new(3, 10)
+            .with_callback(EarlyStopping::new(Monitor::ValLoss, 0).with_min_delta(1e9))
+            .fit(&mut nn, &train, None)
+            .unwrap();
+        assert_eq!(history.len(), 3);
+    }
+
+    #[test]
+    fn test_model_checkpoint() {
+        let (train, test) = mnist();
+        let path = temp_path("best.snnm");
+        let mut nn = network()
+            .with_loss(Loss::Mse)
+            .with_optimizer(crate::optimizer::Momentum::new(0.5, 0.9));
+        let mut trainer =
+            Trainer::new(6, 5).with_callback(ModelCheckpoint::new(&path, Monitor::ValLoss));
+        let history = trainer.fit(&mut nn, &train, Some(&test)).unwrap();
+        let best: NeuralNetwork = NeuralNetwork::load_checkpoint(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let best_stats = history.best(Monitor::ValLoss).unwrap();
+        println!(
+            "val loss {:?}, best epoch {}",
+            history.val_loss(),
+            best_stats.epoch
+        );
+        let (inputs, labels) = test.batches(test.len()).next().unwrap();
+        let loss = best.compute_loss(&inputs, &labels).unwrap();
+        assert!((loss - best_stats.val_loss.unwrap()).abs() < 1e-12);
+        assert_eq!(best.optimizer().name(), "momentum");
+    }
+
+    #[test]
+    fn test_learning_rate_scheduler() {
+        let (train, test) = mnist();
+        let mut nn = network();
+        let scheduler = LearningRateScheduler::new(StepDecay::new(0.4, 1, 0.5));
+        assert_eq!(scheduler.lr(2), 0.1);
+        let mut trainer = Trainer::new(4, 50).with_callback(scheduler);
+        let history = trainer.fit(&mut nn, &train, None).unwrap();
+        assert_eq!(history.lr(), vec![0.4, 0.2, 0.1, 0.05]);
+        assert_eq!(nn.lr(), 0.05);
+
+        let mut nn = network();
+        let mut trainer =
+            Trainer::new(2, 50).with_callback(LearningRateScheduler::new(|epoch: usize| {
+                0.1 * (epoch + 1) as f64
+            }));
+        let history = trainer.fit(&mut nn, &train, None).unwrap();
+        assert_eq!(history.lr(), vec![0.1, 0.2]);
+
+        // nothing counts as an improvement, so the rate halves every other epoch
+        let mut nn = network();
+        let plateau = ReduceOnPlateau::new(0.4, 0.5, 1).with_threshold(1e9);
+        let mut trainer = Trainer::new(6, 50).with_callback(LearningRateScheduler::new(plateau));
+        let history = trainer.fit(&mut nn, &train, Some(&test)).unwrap();
+        assert_eq!(history.lr(), vec![0.4, 0.4, 0.4, 0.2, 0.2, 0.1]);
+
+        // without validation data the monitor can fall back to the training loss
+        let mut nn = network();
+        let plateau = ReduceOnPlateau::new(0.4, 0.5, 0).with_threshold(1e9);
+        let mut trainer = Trainer::new(3, 50)
+            .with_callback(LearningRateScheduler::new(plateau).with_monitor(Monitor::TrainLoss));
+        let history = trainer.fit(&mut nn, &train, None).unwrap();
+        assert_eq!(history.lr(), vec![0.4, 0.4, 0.2]);
+    }
+
+    // records the order of the hooks and stops after `stop_after` epochs
+    struct Recorder {
+        calls: Rc<RefCell<Vec<String>>>,
+        stop_after: usize,
+    }
+
+    impl Callback for Recorder {
+        fn on_train_begin(&mut self, _nn: &mut NeuralNetwork) -> Result<(), TrainError> {
+            self.calls.borrow_mut().push("begin".to_string());
+            Ok(())
+        }
+
+        fn on_epoch_begin(
+            &mut self,
+            epoch: usize,
+            _nn: &mut NeuralNetwork,
+        ) -> Result<(), TrainError> {
+            self.calls.borrow_mut().push(format!("epoch {}", epoch));
+            Ok(())
+        }
+
+        fn on_epoch_end(
+            &mut self,
+            _nn: &mut NeuralNetwork,
+            stats: &EpochStats,
+            history: &History,
+        ) -> Result<Action, TrainError> {
+            assert_eq!(history.last(), Some(stats));
+            self.calls.borrow_mut().push(format!("end {}", stats.epoch));
+            if stats.epoch + 1 == self.stop_after {
+                Ok(Action::Stop)
+            } else {
+                Ok(Action::Continue)
+            }
+        }
+
+        fn on_train_end(
+            &mut self,
+            _nn: &mut NeuralNetwork,
+            history: &History,
+        ) -> Result<(), TrainError> {
+            self.calls
+                .borrow_mut()
+                .push(format!("done {}", history.len()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_custom_callback() {
+        let (train, _) = mnist();
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mut nn = network();
+        let history = Trainer::new(5, 50)
+            .with_callback(Recorder {
+                calls: calls.clone(),
+                stop_after: 2,
+            })
+            .fit(&mut nn, &train, None)
+            .unwrap();
+        assert_eq!(history.stopped_epoch(), Some(1));
+        assert_eq!(
+            *calls.borrow(),
+            vec!["begin", "epoch 0", "end 0", "epoch 1", "end 1", "done 2"]
+        );
+    }
+}